@@ -0,0 +1,135 @@
+//! Ingestion throughput of the candle aggregator: `cargo bench --bench aggregator`
+
+use std::hint::black_box;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use tick_aggregator::pkg::aggregator::ticker_aggregator::KlineAggregator;
+use tick_aggregator::pkg::exchanges::exchange_entities::{TradeData, TradeSide};
+
+const SYMBOL_COUNTS: [usize; 3] = [100, 1_000, 5_000];
+const TICKS_PER_SYMBOL: usize = 20;
+const THREADS: usize = 4;
+
+fn now_millis() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64
+}
+
+fn aggregator() -> KlineAggregator {
+    let intervals = ["1m", "5m", "1h"].map(String::from);
+    KlineAggregator::new("binance", &intervals, 600_000, false, false)
+}
+
+// Round-robin trades over `symbols`, all within the last minute so none are late
+fn trades(symbols: usize) -> Vec<TradeData> {
+    let start = now_millis() - 60_000;
+    (0..symbols * TICKS_PER_SYMBOL)
+        .map(|i| TradeData {
+            symbol: format!("SYM{}USDT", i % symbols),
+            price: 100.0 + (i % 17) as f64,
+            qty: 0.5,
+            side: if i % 2 == 0 { TradeSide::Buy } else { TradeSide::Sell },
+            trade_id: i.to_string(),
+            timestamp: start + (i % 60_000) as i64,
+            count: 1,
+        })
+        .collect()
+}
+
+fn ingest_trades(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ingest_trades");
+    for symbols in SYMBOL_COUNTS {
+        let input = trades(symbols);
+        let agg = aggregator();
+        group.throughput(Throughput::Elements(input.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(symbols), &input, |b, input| {
+            b.iter(|| {
+                for trade in input {
+                    agg.add_trade(black_box(trade));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn ingest_trades_parallel(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ingest_trades_parallel");
+    for symbols in SYMBOL_COUNTS {
+        // Each thread feeds its own symbols, like one stream per connection
+        let mut input: Vec<Vec<TradeData>> = (0..THREADS).map(|_| Vec::new()).collect();
+        for (i, trade) in trades(symbols).into_iter().enumerate() {
+            input[(i % symbols) % THREADS].push(trade);
+        }
+        let agg = aggregator();
+        group.throughput(Throughput::Elements((symbols * TICKS_PER_SYMBOL) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(symbols), &input, |b, input| {
+            b.iter(|| {
+                thread::scope(|scope| {
+                    for feed in input {
+                        let agg = &agg;
+                        scope.spawn(move || {
+                            for trade in feed {
+                                agg.add_trade(black_box(trade));
+                            }
+                        });
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+fn ingest_tickers(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ingest_tickers");
+    for symbols in SYMBOL_COUNTS {
+        let names: Vec<String> = (0..symbols).map(|i| format!("SYM{}USDT", i)).collect();
+        let agg = aggregator();
+        let start = now_millis() - 60_000;
+        group.throughput(Throughput::Elements((symbols * TICKS_PER_SYMBOL) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(symbols), &names, |b, names| {
+            b.iter(|| {
+                for tick in 0..TICKS_PER_SYMBOL {
+                    for name in names {
+                        let time = start + tick as i64 * 1_000;
+                        agg.add_price(black_box(name), 100.0 + tick as f64, 1_000.0 + tick as f64, Some(time));
+                    }
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn extract(c: &mut Criterion) {
+    let mut group = c.benchmark_group("extract_ohlc");
+    for symbols in SYMBOL_COUNTS {
+        // One closed minute per symbol, so every flush writes `symbols` candles
+        let minute = {
+            let now = now_millis();
+            now - now % 60_000 - 60_000
+        };
+        let input: Vec<TradeData> = trades(symbols)
+            .into_iter()
+            .map(|t| TradeData { timestamp: minute + t.timestamp % 60_000, ..t })
+            .collect();
+        group.throughput(Throughput::Elements(symbols as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(symbols), &input, |b, input| {
+            b.iter_batched(
+                || {
+                    let agg = aggregator();
+                    input.iter().for_each(|t| agg.add_trade(t));
+                    agg
+                },
+                |agg| black_box(agg.extract_ohlc()),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, ingest_trades, ingest_trades_parallel, ingest_tickers, extract);
+criterion_main!(benches);
@@ -0,0 +1,121 @@
+//! ClickHouse insert throughput, RowBinary inserter against string-built `INSERT ... VALUES`:
+//! `CLICKHOUSE_BENCH_URL=http://localhost:8123 cargo bench --bench clickhouse_insert`
+//!
+//! Rows are written to `kline_data` under the exchange `bench` and deleted afterwards.
+
+use chrono::{TimeZone, Utc};
+use clickhouse::Client;
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use tick_aggregator::pkg::clickhouse_client::{ClickHouseClient, KlineRow};
+use tick_aggregator::pkg::config::ClickHouseConfig;
+use tick_aggregator::pkg::dbcontext::entities::SymbolKlineData;
+use tokio::runtime::Runtime;
+
+const BATCH_SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+const EXCHANGE: &str = "bench";
+
+fn config(url: String) -> ClickHouseConfig {
+    ClickHouseConfig {
+        enabled: true,
+        url,
+        user: std::env::var("CLICKHOUSE_BENCH_USER").unwrap_or_else(|_| "default".to_string()),
+        password: std::env::var("CLICKHOUSE_BENCH_PASSWORD").unwrap_or_default(),
+        database: std::env::var("CLICKHOUSE_BENCH_DATABASE").unwrap_or_else(|_| "default".to_string()),
+        max_rows_per_insert: 100_000,
+        max_bytes_per_insert: 64 * 1024 * 1024,
+        insert_retries: 0,
+        retry_backoff_millis: 0,
+        lz4: true,
+    }
+}
+
+// One minute candle per symbol, as many symbols as the batch needs
+fn klines(count: usize) -> Vec<SymbolKlineData> {
+    (0..count)
+        .map(|i| SymbolKlineData {
+            exchange: EXCHANGE.to_string(),
+            symbol: format!("SYM{}USDT", i),
+            interval: "1m".to_string(),
+            open: 100.0,
+            high: 101.5,
+            low: 99.25,
+            close: 100.75,
+            open_time: Utc.timestamp_opt(1_700_000_000 - 1_700_000_000 % 60, 0).unwrap(),
+            volume: 12.5,
+            volume_24h: Some(1_250_000.0),
+            trade_count: 42,
+            buy_volume: 7.5,
+            sell_volume: 5.0,
+            is_synthetic: false,
+        })
+        .collect()
+}
+
+// The insert this crate used before the inserter: every row formatted into one SQL statement
+async fn insert_values(client: &Client, klines: &[SymbolKlineData]) {
+    let values: Vec<String> = klines
+        .iter()
+        .map(|k| {
+            format!(
+                "('{}','{}','{}','{}',{},{},{},{},{},{},'{}',{},{},{},{})",
+                k.exchange.replace('\'', "''"),
+                k.symbol.replace('\'', "''"),
+                k.symbol.replace('\'', "''"),
+                k.interval.replace('\'', "''"),
+                k.open,
+                k.high,
+                k.low,
+                k.close,
+                k.volume,
+                k.volume_24h.map_or_else(|| "NULL".to_string(), |v| v.to_string()),
+                k.open_time.format("%Y-%m-%d %H:%M:%S"),
+                k.trade_count,
+                k.buy_volume,
+                k.sell_volume,
+                k.is_synthetic
+            )
+        })
+        .collect();
+    let query = format!(
+        "INSERT INTO kline_data (exchange, symbol, native_symbol, interval, open, high, low, close, volume, volume_24h, timestamp, trade_count, buy_volume, sell_volume, is_synthetic) VALUES {}",
+        values.join(",")
+    );
+    client.query(&query).execute().await.expect("VALUES insert");
+}
+
+fn clickhouse_insert(c: &mut Criterion) {
+    let Ok(url) = std::env::var("CLICKHOUSE_BENCH_URL") else {
+        eprintln!("CLICKHOUSE_BENCH_URL is not set, skipping the ClickHouse insert benchmark");
+        return;
+    };
+    let config = config(url);
+    let rt = Runtime::new().unwrap();
+    let ch = rt.block_on(ClickHouseClient::init(&config)).expect("ClickHouse connection");
+    rt.block_on(ch.create_kline_table(None)).expect("kline_data table");
+    let raw = Client::default()
+        .with_url(&config.url)
+        .with_user(&config.user)
+        .with_password(&config.password)
+        .with_database(&config.database);
+
+    let mut group = c.benchmark_group("clickhouse_insert");
+    group.sample_size(10);
+    for size in BATCH_SIZES {
+        let input = klines(size);
+        let rows: Vec<KlineRow> = input.iter().map(|k| KlineRow::from_kline(k, EXCHANGE, 1).unwrap()).collect();
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::new("values_sql", size), &input, |b, input| {
+            b.iter(|| rt.block_on(insert_values(&raw, input)))
+        });
+        group.bench_with_input(BenchmarkId::new("row_binary", size), &rows, |b, rows| {
+            b.iter(|| rt.block_on(ch.insert_klines(rows)).expect("RowBinary insert"))
+        });
+    }
+    group.finish();
+
+    rt.block_on(raw.query("DELETE FROM kline_data WHERE exchange = ?").bind(EXCHANGE).execute())
+        .expect("bench cleanup");
+}
+
+criterion_group!(benches, clickhouse_insert);
+criterion_main!(benches);
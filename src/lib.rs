@@ -0,0 +1,2 @@
+//! Collector internals, shared by the binary and the benchmarks
+pub mod pkg;
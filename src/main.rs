@@ -1,228 +1,421 @@
-use crate::pkg::aggregator::{symbol_rotator::SymbolRotator, ticker_aggregator::KlineAggregator};
-use crate::pkg::clickhouse_client::ClickHouseClient; // top-level ClickHouse
-use crate::pkg::config::SETTINGS;
-use crate::pkg::dbcontext::kline::save_klines;
-use crate::pkg::exchanges::exchange_client::core_futures_all_tickers;
-use crate::pkg::exchanges::exchange_entities::TickerInfo;
-use crate::pkg::postgre_db;
-
+use tick_aggregator::pkg::backfill::{BackfillRequest, run_backfill};
+use tick_aggregator::pkg::cli::{
+    BackfillArgs, Cli, Command, ExportArgs, GapsArgs, InstrumentsArgs, MigrateAction, MigrateArgs, RefreshAggregatesArgs,
+};
+use tick_aggregator::pkg::collector::run_collector;
+use tick_aggregator::pkg::config::{ExchangeSettings, SETTINGS, default_intervals};
+use tick_aggregator::pkg::dbcontext::migration::{MigrationState, migration_status, migrations, run_migrations};
+use tick_aggregator::pkg::dbcontext::timescale::{apply_timescale_settings, refresh_aggregates};
+use tick_aggregator::pkg::exchanges::exchange_client::create_exchange_api;
+use tick_aggregator::pkg::export::{ExportRequest, run_export};
+use tick_aggregator::pkg::exchanges::instrument::Instrument;
+use tick_aggregator::pkg::gap_scanner::{GAP_SCAN_SETTLE_MINUTES, GapScanRequest, scan_and_repair};
+use tick_aggregator::pkg::postgre_db::DB;
+use tick_aggregator::pkg::shutdown::{
+    EXIT_COLLECTOR_FAILED, EXIT_INTERRUPTED, EXIT_OK, EXIT_SHUTDOWN_TIMEOUT, EXIT_STARTUP_FAILED, wait_for_signal,
+};
+use tick_aggregator::pkg::storage::StorageBackend;
+
+use anyhow::anyhow;
+use chrono::Utc;
+use clap::Parser;
 use dotenv::dotenv;
 use env_logger::Env;
 use log::{error, info, warn};
-use rand::Rng;
-use std::{collections::HashSet, sync::Arc, time::Duration};
-use tokio::signal;
-use tokio::time::{Instant, interval_at};
-
-mod pkg;
-
-enum StorageBackend<'a> {
-    Postgres(&'a crate::pkg::postgre_db::DB),
-    ClickHouse(ClickHouseClient),
-}
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
 
+    let cli = Cli::parse();
+
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
     info!("📈 App started");
 
-    let settings_ref = Arc::clone(&SETTINGS);
+    let command = cli.command.unwrap_or(Command::Run);
+
+    // Storage init applies migrations itself, so the schema commands connect on their own
+    match &command {
+        Command::Migrate(args) => {
+            if let Err(e) = migrate_command(args).await {
+                error!("❌ Migration failed: {:?}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Command::RefreshAggregates(args) => {
+            if let Err(e) = refresh_aggregates_command(args).await {
+                error!("❌ Refreshing aggregates failed: {:?}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        _ => {}
+    }
 
-    let mut db: Option<postgre_db::DB> = None;
-    let storage: StorageBackend = if settings_ref.clickhouse.enabled {
-        info!("⚡ ClickHouse enabled, initializing...");
+    let storage = match StorageBackend::init(&SETTINGS).await {
+        Ok(storage) => storage,
+        Err(e) => {
+            error!("❌ Failed to initialize storage: {:?}", e);
+            std::process::exit(EXIT_STARTUP_FAILED);
+        }
+    };
 
-        let ch_client = match ClickHouseClient::init(&settings_ref.clickhouse).await {
-            Ok(client) => client,
-            Err(e) => {
-                error!("Failed to connect to ClickHouse: {:?}", e);
-                return;
+    match command {
+        Command::Run => {
+            let code = run_collectors(Arc::new(storage)).await;
+            if code != EXIT_OK {
+                std::process::exit(code);
+            }
+        }
+        Command::Backfill(args) => {
+            if let Err(e) = backfill_command(&storage, args).await {
+                error!("❌ Backfill failed: {:?}", e);
+                std::process::exit(1);
             }
-        };
+            info!("✅ Backfill complete");
+        }
+        Command::Gaps(args) => {
+            if let Err(e) = gaps_command(&storage, args).await {
+                error!("❌ Gap scan failed: {:?}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Export(args) => {
+            if let Err(e) = export_command(&storage, args).await {
+                error!("❌ Export failed: {:?}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Instruments(args) => {
+            if let Err(e) = instruments_command(args).await {
+                error!("❌ Listing instruments failed: {:?}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Migrate(_) | Command::RefreshAggregates(_) => {} // handled before storage init
+    }
+}
 
-        // Optionally create table
-        if let Err(e) = ClickHouseClient::create_kline_table(&ch_client).await {
-            error!("❌ Failed to create Kline table: {:?}", e);
-            return;
+/// Start one collector task per configured exchange section. On SIGINT or SIGTERM every
+/// collector stops intake and flushes; returns the process exit code.
+async fn run_collectors(storage: Arc<StorageBackend>) -> i32 {
+    let sections = SETTINGS.exchange_sections();
+    if sections.is_empty() {
+        error!("❌ No exchanges configured, add an `exchanges` list to appsettings.yaml");
+        return EXIT_COLLECTOR_FAILED;
+    }
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let mut tasks = Vec::with_capacity(sections.len());
+    for section in sections {
+        info!("🚀 Starting collector for {} (instance {})", section.exchange, section.instance);
+        let name = section.exchange.clone();
+        let task = tokio::spawn(run_collector(section, Arc::clone(&storage), shutdown_rx.clone()));
+        tasks.push((name, task));
+    }
+    let replayer = SETTINGS
+        .spool
+        .enabled
+        .then(|| tokio::spawn(replay_spools(Arc::clone(&storage), shutdown_rx.clone())));
+
+    match wait_for_signal().await {
+        Ok(name) => info!("🛑 {} received, shutting down", name),
+        Err(e) => error!("❌ Failed to listen for shutdown signals, shutting down: {:?}", e),
+    }
+    let _ = shutdown_tx.send(true);
+
+    // A second signal means don't wait for the flush
+    tokio::spawn(async {
+        if wait_for_signal().await.is_ok() {
+            warn!("⚠️ Second signal received, exiting without waiting for pending writes");
+            std::process::exit(EXIT_INTERRUPTED);
         }
-        info!("✅ ClickHouse ready");
+    });
+
+    let timeout = Duration::from_secs(SETTINGS.shutdown.timeout_seconds);
+    let finished = tokio::time::timeout(timeout, async {
+        let mut code = EXIT_OK;
+        for (name, task) in tasks {
+            match task.await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    error!("❌ Collector for {} failed: {:?}", name, e);
+                    code = EXIT_COLLECTOR_FAILED;
+                }
+                Err(e) => {
+                    error!("❌ Collector for {} exited abnormally: {:?}", name, e);
+                    code = EXIT_COLLECTOR_FAILED;
+                }
+            }
+        }
+        code
+    })
+    .await;
+
+    let code = match finished {
+        Ok(code) => code,
+        Err(_) => {
+            error!("❌ Collectors didn't finish within {}s, exiting with writes pending", timeout.as_secs());
+            return EXIT_SHUTDOWN_TIMEOUT;
+        }
+    };
+    if let Some(replayer) = replayer {
+        let _ = replayer.await;
+    }
+    for (sink, stats) in storage.sink().spool_stats().await {
+        if stats.pending_batches > 0 {
+            warn!("📦 {} batches for {} stay in the spool, they are written on the next start", stats.pending_batches, sink);
+        }
+    }
 
-        StorageBackend::ClickHouse(ch_client)
-    } else {
-        info!("⚡ ClickHouse disabled, initializing Postgres...");
-
-        // Assign to outer variable, don't use `let` here
-        db = match postgre_db::DB::init(&settings_ref.database).await {
-            Ok(db_instance) => Some(db_instance),
-            Err(e) => {
-                error!("Failed to connect to Postgres DB: {:?}", e);
-                return;
+    storage.close().await;
+    info!("👋 App shutdown complete");
+    code
+}
+
+/// Retry spooled batches every `Spool.RetrySeconds` until shutdown, and report spool depth
+/// while a sink is behind
+async fn replay_spools(storage: Arc<StorageBackend>, mut shutdown: watch::Receiver<bool>) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(SETTINGS.spool.retry_seconds.max(1)));
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.changed() => return,
+        }
+
+        let written = storage.sink().replay_spools().await;
+        if written > 0 {
+            info!("♻️ Wrote {} spooled batches", written);
+        }
+        for (sink, stats) in storage.sink().spool_stats().await {
+            if stats.pending_batches > 0 {
+                info!(
+                    "📦 Spool depth for {}: {} batches, {} KiB in {} segments, {} evicted since start",
+                    sink,
+                    stats.pending_batches,
+                    stats.pending_bytes / 1024,
+                    stats.segments,
+                    stats.evicted_batches
+                );
             }
-        };
+        }
+    }
+}
 
-        // Auto-migrate schema
-        if let Some(ref db_instance) = db {
-            if let Err(e) = pkg::dbcontext::migration::auto_migrate(&db_instance.pool).await {
-                error!("❌ AutoMigrate failed: {:?}", e);
-                return;
+/// Exchange section for a one-off command; `--exchange` may name one that isn't configured
+fn command_section(exchange: Option<String>, symbols: Vec<String>) -> anyhow::Result<ExchangeSettings> {
+    let mut section = match SETTINGS.exchange_section(exchange.as_deref()) {
+        Some(section) => section,
+        None => {
+            let name = exchange.ok_or_else(|| anyhow!("no exchange configured, pass --exchange"))?.to_lowercase();
+            warn!("⚠️ {} has no section in appsettings.yaml, using it with defaults", name);
+            ExchangeSettings {
+                instance: name.clone(),
+                exchange: name,
+                refresh_seconds: SETTINGS.refresh_seconds,
+                feed_mode: SETTINGS.feed_mode,
+                intervals: default_intervals(),
+                symbols: Vec::new(),
+                blacklisted_symbols: Vec::new(),
+                symbol_rules: Default::default(),
             }
-            info!("✅ Postgres auto-migration complete");
         }
+    };
 
-        StorageBackend::Postgres(&db.unwrap())
+    // Canonical ids (BTC/USDT:USDT) resolve to this exchange's native symbol
+    if !symbols.is_empty() {
+        section.symbols = symbols
+            .iter()
+            .map(|s| {
+                if s.contains('/') {
+                    Instrument::from_canonical(&section.exchange, s).map(|i| i.native_symbol)
+                } else {
+                    Ok(s.clone())
+                }
+            })
+            .collect::<anyhow::Result<_>>()?;
+    }
+    if section.symbols.is_empty() {
+        return Err(anyhow!("no symbols configured for {}, pass --symbols", section.exchange));
+    }
+    Ok(section)
+}
+
+async fn backfill_command(storage: &StorageBackend, args: BackfillArgs) -> anyhow::Result<()> {
+    let section = command_section(args.exchange, args.symbols)?;
+    let api = create_exchange_api(&section.exchange)?;
+
+    let req = BackfillRequest {
+        exchange: section.exchange,
+        instance: section.instance,
+        symbols: section.symbols,
+        interval: args.interval,
+        start: args.start,
+        end: args.end.unwrap_or_else(Utc::now),
+        checkpoint_path: args.checkpoint,
+        update: args.update,
     };
 
-    let exchange = settings_ref.exchange.to_lowercase();
-    let symbols = settings_ref.symbols.clone();
+    info!("⏪ Backfill of {} symbols on {} into {}", req.symbols.len(), req.exchange, storage.name());
+    run_backfill(api.as_ref(), storage, &req).await
+}
 
-    let mut invalid_symbols: HashSet<String> = settings_ref
-        .blacklisted_symbols
-        .iter()
-        .map(|s| s.to_uppercase())
-        .collect();
+async fn gaps_command(storage: &StorageBackend, args: GapsArgs) -> anyhow::Result<()> {
+    let section = command_section(args.exchange, args.symbols)?;
+    let api = create_exchange_api(&section.exchange)?;
+
+    let end = args
+        .end
+        .unwrap_or_else(|| Utc::now() - chrono::Duration::minutes(GAP_SCAN_SETTLE_MINUTES));
+    let start = args
+        .start
+        .unwrap_or_else(|| end - chrono::Duration::minutes(SETTINGS.gap_scan.lookback_minutes));
+
+    let req = GapScanRequest {
+        exchange: section.exchange,
+        instance: section.instance,
+        symbols: section.symbols,
+        interval: args.interval,
+        start,
+        end,
+        repair: args.repair,
+    };
 
-    let batch_size = 50;
-    let mut rotator = SymbolRotator::new(symbols, batch_size);
+    scan_and_repair(api.as_ref(), storage, &req).await?;
+    Ok(())
+}
 
-    let k_agg = KlineAggregator::new(settings_ref.debug);
+async fn export_command(storage: &StorageBackend, args: ExportArgs) -> anyhow::Result<()> {
+    let section = command_section(args.exchange, args.symbols)?;
+    let req = ExportRequest {
+        exchange: section.exchange,
+        symbols: section.symbols,
+        interval: args.interval,
+        start: args.start,
+        end: args.end.unwrap_or_else(Utc::now),
+        aligned: args.aligned,
+    };
 
-    let refresh_interval_secs = if settings_ref.refresh_seconds < 4 {
-        4
-    } else {
-        settings_ref.refresh_seconds
+    let rows = match &args.output {
+        Some(path) => {
+            let mut out = BufWriter::new(File::create(path)?);
+            run_export(storage.store(), &req, &mut out).await?
+        }
+        None => run_export(storage.store(), &req, &mut BufWriter::new(std::io::stdout())).await?,
     };
-    let interval_duration = Duration::from_secs(refresh_interval_secs as u64);
+    info!("📤 Exported {} rows of {} symbols from {}", rows, req.symbols.len(), storage.store().name());
+    Ok(())
+}
 
-    let mut ticker = interval_at(Instant::now() + interval_duration, interval_duration);
+async fn migrate_command(args: &MigrateArgs) -> anyhow::Result<()> {
+    let db = DB::init(&SETTINGS.database).await?;
+    let table = &db.table;
+
+    match args.action {
+        Some(MigrateAction::Status) => {
+            println!("{} ({})", table.qualified(), table.migrations_table());
+            println!("{:>7}  {:<44} {:<10} applied", "version", "name", "state");
+            for m in migration_status(&db.pool, table).await? {
+                let (state, applied_at) = match m.state {
+                    MigrationState::Pending => ("pending", None),
+                    MigrationState::Applied(at) => ("applied", Some(at)),
+                    MigrationState::Modified(at) => ("modified", Some(at)),
+                    MigrationState::Unknown(at) => ("unknown", Some(at)),
+                };
+                let applied_at = applied_at.map_or_else(|| "-".to_string(), |at| at.to_rfc3339());
+                println!("{:>7}  {:<44} {:<10} {}", m.version, m.name, state, applied_at);
+            }
+        }
+        None if args.dry_run => {
+            let status = migration_status(&db.pool, table).await?;
+            let pending: Vec<_> = migrations()
+                .iter()
+                .filter(|m| status.iter().any(|s| s.version == m.version && s.state == MigrationState::Pending))
+                .collect();
+            if pending.is_empty() {
+                info!("✅ {} is up to date, nothing to apply", table.qualified());
+            }
+            for migration in pending {
+                println!("-- {} {}", migration.version, migration.name);
+                for sql in migration.render(table) {
+                    println!("{};", sql.trim());
+                }
+                println!();
+            }
+        }
+        None => {
+            let applied = run_migrations(&db.pool, table).await?;
+            apply_timescale_settings(&db.pool, table, &SETTINGS.database.timescale).await?;
+            info!("✅ Applied {} migrations to {}", applied, table.qualified());
+        }
+    }
 
-    info!("⏳ Starting periodic fetch loop for exchange: {}", exchange);
+    db.pool.close().await;
+    Ok(())
+}
 
-    loop {
-        tokio::select! {
-                    _ = ticker.tick() => {
-                        info!("🔄 New fetch cycle started");
-
-                        // Random jitter
-                        let jitter = rand::thread_rng().gen_range(0..500);
-                        tokio::time::sleep(Duration::from_millis(jitter)).await;
-
-                        // Get next batch
-                        let raw_batch = rotator.next_batch();
-                        let batch: Vec<String> = raw_batch
-                            .into_iter()
-                            .flat_map(|slice| slice.iter())
-                            .filter(|sym| !invalid_symbols.contains(&sym.to_uppercase()))
-                            .cloned()
-                            .collect();
-
-                        info!("📦 Processing batch of {} symbols", batch.len());
-
-                        if batch.is_empty() {
-                            warn!("⚠️ No valid symbols in batch to process");
-                            continue;
-                        }
-
-                        // Fetch tickers
-                        info!("🌐 Fetching tickers from {}", exchange);
-                        let tickers_res = core_futures_all_tickers(&exchange).await;
-                        let tickers = match tickers_res {
-                            Ok(data) => {
-                                info!("✅ Retrieved {} total tickers from {}", data.len(), exchange);
-                                data
-                            }
-                            Err(e) => {
-                                error!("❌ Failed to fetch tickers: {:?}", e);
-                                continue;
-                            }
-                        };
-
-                        // Filter tickers for batch
-                        let batch_set: HashSet<String> = batch.iter().map(|s| s.to_uppercase()).collect();
-                        let filtered: Vec<TickerInfo> = tickers.into_iter()
-                            .filter(|t| batch_set.contains(&t.symbol.to_uppercase()))
-                            .collect();
-
-                        info!("📥 Matched {} tickers from batch request", filtered.len());
-
-                        // Blacklist missing symbols
-                        let found_symbols: HashSet<String> = filtered.iter()
-                            .map(|t| t.symbol.to_uppercase())
-                            .collect();
-
-                        for sym in &batch {
-                            let up = sym.to_uppercase();
-                            if !found_symbols.contains(&up) && !invalid_symbols.contains(&up) {
-                                warn!("🚫 Symbol {} not found, blacklisting", up);
-                                invalid_symbols.insert(up.clone());
-
-                                if let Err(e) = pkg::save_config::save_config("appsettings.yaml").await {
-                                    error!("❌ Failed to save config: {:?}", e);
-                                }
-                            }
-                        }
-
-                        // Feed aggregator
-                        for t in &filtered {
-                            match (t.last_price.parse::<f64>(), t.vol_24h.as_deref()) {
-                                (Ok(price), Some(vol_str)) => {
-                                    if let Ok(volume) = vol_str.parse::<f64>() {
-                                        k_agg.add_price(&t.symbol, price, volume).await;
-                                    } else {
-                                        warn!("❌ Failed to parse volume for symbol {}", t.symbol);
-                                    }
-                                }
-                                _ => warn!("❌ Failed to parse price or volume for symbol {}", t.symbol),
-                            }
-                        }
-                        info!("📊 Aggregator updated with {} tickers", filtered.len());
-
-                        // Flush intervals
-                        let flush_intervals = get_flush_intervals();
-                        if k_agg.debug {
-                            info!("[Debug] Flushing intervals: {:?}", flush_intervals);
-                        }
-
-                        if !flush_intervals.is_empty() {
-                            let flush_refs: Vec<&str> = flush_intervals.iter().map(|s| s.as_str()).collect();
-                            let kline_data = k_agg.extract_ohlc(&flush_refs).await;
-
-                            if !kline_data.is_empty() {
-                                info!("📝 Preparing to save {} OHLC records", kline_data.len());
-                                match &storage {
-                                    StorageBackend::ClickHouse(ch_client) => {
-                                        if let Err(e) = ch_client.save_symbol_klines(&kline_data, &SETTINGS.instance).await {
-                                            error!("❌ Failed to save klines to ClickHouse: {:?}", e);
-                                        } else {
-                                            info!("💾 Successfully saved {} OHLC entries to ClickHouse", kline_data.len());
-                                        }
-                                    }
-                                    StorageBackend::Postgres(db) => {
-                                        if let Err(e) = crate::pkg::dbcontext::kline::save_klines(&db.pool, &kline_data, &SETTINGS.instance).await {
-                                            error!("❌ Failed to save klines to Postgres: {:?}", e);
-                                        } else {
-                                            info!("💾 Successfully saved {} OHLC entries to Postgres", kline_data.len());
-                                        }
-                                    }
-                                }
-                                
-                            } else {
-                                info!("ℹ️ No OHLC data to save this cycle");
-                            }
-                        }
-                    },
-                    _ = signal::ctrl_c() => {
-                        info!("🛑 Shutdown signal received");
-                        break;
-                    }
-                }
+async fn refresh_aggregates_command(args: &RefreshAggregatesArgs) -> anyhow::Result<()> {
+    let intervals = if args.intervals.is_empty() {
+        SETTINGS.database.timescale.continuous_aggregates.clone()
+    } else {
+        args.intervals.clone()
+    };
+    if intervals.is_empty() {
+        return Err(anyhow!("no continuous aggregates configured, pass --intervals"));
     }
 
-    info!("👋 App shutdown complete");
+    let db = DB::init(&SETTINGS.database).await?;
+    let end = args.end.unwrap_or_else(Utc::now);
+    let result = refresh_aggregates(&db.pool, &db.table, &SETTINGS.database.timescale, &intervals, args.start, end).await;
+    db.pool.close().await;
+    result?;
+    info!("✅ Refreshed {} continuous aggregates", intervals.len());
+    Ok(())
 }
-fn get_flush_intervals() -> Vec<String> {
-    // For now, always flush 1m interval klines
-    vec!["1m".to_string()]
+
+async fn instruments_command(args: InstrumentsArgs) -> anyhow::Result<()> {
+    let exchange = match args.exchange {
+        Some(exchange) => exchange.to_lowercase(),
+        None => SETTINGS
+            .exchange_section(None)
+            .map(|s| s.exchange)
+            .ok_or_else(|| anyhow!("no exchange configured, pass --exchange"))?,
+    };
+    let api = create_exchange_api(&exchange)?;
+
+    let mut instruments = api.get_instruments().await?;
+    if let Some(quote) = &args.quote {
+        instruments.retain(|i| i.instrument.quote.eq_ignore_ascii_case(quote));
+    }
+    instruments.sort_by(|a, b| a.instrument.native_symbol.cmp(&b.instrument.native_symbol));
+
+    println!(
+        "{:<24} {:<28} {:<12} {:<10} {:<8} {:<8} {:<8} {:>14} {:>14} {:>10}  listed",
+        "symbol", "canonical", "kind", "status", "base", "quote", "settle", "tick", "lot", "ct_val"
+    );
+    for i in &instruments {
+        println!(
+            "{:<24} {:<28} {:<12} {:<10} {:<8} {:<8} {:<8} {:>14} {:>14} {:>10}  {}",
+            i.instrument.native_symbol,
+            i.instrument.canonical(),
+            format!("{:?}", i.instrument.kind),
+            format!("{:?}", i.status),
+            i.instrument.base,
+            i.instrument.quote,
+            i.instrument.settle,
+            i.tick_size,
+            i.lot_size,
+            i.contract_value,
+            i.listing_time.map(|t| t.to_rfc3339()).unwrap_or_else(|| "-".to_string())
+        );
+    }
+    info!("✅ {} instruments on {}", instruments.len(), exchange);
+    Ok(())
 }
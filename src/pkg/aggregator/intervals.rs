@@ -0,0 +1,13 @@
+/// Interval length in milliseconds, `None` for intervals we don't support (1m, 3m, 5m, 15m, 1h, 4h, 1d)
+pub fn interval_to_ms(interval: &str) -> Option<i64> {
+    match interval {
+        "1m" => Some(60_000),
+        "3m" => Some(3 * 60_000),
+        "5m" => Some(5 * 60_000),
+        "15m" => Some(15 * 60_000),
+        "1h" => Some(60 * 60_000),
+        "4h" => Some(4 * 60 * 60_000),
+        "1d" => Some(24 * 60 * 60_000),
+        _ => None,
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::{BTreeMap, HashMap};
+use chrono::{TimeZone, Utc};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::pkg::dbcontext::entities::SymbolKlineData;
+
+/// Builds higher timeframe candles out of base candles, buckets aligned to UTC.
+/// The base candles of a bucket are kept until it is final, so an amended base candle
+/// amends the bucket it belongs to as well.
+pub struct KlineRollup {
+    targets: Vec<(String, i64)>, // (interval, length in ms), all multiples of the base interval
+    buckets: HashMap<(String, String), BTreeMap<i64, Bucket>>, // (symbol, interval) -> bucket start -> bucket
+    final_until: HashMap<(String, String), i64>, // buckets ending by here were dropped, later input for them is too
+}
+
+/// Candles to write after a flush: `amended` ones replace rows written earlier
+#[derive(Debug, Default)]
+pub struct FlushedKlines {
+    pub closed: Vec<SymbolKlineData>,  // first version of a bucket
+    pub amended: Vec<SymbolKlineData>, // bucket written before whose base candles changed since
+}
+
+/// Open buckets and finality marks of a rollup, carried in the aggregator snapshot
+#[derive(Default, Serialize, Deserialize)]
+pub struct RollupSnapshot {
+    buckets: Vec<(String, String, BTreeMap<i64, Bucket>)>, // (symbol, interval, buckets)
+    final_until: Vec<(String, String, i64)>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Bucket {
+    exchange: String,
+    bars: BTreeMap<i64, Bar>, // base open time -> base candle
+    complete: bool, // last base candle of the bucket is in
+    emitted: bool,
+    dirty: bool, // changed since it was emitted
+}
+
+// Base candle without its labels, a day of 1m bars per symbol stays small
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Bar {
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    volume: f64,
+    volume_24h: Option<f64>,
+    trade_count: i64,
+    buy_volume: f64,
+    sell_volume: f64,
+    is_synthetic: bool,
+}
+
+impl KlineRollup {
+    pub fn new(targets: Vec<(String, i64)>) -> Self {
+        Self {
+            targets,
+            buckets: HashMap::new(),
+            final_until: HashMap::new(),
+        }
+    }
+
+    /// Add or replace a base candle of `base_ms` length in every target bucket it belongs to
+    pub fn add(&mut self, base: &SymbolKlineData, base_ms: i64) {
+        let open_ms = base.open_time.timestamp_millis();
+        let bar = Bar {
+            open: base.open,
+            high: base.high,
+            low: base.low,
+            close: base.close,
+            volume: base.volume,
+            volume_24h: base.volume_24h,
+            trade_count: base.trade_count,
+            buy_volume: base.buy_volume,
+            sell_volume: base.sell_volume,
+            is_synthetic: base.is_synthetic,
+        };
+
+        for (interval, interval_ms) in &self.targets {
+            let bucket_start = open_ms - open_ms.rem_euclid(*interval_ms);
+            let key = (base.symbol.clone(), interval.clone());
+
+            if self.final_until.get(&key).is_some_and(|end| bucket_start < *end) {
+                debug!("Dropping late {} candle for {} [{}]", base.interval, base.symbol, interval);
+                continue;
+            }
+
+            let bucket = self.buckets.entry(key).or_default().entry(bucket_start).or_default();
+            bucket.exchange.clone_from(&base.exchange);
+            bucket.bars.insert(open_ms, bar);
+            bucket.complete |= open_ms + base_ms >= bucket_start + interval_ms;
+            bucket.dirty = bucket.emitted;
+        }
+    }
+
+    /// Buckets that are complete or ended by `now_ms` and not yet written, plus written ones
+    /// that changed since. Buckets ending by `final_ms` are dropped afterwards.
+    pub fn take(&mut self, now_ms: i64, final_ms: i64) -> FlushedKlines {
+        let lengths: HashMap<&str, i64> = self.targets.iter().map(|(i, ms)| (i.as_str(), *ms)).collect();
+        let mut out = FlushedKlines::default();
+
+        for ((symbol, interval), buckets) in self.buckets.iter_mut() {
+            let interval_ms = lengths.get(interval.as_str()).copied().unwrap_or(0);
+
+            for (start, bucket) in buckets.iter_mut() {
+                let end = start + interval_ms;
+                if !bucket.emitted && (bucket.complete || end <= now_ms) {
+                    out.closed.push(bucket.to_kline(symbol, interval, *start));
+                    bucket.emitted = true;
+                } else if bucket.dirty {
+                    out.amended.push(bucket.to_kline(symbol, interval, *start));
+                    bucket.dirty = false;
+                }
+            }
+
+            let before = buckets.len();
+            buckets.retain(|start, bucket| !(bucket.emitted && start + interval_ms <= final_ms));
+            if buckets.len() != before {
+                let until = final_ms - final_ms.rem_euclid(interval_ms.max(1));
+                self.final_until.insert((symbol.clone(), interval.clone()), until);
+            }
+        }
+        self.buckets.retain(|_, buckets| !buckets.is_empty());
+
+        out.closed.sort_by(|a, b| a.open_time.cmp(&b.open_time).then_with(|| a.symbol.cmp(&b.symbol)));
+        out.amended.sort_by(|a, b| a.open_time.cmp(&b.open_time).then_with(|| a.symbol.cmp(&b.symbol)));
+        out
+    }
+
+    /// Copy of the open buckets, for saving across restarts
+    pub fn snapshot(&self) -> RollupSnapshot {
+        RollupSnapshot {
+            buckets: self
+                .buckets
+                .iter()
+                .map(|((symbol, interval), buckets)| (symbol.clone(), interval.clone(), buckets.clone()))
+                .collect(),
+            final_until: self
+                .final_until
+                .iter()
+                .map(|((symbol, interval), until)| (symbol.clone(), interval.clone(), *until))
+                .collect(),
+        }
+    }
+
+    /// Load buckets saved by an earlier run; intervals no longer configured are skipped
+    pub fn restore(&mut self, snapshot: RollupSnapshot) {
+        let configured = |interval: &str| self.targets.iter().any(|(i, _)| i == interval);
+        let buckets: Vec<_> = snapshot.buckets.into_iter().filter(|(_, i, _)| configured(i)).collect();
+        let final_until: Vec<_> = snapshot.final_until.into_iter().filter(|(_, i, _)| configured(i)).collect();
+
+        for (symbol, interval, buckets) in buckets {
+            self.buckets.insert((symbol, interval), buckets);
+        }
+        for (symbol, interval, until) in final_until {
+            self.final_until.insert((symbol, interval), until);
+        }
+    }
+}
+
+impl Bucket {
+    fn to_kline(&self, symbol: &str, interval: &str, start: i64) -> SymbolKlineData {
+        let mut bars = self.bars.values();
+        let first = *bars.next().expect("bucket without base candles");
+        let candle = bars.fold(first, |mut acc, bar| {
+            acc.high = acc.high.max(bar.high);
+            acc.low = acc.low.min(bar.low);
+            acc.close = bar.close;
+            acc.volume += bar.volume;
+            acc.trade_count += bar.trade_count;
+            acc.buy_volume += bar.buy_volume;
+            acc.sell_volume += bar.sell_volume;
+            if bar.volume_24h.is_some() {
+                acc.volume_24h = bar.volume_24h;
+            }
+            // Synthetic only if no base candle in the bucket had ticks
+            acc.is_synthetic &= bar.is_synthetic;
+            acc
+        });
+
+        SymbolKlineData {
+            exchange: self.exchange.clone(),
+            symbol: symbol.to_string(),
+            interval: interval.to_string(),
+            open: candle.open,
+            high: candle.high,
+            low: candle.low,
+            close: candle.close,
+            open_time: Utc.timestamp_millis_opt(start).single().unwrap_or_default(),
+            volume: candle.volume,
+            volume_24h: candle.volume_24h,
+            trade_count: candle.trade_count,
+            buy_volume: candle.buy_volume,
+            sell_volume: candle.sell_volume,
+            is_synthetic: candle.is_synthetic,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minute(minute: i64, open: f64, close: f64) -> SymbolKlineData {
+        SymbolKlineData {
+            exchange: "binance".to_string(),
+            symbol: "BTCUSDT".to_string(),
+            interval: "1m".to_string(),
+            open,
+            high: open.max(close) + 1.0,
+            low: open.min(close) - 1.0,
+            close,
+            open_time: Utc.timestamp_millis_opt(minute * 60_000).unwrap(),
+            volume: 2.0,
+            volume_24h: None,
+            trade_count: 3,
+            buy_volume: 1.5,
+            sell_volume: 0.5,
+            is_synthetic: false,
+        }
+    }
+
+    #[test]
+    fn five_minute_bucket_closes_on_its_last_minute() {
+        let mut rollup = KlineRollup::new(vec![("5m".to_string(), 300_000)]);
+
+        for m in 0..4 {
+            rollup.add(&minute(m, 100.0 + m as f64, 101.0 + m as f64), 60_000);
+            assert!(rollup.take(0, 0).closed.is_empty());
+        }
+        rollup.add(&minute(4, 104.0, 99.0), 60_000);
+        let done = rollup.take(0, 0).closed;
+
+        assert_eq!(done.len(), 1);
+        let candle = &done[0];
+        assert_eq!(candle.interval, "5m");
+        assert_eq!(candle.open_time.timestamp_millis(), 0);
+        assert_eq!((candle.open, candle.close), (100.0, 99.0));
+        assert_eq!((candle.high, candle.low), (105.0, 98.0));
+        assert_eq!(candle.volume, 10.0);
+        assert_eq!(candle.trade_count, 15);
+    }
+
+    #[test]
+    fn amended_minute_amends_its_bucket_until_final() {
+        let mut rollup = KlineRollup::new(vec![("5m".to_string(), 300_000)]);
+        for m in 0..5 {
+            rollup.add(&minute(m, 100.0, 100.0), 60_000);
+        }
+        assert_eq!(rollup.take(0, 0).closed.len(), 1);
+
+        // A late tick rebuilt minute 2 with a new high
+        let mut amended = minute(2, 100.0, 100.0);
+        amended.high = 120.0;
+        rollup.add(&amended, 60_000);
+        let out = rollup.take(300_000, 300_000);
+        assert!(out.closed.is_empty());
+        assert_eq!(out.amended.len(), 1);
+        assert_eq!(out.amended[0].high, 120.0);
+
+        // Past the watermark the bucket is gone and stragglers are ignored
+        rollup.add(&minute(3, 1.0, 1.0), 60_000);
+        let out = rollup.take(i64::MAX, i64::MAX);
+        assert!(out.closed.is_empty() && out.amended.is_empty());
+    }
+
+    #[test]
+    fn incomplete_bucket_closes_by_the_clock() {
+        let mut rollup = KlineRollup::new(vec![("15m".to_string(), 900_000)]);
+        rollup.add(&minute(16, 10.0, 11.0), 60_000);
+
+        assert!(rollup.take(29 * 60_000, 0).closed.is_empty());
+        let closed = rollup.take(30 * 60_000, 0).closed;
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].open_time.timestamp_millis(), 15 * 60_000);
+    }
+}
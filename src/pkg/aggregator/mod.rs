@@ -1,2 +1,5 @@
+pub mod intervals;
+pub mod kline_rollup;
+pub mod snapshot;
 pub mod symbol_rotator;
-pub mod ticker_aggregator;
\ No newline at end of file
+pub mod ticker_aggregator;
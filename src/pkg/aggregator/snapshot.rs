@@ -0,0 +1,51 @@
+use std::path::{Path, PathBuf};
+use anyhow::Context;
+use log::debug;
+
+use crate::pkg::aggregator::ticker_aggregator::{AggregatorSnapshot, KlineAggregator};
+
+/// Aggregator state on local disk, one JSON file per exchange section.
+/// Files are replaced atomically, a crash mid-write leaves the previous snapshot intact.
+pub struct SnapshotStore {
+    path: PathBuf,
+}
+
+impl SnapshotStore {
+    pub fn new(directory: &str, instance: &str) -> Self {
+        Self {
+            path: Path::new(directory).join(format!("{}.json", instance)),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write the aggregator's in-progress state
+    pub async fn save(&self, agg: &KlineAggregator) -> anyhow::Result<()> {
+        let data = serde_json::to_vec(&agg.snapshot())?;
+        if let Some(dir) = self.path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+
+        let tmp = self.path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, &data)
+            .await
+            .with_context(|| format!("writing {}", tmp.display()))?;
+        tokio::fs::rename(&tmp, &self.path).await?;
+        debug!("Saved aggregator snapshot to {} ({} bytes)", self.path.display(), data.len());
+        Ok(())
+    }
+
+    /// Load the saved state into `agg`; false when there is none or it is too old to use
+    pub async fn restore(&self, agg: &KlineAggregator) -> anyhow::Result<bool> {
+        let data = match tokio::fs::read(&self.path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", self.path.display())),
+        };
+        let snapshot: AggregatorSnapshot =
+            serde_json::from_slice(&data).with_context(|| format!("parsing {}", self.path.display()))?;
+        Ok(agg.restore(snapshot))
+    }
+}
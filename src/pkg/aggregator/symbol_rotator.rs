@@ -13,6 +13,12 @@ impl SymbolRotator {
         }
     }
 
+    /// Swap in a new symbol list, e.g. after a listing refresh; rotation restarts from the top
+    pub fn set_symbols(&mut self, symbols: Vec<String>) {
+        self.all_symbols = symbols;
+        self.current_idx = 0;
+    }
+
     pub fn next_batch(&mut self) -> Option<&[String]> {
         let n = self.all_symbols.len();
         if n == 0 || self.batch_size == 0 {
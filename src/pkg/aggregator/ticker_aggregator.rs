@@ -1,185 +1,563 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{BuildHasher, RandomState};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
 use std::time::{SystemTime, UNIX_EPOCH};
 use chrono::{TimeZone, Utc};
-use log::debug;
-use tokio::sync::Mutex;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
 
+use crate::pkg::aggregator::intervals::interval_to_ms;
+use crate::pkg::aggregator::kline_rollup::{KlineRollup, FlushedKlines, RollupSnapshot};
 use crate::pkg::dbcontext::entities::SymbolKlineData;
+use crate::pkg::exchanges::exchange_entities::{TradeData, TradeSide};
+
+/// A 24h volume drop below this share of the previous reading is treated as a counter reset
+/// rather than old trades rolling out of the 24h window.
+const VOLUME_RESET_RATIO: f64 = 0.5;
+
+/// Ticks are only bucketed into base candles; every other interval is rolled up from those
+pub const BASE_INTERVAL: &str = "1m";
+const BASE_INTERVAL_MS: i64 = 60_000;
+
+/// Ticks stamped further than this ahead of the local clock are rejected instead of moving the
+/// event clock: one bad timestamp would otherwise push the watermark past every real tick
+const MAX_CLOCK_SKEW_MS: i64 = 10_000;
+
+/// Symbol map shards; a tick only locks the shard its symbol hashes to
+const SHARD_COUNT: usize = 64;
 
 #[derive(Clone, Debug)]
 pub struct TickData {
     pub price: f64,
-    pub time: i64, // milliseconds since epoch
-    pub volume: f64,
+    pub time: i64, // exchange event time, milliseconds since epoch
+    pub volume: f64, // traded volume since the previous tick (trade qty for public trades)
+    pub volume_24h: Option<f64>, // raw 24h snapshot, ticker ticks only
+    pub side: Option<TradeSide>, // set for public trades, None for ticker snapshots
+    pub count: i64,
+}
+
+/// Running OHLC of one base candle, updated in place by every tick that lands in it.
+/// Open and close follow event time, so out-of-order ticks still end up where they belong;
+/// ticks sharing a timestamp keep arrival order.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct MinuteBar {
+    open: f64,
+    open_tick: i64, // event time of the tick that set `open`
+    high: f64,
+    low: f64,
+    close: f64,
+    close_tick: i64, // event time of the tick that set `close`
+    volume: f64,
+    buy_volume: f64,
+    sell_volume: f64,
+    count: i64,
+    volume_24h: Option<f64>,
+    volume_24h_tick: i64,
+    changed: bool, // got ticks since it was last written
+}
+
+/// Everything kept per symbol: the base candles still within the allowed lateness
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct SymbolState {
+    bars: BTreeMap<i64, MinuteBar>, // base open time -> running candle
+    written: BTreeMap<i64, Written>, // base candles written within the allowed lateness, plus the newest one
+    last_volume_24h: Option<(i64, f64)>, // (event time, 24h volume) of the newest snapshot
+    last_snapshot: Option<i64>, // second of the newest ticker snapshot, repeats within it only add volume
+}
+
+// What was stored for a base candle, enough to forward-fill after it and to amend it
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Written {
+    close: f64,
+    synthetic: bool,
 }
 
+/// In-progress state of an aggregator, saved to disk so a restart picks up the current minute
+#[derive(Serialize, Deserialize)]
+pub struct AggregatorSnapshot {
+    pub exchange: String,
+    pub saved_at: i64, // local time of the snapshot, milliseconds since epoch
+    event_clock: i64,
+    symbols: HashMap<String, SymbolState>,
+    rollup: RollupSnapshot,
+}
+
+/// Event-time candle builder. Ticks are folded into per-symbol running candles keyed by their
+/// exchange timestamp; a candle is written once its minute has passed on the event clock
+/// (newest exchange timestamp or local clock, whichever is ahead) and amended by ticks arriving
+/// within `allowed_lateness_ms`. Older ticks are past the watermark and only counted.
+///
+/// Symbols are spread over `SHARD_COUNT` independently locked maps, so feeds for different
+/// symbols rarely contend; the clock and counters are atomics and read without a lock.
 pub struct KlineAggregator {
-    tick_buffer: Mutex<HashMap<String, HashMap<String, Vec<TickData>>>>,
-    interval_to_ms: HashMap<String, i64>,
-    max_interval_ms: i64,
+    shards: Box<[Mutex<HashMap<String, SymbolState>>]>,
+    hasher: RandomState,
+    rollup: Mutex<KlineRollup>,
+    emit_base: bool, // base candles are only returned when BASE_INTERVAL is configured
+    forward_fill: bool, // write a synthetic candle for tracked symbols with no ticks in a minute
+    event_clock: AtomicI64, // newest exchange timestamp seen
+    allowed_lateness_ms: i64,
+    dropped_late: AtomicU64,
+    dropped_future: AtomicU64,
+    exchange: String,
     pub debug: bool,
 }
 
 impl KlineAggregator {
-    pub fn new(debug: bool) -> Self {
-        let intervals = HashMap::from([
-            ("1m".to_string(), 60_000),
-            // add more intervals here if needed
-        ]);
-
-        // Find max interval in milliseconds
-        let max_interval_ms = intervals.values().copied().max().unwrap_or(60_000);
+    /// Aggregator producing `intervals` (1m, 3m, 5m, 15m, 1h, 4h, 1d); unsupported ones are skipped
+    pub fn new(exchange: &str, intervals: &[String], allowed_lateness_ms: i64, forward_fill: bool, debug: bool) -> Self {
+        let mut targets = Vec::new();
+        for interval in intervals {
+            match interval_to_ms(interval) {
+                Some(_) if interval == BASE_INTERVAL => {}
+                Some(ms) => targets.push((interval.clone(), ms)),
+                None => warn!("⚠️ [{}] Unsupported interval {}, skipping it", exchange, interval),
+            }
+        }
 
         Self {
-            tick_buffer: Mutex::new(HashMap::new()),
-            interval_to_ms: intervals,
-            max_interval_ms,
+            shards: (0..SHARD_COUNT).map(|_| Mutex::new(HashMap::new())).collect(),
+            hasher: RandomState::new(),
+            rollup: Mutex::new(KlineRollup::new(targets)),
+            emit_base: intervals.iter().any(|i| i == BASE_INTERVAL),
+            forward_fill,
+            event_clock: AtomicI64::new(0),
+            allowed_lateness_ms: allowed_lateness_ms.max(0),
+            dropped_late: AtomicU64::new(0),
+            dropped_future: AtomicU64::new(0),
+            exchange: exchange.to_lowercase(),
             debug,
         }
     }
 
-    /// Add a new price tick for a symbol
-    pub async fn add_price(&self, symbol: &str, price: f64, volume: f64) {
-        let mut buffer = self.tick_buffer.lock().await;
+    /// Ticks dropped for arriving past the watermark since startup
+    pub fn dropped_late(&self) -> u64 {
+        self.dropped_late.load(Ordering::Relaxed)
+    }
 
-        let now = current_millis();
-        let truncated = now - (now % 1000); // truncate to nearest second in ms
+    /// Ticks dropped for a timestamp more than `MAX_CLOCK_SKEW_MS` ahead of the local clock since startup
+    pub fn dropped_future(&self) -> u64 {
+        self.dropped_future.load(Ordering::Relaxed)
+    }
+
+    /// Add a new price tick for a symbol, `volume_24h` being the exchange's rolling 24h volume.
+    /// `timestamp` is the exchange event time; the local clock stands in when the feed has none.
+    pub fn add_price(&self, symbol: &str, price: f64, volume_24h: f64, timestamp: Option<i64>) {
+        let event_time = timestamp.unwrap_or_else(current_millis);
+        let truncated = event_time - event_time.rem_euclid(1000); // truncate to the second
+        if !self.accept(truncated) {
+            return;
+        }
+
+        let mut shard = self.shard(symbol);
+        let state = state_mut(&mut shard, symbol);
+
+        let volume = match state.last_volume_24h {
+            // An older snapshot than one already seen says nothing about volume since then
+            Some((last_time, _)) if last_time > event_time => 0.0,
+            prev => {
+                state.last_volume_24h = Some((event_time, volume_24h));
+                volume_delta(prev.map(|(_, v)| v), volume_24h)
+            }
+        };
+
+        let minute = truncated - truncated.rem_euclid(BASE_INTERVAL_MS);
+        let repeat = state.last_snapshot == Some(truncated);
+        state.last_snapshot = Some(truncated);
+
+        // Another snapshot within the same second only adds its volume delta
+        if repeat && let Some(bar) = state.bars.get_mut(&minute) {
+            bar.volume += volume;
+            bar.volume_24h = Some(volume_24h);
+            bar.changed = true;
+            return;
+        }
 
         let tick = TickData {
             price,
             time: truncated,
             volume,
+            volume_24h: Some(volume_24h),
+            side: None,
+            count: 1,
         };
+        add_tick(state, minute, &tick);
+        if self.debug {
+            debug!("Added tick to {}: {:.2}", symbol, price);
+        }
+    }
 
-        // Ensure symbol entry exists
-        let interval_map = buffer
-            .entry(symbol.to_string())
-            .or_insert_with(HashMap::new);
-
-        for (interval, _) in &self.interval_to_ms {
-            let ticks = interval_map
-                .entry(interval.clone())
-                .or_insert_with(Vec::new);
-
-            // Only add if no tick with same timestamp exists
-            if !ticks.iter().any(|t| t.time == truncated) {
-                ticks.push(tick.clone());
-                if self.debug {
-                    debug!("Added tick to {} [{}]: {:.2}", symbol, interval, price);
-                }
-            }
+    /// Add a public trade for a symbol, bucketed by its exchange timestamp
+    pub fn add_trade(&self, trade: &TradeData) {
+        if !self.accept(trade.timestamp) {
+            return;
+        }
+
+        let tick = TickData {
+            price: trade.price,
+            time: trade.timestamp,
+            volume: trade.qty,
+            volume_24h: None,
+            side: Some(trade.side),
+            count: trade.count,
+        };
+
+        // Every trade counts, several can share one millisecond
+        let mut shard = self.shard(&trade.symbol);
+        let state = state_mut(&mut shard, &trade.symbol);
+        add_tick(state, tick.time - tick.time.rem_euclid(BASE_INTERVAL_MS), &tick);
+
+        if self.debug {
+            debug!("Added trade {} to {}: {:.2} x {}", trade.trade_id, trade.symbol, trade.price, trade.qty);
         }
     }
 
-    /// Extract OHLC data for requested intervals
-    pub async fn extract_ohlc(&self, intervals: &[&str]) -> Vec<SymbolKlineData> {
-        let mut buffer = self.tick_buffer.lock().await;
+    /// Advance the event clock with `event_time`; false (and counted) when it is past the watermark
+    /// or too far in the future
+    fn accept(&self, event_time: i64) -> bool {
         let now = current_millis();
-        let mut result = Vec::new();
+        if event_time > now + MAX_CLOCK_SKEW_MS {
+            self.dropped_future.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        // Plain load first, concurrent feeds would otherwise all write the same cache line
+        if event_time > self.event_clock.load(Ordering::Relaxed) {
+            self.event_clock.fetch_max(event_time, Ordering::Relaxed);
+        }
+        if event_time < self.watermark(now) {
+            self.dropped_late.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    // Helper: locked shard holding `symbol`
+    fn shard(&self, symbol: &str) -> MutexGuard<'_, HashMap<String, SymbolState>> {
+        let idx = self.hasher.hash_one(symbol) as usize % self.shards.len();
+        lock(&self.shards[idx])
+    }
+
+    // Helper: event clock, ahead of the local clock only if the exchange's is
+    fn clock(&self, now: i64) -> i64 {
+        self.event_clock.load(Ordering::Relaxed).max(now)
+    }
+
+    // Helper: ticks older than this are final, candles ending by it can't change anymore
+    fn watermark(&self, now: i64) -> i64 {
+        self.clock(now) - self.allowed_lateness_ms
+    }
+
+    /// Stop tracking symbols that are no longer collected, so they aren't forward-filled forever
+    pub fn retain_symbols(&self, symbols: &[String]) {
+        let keep: HashSet<&str> = symbols.iter().map(|s| s.as_str()).collect();
+        for shard in self.shards.iter() {
+            let mut shard = lock(shard);
+            shard.retain(|symbol, _| keep.contains(symbol.as_str()));
+        }
+    }
 
-        for (symbol, interval_map) in buffer.iter_mut() {
-            for &interval in intervals {
-                let ticks = match interval_map.get_mut(interval) {
-                    Some(t) if !t.is_empty() => t,
-                    _ => continue,
-                };
+    /// Copy of the running candles, what was written within the allowed lateness, the open
+    /// rollup buckets and the event clock
+    pub fn snapshot(&self) -> AggregatorSnapshot {
+        let mut symbols = HashMap::new();
+        for shard in self.shards.iter() {
+            let shard = lock(shard);
+            symbols.extend(shard.iter().map(|(symbol, state)| (symbol.clone(), state.clone())));
+        }
 
-                // Sort ticks by time ascending
-                ticks.sort_unstable_by_key(|t| t.time);
+        AggregatorSnapshot {
+            exchange: self.exchange.clone(),
+            saved_at: current_millis(),
+            event_clock: self.event_clock.load(Ordering::Relaxed),
+            symbols,
+            rollup: lock(&self.rollup).snapshot(),
+        }
+    }
 
-                let interval_ms = match self.interval_to_ms.get(interval) {
-                    Some(ms) => *ms,
-                    None => continue,
-                };
+    /// Load a snapshot taken by an earlier run. False when it belongs to another exchange or
+    /// the minute it was building can no longer be amended, its candles would be stale.
+    pub fn restore(&self, snapshot: AggregatorSnapshot) -> bool {
+        let now = current_millis();
+        let building = snapshot.saved_at - snapshot.saved_at.rem_euclid(BASE_INTERVAL_MS);
+        if snapshot.exchange != self.exchange || building + BASE_INTERVAL_MS + self.allowed_lateness_ms <= now {
+            return false;
+        }
 
-                let candle_end = now - (now % interval_ms);
-                let candle_start = candle_end - interval_ms;
+        self.event_clock.fetch_max(snapshot.event_clock, Ordering::Relaxed);
+        let watermark = self.watermark(now);
+        let final_start = watermark - watermark.rem_euclid(BASE_INTERVAL_MS);
+        for (symbol, mut state) in snapshot.symbols {
+            state.bars.retain(|start, _| *start >= final_start);
+            self.shard(&symbol).insert(symbol, state);
+        }
+        lock(&self.rollup).restore(snapshot.rollup);
+        true
+    }
 
-                // Binary search start and end indices
-                let start_idx = ticks.partition_point(|t| t.time < candle_start);
-                let end_idx = ticks.partition_point(|t| t.time < candle_end);
+    /// Base candles whose minute has passed on the event clock and that got ticks since the
+    /// last flush, plus every higher interval bucket they closed or amended.
+    /// With forward fill, every tracked symbol also gets a synthetic candle for each such
+    /// minute without ticks.
+    pub fn extract_ohlc(&self) -> FlushedKlines {
+        let now = current_millis();
+        let clock = self.clock(now);
+        let last_closed = clock - clock.rem_euclid(BASE_INTERVAL_MS) - BASE_INTERVAL_MS;
+        self.flush(last_closed, now, self.watermark(now))
+    }
 
-                if start_idx == end_idx {
-                    continue;
+    /// Every candle still open, the current minute and unfinished higher interval buckets
+    /// included, for the last flush before shutdown. Nothing stays amendable afterwards.
+    pub fn drain(&self) -> FlushedKlines {
+        let clock = self.clock(current_millis());
+        self.flush(clock - clock.rem_euclid(BASE_INTERVAL_MS), i64::MAX, i64::MAX)
+    }
+
+    // Helper: write base candles up to `last_closed` and roll them up; candles before
+    // `watermark` are final and dropped
+    fn flush(&self, last_closed: i64, now: i64, watermark: i64) -> FlushedKlines {
+        let final_start = watermark - watermark.rem_euclid(BASE_INTERVAL_MS);
+        let mut closed = Vec::new();
+        let mut amended = Vec::new();
+
+        // One shard at a time, ingestion for the other shards carries on meanwhile
+        for shard in self.shards.iter() {
+            let mut shard = lock(shard);
+            for (symbol, state) in shard.iter_mut() {
+                let mut built = BTreeMap::new();
+                for (candle_start, bar) in state.bars.range_mut(..=last_closed) {
+                    if bar.changed {
+                        bar.changed = false;
+                        built.insert(*candle_start, bar.to_kline(&self.exchange, symbol, *candle_start));
+                    }
                 }
 
-                let group = &ticks[start_idx..end_idx];
-                let kline = Self::build_kline(symbol, interval, group, candle_start);
-                result.push(kline);
+                self.write_minutes(symbol, state, built, last_closed, &mut closed, &mut amended);
 
-                // Remove used ticks
-                let remaining = ticks.split_off(end_idx);
-                *ticks = remaining;
+                // Candles before the watermark are final and dropped; the newest written
+                // candle stays as the forward-fill source
+                let original_len = state.bars.len();
+                state.bars.retain(|start, _| *start >= final_start);
+                let newest = state.written.last_key_value().map(|(s, _)| *s);
+                state.written.retain(|s, _| *s >= final_start || Some(*s) == newest);
+                if self.debug && state.bars.len() != original_len {
+                    debug!("Cleaned {} final candles from {}", original_len - state.bars.len(), symbol);
+                }
             }
-            self.cleanup_old_ticks(symbol, interval_map, now).await;
+            shard.retain(|_, state| !state.bars.is_empty() || self.forward_fill);
         }
-        result
+
+        if !amended.is_empty() {
+            debug!("Amending {} {} candles with late ticks", amended.len(), BASE_INTERVAL);
+        }
+        self.roll_up(closed, amended, now, watermark)
     }
 
-    /// Clean up ticks older than retention for a symbol
-    async fn cleanup_old_ticks(
+    // Helper: walk the minutes up to `last_closed` in order so each synthetic candle carries the
+    // close before it; an amended close also amends the synthetic candles that followed it
+    fn write_minutes(
         &self,
         symbol: &str,
-        interval_map: &mut HashMap<String, Vec<TickData>>,
-        now: i64,
+        state: &mut SymbolState,
+        mut built: BTreeMap<i64, SymbolKlineData>,
+        last_closed: i64,
+        closed: &mut Vec<SymbolKlineData>,
+        amended: &mut Vec<SymbolKlineData>,
     ) {
-        let retention = self.max_interval_ms * 3;
-        let oldest_valid = now - retention;
-
-        for (interval, ticks) in interval_map.iter_mut() {
-            let original_len = ticks.len();
-            ticks.retain(|t| t.time >= oldest_valid);
-            if self.debug && ticks.len() != original_len {
-                debug!(
-                    "Cleaned {} old ticks from {} [{}]",
-                    original_len - ticks.len(),
-                    symbol,
-                    interval
-                );
-            }
-        }
-    }
+        let filled_until = state.written.last_key_value().map(|(s, _)| *s);
+        let walk_from = match (built.first_key_value().map(|(s, _)| *s), filled_until) {
+            (Some(first), Some(filled)) if self.forward_fill => first.min(filled + BASE_INTERVAL_MS),
+            (Some(first), _) => first,
+            (None, Some(filled)) if self.forward_fill => filled + BASE_INTERVAL_MS,
+            (None, _) => return,
+        };
+        let mut prev_close = state.written.range(..walk_from).next_back().map(|(_, w)| w.close);
+        let mut prev_amended = false;
 
-    /// Build OHLC candle from a slice of TickData (already sorted)
-    fn build_kline(
-        symbol: &str,
-        interval: &str,
-        group: &[TickData],
-        open_time: i64,
-    ) -> SymbolKlineData {
-        let open = group.first().unwrap().price;
-        let close = group.last().unwrap().price;
-        let mut high = open;
-        let mut low = open;
-        let mut volume_sum = 0.0;
-
-        for tick in group {
-            if tick.price > high {
-                high = tick.price;
+        let mut candle_start = walk_from;
+        while candle_start <= last_closed {
+            let previous = state.written.get(&candle_start).copied();
+            let kline = match (built.remove(&candle_start), previous) {
+                (Some(kline), _) => Some(kline),
+                // A synthetic candle whose source close changed
+                (None, Some(w)) if w.synthetic && prev_amended && prev_close != Some(w.close) => {
+                    prev_close.map(|close| self.synthetic_kline(symbol, candle_start, close))
+                }
+                (None, Some(_)) => None,
+                (None, None) if self.forward_fill => prev_close.map(|close| self.synthetic_kline(symbol, candle_start, close)),
+                (None, None) => None,
+            };
+
+            prev_amended = false;
+            match kline {
+                Some(kline) => {
+                    state.written.insert(candle_start, Written { close: kline.close, synthetic: kline.is_synthetic });
+                    prev_close = Some(kline.close);
+                    if previous.is_some() {
+                        prev_amended = true;
+                        amended.push(kline);
+                    } else {
+                        closed.push(kline);
+                    }
+                }
+                None => prev_close = previous.map(|w| w.close).or(prev_close),
             }
-            if tick.price < low {
-                low = tick.price;
+
+            // Without forward fill only minutes with ticks matter
+            if !self.forward_fill {
+                match built.first_key_value() {
+                    Some((next, _)) => {
+                        candle_start = *next;
+                        continue;
+                    }
+                    None => break,
+                }
             }
-            volume_sum += tick.volume;
+            candle_start += BASE_INTERVAL_MS;
         }
+    }
 
+    // Helper: forward-filled candle for a minute without ticks
+    fn synthetic_kline(&self, symbol: &str, open_time: i64, close: f64) -> SymbolKlineData {
         SymbolKlineData {
+            exchange: self.exchange.clone(),
             symbol: symbol.to_string(),
-            interval: interval.to_string(),
-            open,
+            interval: BASE_INTERVAL.to_string(),
+            open: close,
+            high: close,
+            low: close,
             close,
-            high,
-            low,
+            open_time: Utc.timestamp_millis_opt(open_time).single().unwrap_or_default(),
+            volume: 0.0,
+            volume_24h: None,
+            trade_count: 0,
+            buy_volume: 0.0,
+            sell_volume: 0.0,
+            is_synthetic: true,
+        }
+    }
+
+    /// Feed base candles (oldest first per symbol) into the higher intervals. Buckets still
+    /// open at `now_ms` are written once complete, and stay amendable until `final_ms` passes them.
+    /// Base candles are part of the result when BASE_INTERVAL is configured.
+    pub fn roll_up(
+        &self,
+        closed: Vec<SymbolKlineData>,
+        amended: Vec<SymbolKlineData>,
+        now_ms: i64,
+        final_ms: i64,
+    ) -> FlushedKlines {
+        let mut rollup = lock(&self.rollup);
+        for candle in closed.iter().chain(amended.iter()) {
+            rollup.add(candle, BASE_INTERVAL_MS);
+        }
+        let mut result = rollup.take(now_ms, final_ms);
+
+        if self.emit_base {
+            result.closed.splice(0..0, closed);
+            result.amended.splice(0..0, amended);
+        }
+        result
+    }
+}
+
+impl MinuteBar {
+    fn new(tick: &TickData) -> Self {
+        Self {
+            open: tick.price,
+            open_tick: tick.time,
+            high: tick.price,
+            low: tick.price,
+            close: tick.price,
+            close_tick: tick.time,
+            volume: 0.0,
+            buy_volume: 0.0,
+            sell_volume: 0.0,
+            count: 0,
+            volume_24h: None,
+            volume_24h_tick: tick.time,
+            changed: true,
+        }
+    }
+
+    fn add(&mut self, tick: &TickData) {
+        if tick.time < self.open_tick {
+            self.open = tick.price;
+            self.open_tick = tick.time;
+        }
+        if tick.time >= self.close_tick {
+            self.close = tick.price;
+            self.close_tick = tick.time;
+        }
+        self.high = self.high.max(tick.price);
+        self.low = self.low.min(tick.price);
+        self.volume += tick.volume;
+        self.count += tick.count;
+        match tick.side {
+            Some(TradeSide::Buy) => self.buy_volume += tick.volume,
+            Some(TradeSide::Sell) => self.sell_volume += tick.volume,
+            None => {}
+        }
+        if tick.volume_24h.is_some() && tick.time >= self.volume_24h_tick {
+            self.volume_24h = tick.volume_24h;
+            self.volume_24h_tick = tick.time;
+        }
+        self.changed = true;
+    }
+
+    fn to_kline(self, exchange: &str, symbol: &str, open_time: i64) -> SymbolKlineData {
+        SymbolKlineData {
+            exchange: exchange.to_string(),
+            symbol: symbol.to_string(),
+            interval: BASE_INTERVAL.to_string(),
+            open: self.open,
+            close: self.close,
+            high: self.high,
+            low: self.low,
             open_time: Utc.timestamp_millis_opt(open_time)
                 .single()
                 .unwrap_or_else(|| panic!("Invalid timestamp: {}", open_time)),
-            volume: volume_sum,
-            trade_count: group.len() as i64,
-
+            volume: self.volume,
+            volume_24h: self.volume_24h,
+            trade_count: self.count,
+            buy_volume: self.buy_volume,
+            sell_volume: self.sell_volume,
+            is_synthetic: false,
         }
     }
 }
 
+// Helper: lock that survives a panicked holder, the state is plain data and stays usable
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Helper: state of `symbol`, created on its first tick
+fn state_mut<'a>(shard: &'a mut HashMap<String, SymbolState>, symbol: &str) -> &'a mut SymbolState {
+    if !shard.contains_key(symbol) {
+        shard.insert(symbol.to_string(), SymbolState::default());
+    }
+    shard.get_mut(symbol).expect("symbol state just inserted")
+}
+
+// Helper: fold a tick into the running candle of `minute`
+fn add_tick(state: &mut SymbolState, minute: i64, tick: &TickData) {
+    state.bars.entry(minute).or_insert_with(|| MinuteBar::new(tick)).add(tick);
+}
+
+// Helper: volume traded between two readings of a rolling 24h volume counter
+fn volume_delta(prev: Option<f64>, current: f64) -> f64 {
+    match prev {
+        // First reading only establishes the baseline
+        None => 0.0,
+        Some(prev) if current >= prev => current - prev,
+        // Counter restarted, everything since the reset is new volume
+        Some(prev) if current < prev * VOLUME_RESET_RATIO => current,
+        // More volume rolled out of the window than was traded, nothing measurable
+        Some(_) => 0.0,
+    }
+}
+
 // Helper: current time in milliseconds since epoch
 fn current_millis() -> i64 {
     let dur = SystemTime::now()
@@ -187,3 +565,153 @@ fn current_millis() -> i64 {
         .expect("Time went backwards");
     dur.as_millis() as i64
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(timestamp: i64, price: f64) -> TradeData {
+        TradeData {
+            symbol: "BTCUSDT".to_string(),
+            price,
+            qty: 1.0,
+            side: TradeSide::Buy,
+            trade_id: timestamp.to_string(),
+            timestamp,
+            count: 1,
+        }
+    }
+
+    #[test]
+    fn late_ticks_amend_and_ticks_past_the_watermark_are_dropped() {
+        let agg = KlineAggregator::new("binance", &["1m".to_string()], 180_000, false, false);
+        let now = current_millis();
+        let minute = now - now.rem_euclid(BASE_INTERVAL_MS) - 2 * BASE_INTERVAL_MS;
+
+        agg.add_trade(&trade(minute + 1_000, 100.0));
+        let first = agg.extract_ohlc();
+        assert_eq!(first.closed.len(), 1);
+        assert!(first.amended.is_empty());
+
+        // Same minute again, still within the allowed lateness
+        agg.add_trade(&trade(minute + 2_000, 110.0));
+        let second = agg.extract_ohlc();
+        assert!(second.closed.is_empty());
+        assert_eq!(second.amended.len(), 1);
+        assert_eq!((second.amended[0].high, second.amended[0].trade_count), (110.0, 2));
+
+        agg.add_trade(&trade(now - 600_000, 90.0));
+        assert_eq!(agg.dropped_late(), 1);
+        assert!(agg.extract_ohlc().amended.is_empty());
+    }
+
+    #[test]
+    fn volume_deltas_follow_the_24h_counter() {
+        assert_eq!(volume_delta(None, 1_000.0), 0.0);
+        assert_eq!(volume_delta(Some(1_000.0), 1_250.0), 250.0);
+        // Older volume rolling out of the window outweighs what was traded
+        assert_eq!(volume_delta(Some(1_000.0), 900.0), 0.0);
+        // Below half of the last reading the counter was reset
+        assert_eq!(volume_delta(Some(1_000.0), 300.0), 300.0);
+    }
+
+    #[test]
+    fn ticker_snapshots_add_their_volume_deltas_to_the_candle() {
+        let agg = KlineAggregator::new("binance", &["1m".to_string()], 180_000, false, false);
+        let now = current_millis();
+        let minute = now - now.rem_euclid(BASE_INTERVAL_MS) - 2 * BASE_INTERVAL_MS;
+
+        agg.add_price("BTCUSDT", 100.0, 1_000.0, Some(minute + 1_000));
+        agg.add_price("BTCUSDT", 101.0, 1_100.0, Some(minute + 2_000)); // +100
+        agg.add_price("BTCUSDT", 102.0, 1_050.0, Some(minute + 3_000)); // roll-off, +0
+        agg.add_price("BTCUSDT", 103.0, 200.0, Some(minute + 4_000)); // reset, +200
+
+        let closed = agg.extract_ohlc().closed;
+        assert_eq!(closed.len(), 1);
+        assert_eq!((closed[0].volume, closed[0].volume_24h), (300.0, Some(200.0)));
+    }
+
+    #[test]
+    fn a_tick_from_the_future_does_not_move_the_watermark() {
+        let agg = KlineAggregator::new("binance", &["1m".to_string()], 180_000, false, false);
+        let now = current_millis();
+
+        agg.add_trade(&trade(now + 3_600_000, 100.0));
+        assert_eq!((agg.dropped_future(), agg.dropped_late()), (1, 0));
+
+        // Ticks on the real clock, including a late one within the allowed lateness, still count
+        agg.add_trade(&trade(now, 101.0));
+        agg.add_trade(&trade(now - 120_000, 99.0));
+        assert_eq!((agg.dropped_future(), agg.dropped_late()), (1, 0));
+        assert!(agg.watermark(now) <= now - 180_000 + MAX_CLOCK_SKEW_MS);
+    }
+
+    #[test]
+    fn forward_fill_carries_the_last_close_and_follows_amendments() {
+        let agg = KlineAggregator::new("binance", &["1m".to_string()], 600_000, true, false);
+        let now = current_millis();
+        let minute = now - now.rem_euclid(BASE_INTERVAL_MS) - 3 * BASE_INTERVAL_MS;
+
+        agg.add_trade(&trade(minute + 1_000, 100.0));
+        let first = agg.extract_ohlc();
+        assert_eq!(first.closed.len(), 3);
+        assert!(!first.closed[0].is_synthetic);
+        for filled in &first.closed[1..] {
+            assert!(filled.is_synthetic);
+            assert_eq!((filled.open, filled.close, filled.volume), (100.0, 100.0, 0.0));
+        }
+
+        // A late trade turns the first filled minute real and moves the one after it
+        agg.add_trade(&trade(minute + BASE_INTERVAL_MS + 1_000, 120.0));
+        let second = agg.extract_ohlc();
+        assert_eq!(second.amended.len(), 2);
+        assert!(!second.amended[0].is_synthetic);
+        assert!(second.amended[1].is_synthetic);
+        assert_eq!(second.amended[1].close, 120.0);
+    }
+
+    #[test]
+    fn out_of_order_trades_land_by_event_time() {
+        let agg = KlineAggregator::new("binance", &["1m".to_string()], 180_000, false, false);
+        let now = current_millis();
+        let minute = now - now.rem_euclid(BASE_INTERVAL_MS) - 2 * BASE_INTERVAL_MS;
+
+        for (offset, price) in [(30_000, 105.0), (50_000, 101.0), (10_000, 100.0), (40_000, 97.0)] {
+            agg.add_trade(&trade(minute + offset, price));
+        }
+        let candle = &agg.extract_ohlc().closed[0];
+        assert_eq!((candle.open, candle.high, candle.low, candle.close), (100.0, 105.0, 97.0, 101.0));
+        assert_eq!((candle.trade_count, candle.buy_volume), (4, 4.0));
+    }
+
+    #[test]
+    fn restored_snapshot_continues_the_running_candle() {
+        let intervals = ["1m".to_string()];
+        let agg = KlineAggregator::new("binance", &intervals, 180_000, false, false);
+        let now = current_millis();
+        let minute = now - now.rem_euclid(BASE_INTERVAL_MS) - BASE_INTERVAL_MS;
+        agg.add_trade(&trade(minute + 1_000, 100.0));
+
+        let saved = serde_json::to_vec(&agg.snapshot()).unwrap();
+        let restarted = KlineAggregator::new("binance", &intervals, 180_000, false, false);
+        assert!(restarted.restore(serde_json::from_slice(&saved).unwrap()));
+        assert!(!KlineAggregator::new("okx", &intervals, 180_000, false, false).restore(serde_json::from_slice(&saved).unwrap()));
+
+        restarted.add_trade(&trade(minute + 2_000, 90.0));
+        let candle = &restarted.extract_ohlc().closed[0];
+        assert_eq!((candle.open, candle.close, candle.trade_count), (100.0, 90.0, 2));
+    }
+
+    #[test]
+    fn drain_closes_the_current_minute_and_open_buckets() {
+        let agg = KlineAggregator::new("binance", &["1m".to_string(), "1h".to_string()], 10_000, false, false);
+        let now = current_millis();
+        agg.add_trade(&trade(now, 100.0));
+        assert!(agg.extract_ohlc().closed.is_empty());
+
+        let drained = agg.drain().closed;
+        let intervals: Vec<&str> = drained.iter().map(|k| k.interval.as_str()).collect();
+        assert_eq!(intervals, ["1m", "1h"]);
+        assert!(agg.drain().closed.is_empty());
+    }
+}
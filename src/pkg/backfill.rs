@@ -0,0 +1,202 @@
+use crate::pkg::aggregator::intervals::interval_to_ms;
+use crate::pkg::exchanges::exchange::ExchangeApi;
+use crate::pkg::exchanges::exchange_client::kline_open_time;
+use crate::pkg::sink::UpdatePolicy;
+use crate::pkg::storage::StorageBackend;
+use crate::pkg::store::KlineRange;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+pub struct BackfillRequest {
+    pub exchange: String,
+    pub instance: String,
+    pub symbols: Vec<String>,
+    pub interval: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub checkpoint_path: PathBuf,
+    /// What happens to candles already stored
+    pub update: UpdatePolicy,
+}
+
+/// Open time (ms) of the last candle stored per `exchange:symbol:interval`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BackfillCheckpoint {
+    completed: HashMap<String, i64>,
+}
+
+impl BackfillCheckpoint {
+    pub async fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path).await {
+            Ok(data) => Ok(serde_json::from_str(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write via a temp file so a crash mid-write never leaves a truncated checkpoint
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?).await?;
+        fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+
+    fn key(exchange: &str, symbol: &str, interval: &str) -> String {
+        format!("{}:{}:{}", exchange, symbol, interval)
+    }
+}
+
+/// Page through the exchange's kline endpoint for every symbol and store the result.
+/// Requests go through the adapter's rate-limited client one page at a time.
+pub async fn run_backfill(api: &dyn ExchangeApi, storage: &StorageBackend, req: &BackfillRequest) -> Result<()> {
+    let interval_ms = interval_to_ms(&req.interval).ok_or_else(|| anyhow!("unsupported interval: {}", req.interval))?;
+
+    // Align to bucket boundaries and never ask for the candle that is still open
+    let start_ms = req.start.timestamp_millis() - req.start.timestamp_millis().rem_euclid(interval_ms);
+    let last_closed_ms = {
+        let now = Utc::now().timestamp_millis();
+        now - now.rem_euclid(interval_ms) - interval_ms
+    };
+    let end_ms = req.end.timestamp_millis().min(last_closed_ms);
+    if end_ms < start_ms {
+        return Err(anyhow!("backfill range is empty: {} > {}", req.start, req.end));
+    }
+
+    let mut checkpoint = BackfillCheckpoint::load(&req.checkpoint_path).await?;
+    let mut failed = Vec::new();
+    let mut written: Option<(i64, i64)> = None; // open times of the candles written, for the aggregates
+
+    for symbol in &req.symbols {
+        let key = BackfillCheckpoint::key(&req.exchange, symbol, &req.interval);
+
+        // Resume after the last stored candle if the checkpoint falls inside the range
+        let cursor = match checkpoint.completed.get(&key) {
+            Some(&last) if last >= start_ms => last + interval_ms,
+            _ => start_ms,
+        };
+        if cursor > end_ms {
+            info!("⏭️ {} [{}] already backfilled", symbol, req.interval);
+            continue;
+        }
+
+        info!(
+            "⏪ Backfilling {} [{}] from {} to {}",
+            symbol,
+            req.interval,
+            kline_open_time(cursor)?,
+            kline_open_time(end_ms)?
+        );
+
+        let mut saved = 0;
+        let result: Result<()> = async {
+            for (page_start, page_end) in page_ranges(cursor, end_ms, interval_ms, api.klines_page_limit()) {
+                // Ignored writes wouldn't change stored candles, so pages already complete aren't refetched
+                if req.update == UpdatePolicy::Ignore
+                    && page_stored(storage, req, symbol, page_start, page_end, interval_ms).await?
+                {
+                    checkpoint.completed.insert(key.clone(), page_end);
+                    checkpoint.save(&req.checkpoint_path).await?;
+                    continue;
+                }
+
+                let klines = api
+                    .get_klines(symbol, &req.interval, kline_open_time(page_start)?, kline_open_time(page_end)?)
+                    .await?;
+
+                if !klines.is_empty() {
+                    storage.write(req.update, &klines, &req.instance).await?;
+                    saved += klines.len();
+                    written = Some(written.map_or((page_start, page_end), |(s, e)| (s.min(page_start), e.max(page_end))));
+                }
+
+                checkpoint.completed.insert(key.clone(), page_end);
+                checkpoint.save(&req.checkpoint_path).await?;
+            }
+            Ok(())
+        }
+        .await;
+
+        match result {
+            Ok(()) => info!("✅ Backfilled {} klines for {} [{}]", saved, symbol, req.interval),
+            Err(e) => {
+                error!("❌ Backfill of {} stopped after {} klines: {:?}", symbol, saved, e);
+                failed.push(symbol.clone());
+            }
+        }
+    }
+
+    // Backfilled history is older than the aggregates' refresh policy looks back
+    if let Some((first, last)) = written
+        && let Err(e) = storage.refresh_aggregates(&req.interval, kline_open_time(first)?, kline_open_time(last)?).await
+    {
+        error!("❌ Refreshing continuous aggregates after the backfill failed, run `refresh-aggregates`: {:?}", e);
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("backfill incomplete for {} symbols, rerun to resume: {:?}", failed.len(), failed))
+    }
+}
+
+// Helper: whether storage already has every candle of the page
+async fn page_stored(
+    storage: &StorageBackend,
+    req: &BackfillRequest,
+    symbol: &str,
+    page_start: i64,
+    page_end: i64,
+    interval_ms: i64,
+) -> Result<bool> {
+    let range = KlineRange {
+        exchange: req.exchange.clone(),
+        symbol: symbol.to_string(),
+        interval: req.interval.clone(),
+        start: kline_open_time(page_start)?,
+        end: kline_open_time(page_end)?,
+    };
+    let expected = ((page_end - page_start) / interval_ms + 1) as usize;
+    Ok(storage.store().open_times(&range).await?.len() >= expected)
+}
+
+/// Split [start_ms, end_ms] (candle open times) into inclusive ranges of at most `page_limit` candles
+pub fn page_ranges(start_ms: i64, end_ms: i64, interval_ms: i64, page_limit: usize) -> Vec<(i64, i64)> {
+    let page_span_ms = (page_limit.max(1) as i64 - 1) * interval_ms;
+    let mut ranges = Vec::new();
+    let mut cursor = start_ms;
+    while cursor <= end_ms {
+        let page_end = (cursor + page_span_ms).min(end_ms);
+        ranges.push((cursor, page_end));
+        cursor = page_end + interval_ms;
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: i64 = 60_000;
+
+    #[test]
+    fn pages_cover_the_range_without_overlap() {
+        // Shorter than one page
+        assert_eq!(page_ranges(0, 4 * MINUTE, MINUTE, 10), vec![(0, 4 * MINUTE)]);
+
+        // Exactly two pages of 10 candles, no empty page after them
+        assert_eq!(
+            page_ranges(0, 19 * MINUTE, MINUTE, 10),
+            vec![(0, 9 * MINUTE), (10 * MINUTE, 19 * MINUTE)]
+        );
+
+        // A single candle, and nothing for an empty range
+        assert_eq!(page_ranges(5 * MINUTE, 5 * MINUTE, MINUTE, 10), vec![(5 * MINUTE, 5 * MINUTE)]);
+        assert!(page_ranges(5 * MINUTE, 4 * MINUTE, MINUTE, 10).is_empty());
+    }
+}
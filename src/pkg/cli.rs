@@ -0,0 +1,139 @@
+use chrono::{DateTime, Utc};
+use crate::pkg::sink::UpdatePolicy;
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(name = "TickAggregator", version, about = "Kline (OHLC) collector for crypto exchanges")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the collector (default when no command is given)
+    Run,
+    /// Page historical klines from the exchange into storage
+    Backfill(BackfillArgs),
+    /// Look for missing candles in storage and optionally refetch them
+    Gaps(GapsArgs),
+    /// Write stored candles as CSV
+    Export(ExportArgs),
+    /// List the exchange's contracts with their trading metadata
+    Instruments(InstrumentsArgs),
+    /// Apply pending Postgres schema migrations
+    Migrate(MigrateArgs),
+    /// Recompute the TimescaleDB continuous aggregates over a time range
+    RefreshAggregates(RefreshAggregatesArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct BackfillArgs {
+    /// Exchange to pull from, defaults to `exchange` in appsettings.yaml
+    #[arg(long)]
+    pub exchange: Option<String>,
+    /// Comma separated symbols, defaults to the configured symbol list
+    #[arg(long, value_delimiter = ',')]
+    pub symbols: Vec<String>,
+    /// Candle interval
+    #[arg(long, default_value = "1m")]
+    pub interval: String,
+    /// First candle open time, RFC 3339 (e.g. 2025-08-01T00:00:00Z)
+    #[arg(long)]
+    pub start: DateTime<Utc>,
+    /// Last candle open time, RFC 3339; defaults to the last closed candle
+    #[arg(long)]
+    pub end: Option<DateTime<Utc>>,
+    /// Progress file used to resume an interrupted run
+    #[arg(long, default_value = "backfill_checkpoint.json")]
+    pub checkpoint: PathBuf,
+    /// Candles already stored: keep them, overwrite them, or merge into them
+    #[arg(long, value_enum, default_value_t = UpdatePolicy::Ignore)]
+    pub update: UpdatePolicy,
+}
+
+#[derive(Debug, Args)]
+pub struct GapsArgs {
+    /// Exchange to repair from, defaults to `exchange` in appsettings.yaml
+    #[arg(long)]
+    pub exchange: Option<String>,
+    /// Comma separated symbols, defaults to the configured symbol list
+    #[arg(long, value_delimiter = ',')]
+    pub symbols: Vec<String>,
+    /// Candle interval
+    #[arg(long, default_value = "1m")]
+    pub interval: String,
+    /// First candle open time, RFC 3339; defaults to `GapScan.LookbackMinutes` ago
+    #[arg(long)]
+    pub start: Option<DateTime<Utc>>,
+    /// Last candle open time, RFC 3339; defaults to the last settled candle
+    #[arg(long)]
+    pub end: Option<DateTime<Utc>>,
+    /// Fetch missing candles from the exchange instead of only reporting them
+    #[arg(long)]
+    pub repair: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// Exchange the candles came from, defaults to `exchange` in appsettings.yaml
+    #[arg(long)]
+    pub exchange: Option<String>,
+    /// Comma separated symbols, defaults to the configured symbol list
+    #[arg(long, value_delimiter = ',')]
+    pub symbols: Vec<String>,
+    /// Candle interval
+    #[arg(long, default_value = "1m")]
+    pub interval: String,
+    /// First candle open time, RFC 3339
+    #[arg(long)]
+    pub start: DateTime<Utc>,
+    /// Last candle open time, RFC 3339; defaults to now
+    #[arg(long)]
+    pub end: Option<DateTime<Utc>>,
+    /// File to write, defaults to stdout
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+    /// One row per open time with each symbol's close instead of one row per candle
+    #[arg(long)]
+    pub aligned: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct InstrumentsArgs {
+    /// Exchange to query, defaults to the first configured exchange
+    #[arg(long)]
+    pub exchange: Option<String>,
+    /// Only show instruments quoted in this asset (e.g. USDT)
+    #[arg(long)]
+    pub quote: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct MigrateArgs {
+    #[command(subcommand)]
+    pub action: Option<MigrateAction>,
+    /// Print the SQL of pending migrations instead of applying them
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MigrateAction {
+    /// List every migration with its state and when it was applied
+    Status,
+}
+
+#[derive(Debug, Args)]
+pub struct RefreshAggregatesArgs {
+    /// Start of the range, RFC 3339
+    #[arg(long)]
+    pub start: DateTime<Utc>,
+    /// End of the range (exclusive), RFC 3339; defaults to now
+    #[arg(long)]
+    pub end: Option<DateTime<Utc>>,
+    /// Comma separated aggregate intervals, defaults to `database.timescale.continuousAggregates`
+    #[arg(long, value_delimiter = ',')]
+    pub intervals: Vec<String>,
+}
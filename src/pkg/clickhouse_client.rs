@@ -1,29 +1,132 @@
-use crate::pkg::dbcontext::entities::{SymbolKlineData, clean_symbol};
+use crate::pkg::config::ExchangeSettings;
+use crate::pkg::dbcontext::entities::{SymbolKlineData, canonical_symbol};
+use crate::pkg::sink::{KlineSink, UpdatePolicy};
+use crate::pkg::store::{KlinePage, KlineRange, KlineStore};
 use anyhow::Result;
+use async_trait::async_trait;
 use chrono::DateTime;
 use chrono::Utc;
-use clickhouse::Client;
+use clickhouse::{Client, Compression};
 use clickhouse_derive::Row;
-use log::info;
-use serde::Serialize;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Clone)]
 pub struct ClickHouseClient {
     client: Arc<Client>,
+    max_rows: u64,     // rows per INSERT
+    max_bytes: u64,    // uncompressed bytes per INSERT
+    retries: u32,      // retries of a failed INSERT
+    backoff: Duration, // wait before the first retry, doubled per retry
 }
 
-#[derive(Debug, Serialize, Row)]
-struct KlineRow {
-    symbol: String,   // matches ClickHouse `symbol String`
+// Row versions, `ReplacingMergeTree` keeps the highest per key. Overwrites and merges use the
+// write time in microseconds, so the latest one wins. Ignoring writes count down from here by the
+// millisecond, below every overwrite, so a row they would replace outlives them. The base is above
+// every millisecond timestamp for the next 35,000 years and below microsecond ones since 2005.
+const IGNORE_VERSION_BASE: u64 = 1 << 50;
+
+/// One `kline_data` row, sent as RowBinary by column name
+#[derive(Debug, Serialize, Deserialize, Row)]
+pub struct KlineRow {
+    exchange: String, // matches `exchange LowCardinality(String)`
+    instance: String, // matches `instance LowCardinality(String)`, collector that wrote the row
+    symbol: String,   // matches ClickHouse `symbol String`, canonical id
+    native_symbol: String, // matches `native_symbol String`
     interval: String, // matches `interval String`
     open: f64,        // matches `open Float64`
     high: f64,        // matches `high Float64`
     low: f64,         // matches `low Float64`
     close: f64,       // matches `close Float64`
     volume: f64,      // matches `volume Float64`
-    timestamp: DateTime<Utc>,
+    volume_24h: Option<f64>, // matches `volume_24h Nullable(Float64)`
+    #[serde(with = "clickhouse::serde::chrono::datetime64::millis")]
+    timestamp: DateTime<Utc>, // matches `timestamp DateTime64(3, 'UTC')`
     trade_count: i64, // matches `trade_count Int64`
+    buy_volume: f64,  // matches `buy_volume Float64`
+    sell_volume: f64, // matches `sell_volume Float64`
+    is_synthetic: bool, // matches `is_synthetic Bool`
+    version: u64,       // matches `version UInt64`, see IGNORE_VERSION_BASE
+}
+
+impl KlineRow {
+    /// Validate a candle and convert it into a row written by `instance` with `version`
+    pub fn from_kline(k: &SymbolKlineData, instance: &str, version: u64) -> Result<Self> {
+        if k.symbol.is_empty() || k.interval.is_empty() {
+            return Err(anyhow::anyhow!("Invalid data: empty symbol or interval"));
+        }
+        if !k.open.is_finite()
+            || !k.high.is_finite()
+            || !k.low.is_finite()
+            || !k.close.is_finite()
+            || !k.volume.is_finite()
+        {
+            return Err(anyhow::anyhow!(
+                "Invalid data: NaN or infinite values detected"
+            ));
+        }
+        Ok(KlineRow {
+            exchange: k.exchange.clone(),
+            instance: instance.to_string(),
+            symbol: canonical_symbol(&k.exchange, &k.symbol),
+            native_symbol: k.symbol.clone(),
+            interval: k.interval.clone(),
+            open: k.open,
+            high: k.high,
+            low: k.low,
+            close: k.close,
+            volume: k.volume,
+            volume_24h: k.volume_24h,
+            timestamp: k.open_time,
+            trade_count: k.trade_count, // direct i64
+            buy_volume: k.buy_volume,
+            sell_volume: k.sell_volume,
+            is_synthetic: k.is_synthetic,
+            version,
+        })
+    }
+
+    // Helper: back to a candle, for merging with a new one
+    fn into_kline(self) -> SymbolKlineData {
+        SymbolKlineData {
+            exchange: self.exchange,
+            symbol: self.native_symbol,
+            interval: self.interval,
+            open: self.open,
+            high: self.high,
+            low: self.low,
+            close: self.close,
+            open_time: self.timestamp,
+            volume: self.volume,
+            volume_24h: self.volume_24h,
+            trade_count: self.trade_count,
+            buy_volume: self.buy_volume,
+            sell_volume: self.sell_volume,
+            is_synthetic: self.is_synthetic,
+        }
+    }
+}
+
+// Helper: the base the schema before exchanges stored for a native symbol, as its `clean_symbol` did
+fn legacy_base(native: &str) -> String {
+    let symbol = native.to_uppercase();
+    let suffixes = ["-USDT-SWAP", "-USDT", "-USD-SWAP", "-USD", "-PERP", "-FUTURE", "-SWAP"];
+    let quotes = ["USDT", "USD", "BUSD", "USDC", "TUSD", "DAI", "USDT_UMCBL"];
+    match suffixes.iter().chain(quotes.iter()).find(|s| symbol.ends_with(*s)) {
+        Some(suffix) => symbol.trim_end_matches(suffix).to_string(),
+        None => symbol,
+    }
+}
+
+/// Version a row written under `policy` at `now` gets
+fn write_version(policy: UpdatePolicy, now: DateTime<Utc>) -> u64 {
+    match policy {
+        UpdatePolicy::Ignore => IGNORE_VERSION_BASE - now.timestamp_millis().max(0) as u64,
+        UpdatePolicy::Overwrite | UpdatePolicy::Merge => now.timestamp_micros().max(0) as u64,
+    }
 }
 
 impl ClickHouseClient {
@@ -38,7 +141,8 @@ impl ClickHouseClient {
             .with_url(url)
             .with_user(&config.user)
             .with_password(&config.password)
-            .with_database(&config.database);
+            .with_database(&config.database)
+            .with_compression(if config.lz4 { Compression::Lz4 } else { Compression::None });
 
         info!("🧪 Testing connection...");
         match client.query("SELECT 1").fetch_one::<u8>().await {
@@ -49,6 +153,10 @@ impl ClickHouseClient {
                 );
                 Ok(Self {
                     client: Arc::new(client),
+                    max_rows: config.max_rows_per_insert.max(1),
+                    max_bytes: config.max_bytes_per_insert.max(1),
+                    retries: config.insert_retries,
+                    backoff: Duration::from_millis(config.retry_backoff_millis),
                 })
             }
             Err(e) => {
@@ -62,155 +170,240 @@ impl ClickHouseClient {
         }
     }
 
-    /// Create kline table if not exists
-    pub async fn create_kline_table(&self) -> Result<()> {
+    /// Create the kline table if it doesn't exist, or bring an older one to the current schema.
+    /// Rows are keyed on (exchange, symbol, interval, timestamp) and the row with the highest
+    /// `version` replaces the others when parts merge; reads use `FINAL` so they see one row per
+    /// key before that. `legacy` is the exchange section a table from before exchanges were stored
+    /// was collected with; its rows get that exchange.
+    pub async fn create_kline_table(&self, legacy: Option<&ExchangeSettings>) -> Result<()> {
+        match self.table_engine("kline_data").await? {
+            None => {
+                self.client.query(&kline_table_ddl("kline_data")).execute().await?;
+                info!("📊 ClickHouse kline_data table created");
+            }
+            Some(engine) if engine == "ReplacingMergeTree" && self.has_column("kline_data", "version").await? => {
+                info!("📊 ClickHouse kline_data table verified");
+            }
+            Some(engine) => self.migrate_kline_table(&engine, legacy).await?,
+        }
+        Ok(())
+    }
+
+    // Helper: whether a table in the current database has a column
+    async fn has_column(&self, table: &str, column: &str) -> Result<bool> {
+        let count: u64 = self
+            .client
+            .query("SELECT count() FROM system.columns WHERE database = currentDatabase() AND table = ? AND name = ?")
+            .bind(table)
+            .bind(column)
+            .fetch_one()
+            .await?;
+        Ok(count > 0)
+    }
+
+    // Helper: engine of a table in the current database, `None` if there is no such table
+    async fn table_engine(&self, table: &str) -> Result<Option<String>> {
+        Ok(self
+            .client
+            .query("SELECT engine FROM system.tables WHERE database = currentDatabase() AND name = ?")
+            .bind(table)
+            .fetch_optional::<String>()
+            .await?)
+    }
+
+    // Helper: copy a table from before the versioned replacing schema (plain `MergeTree`, or
+    // `ReplacingMergeTree` without `version`) into a new table and swap them. Copied rows get
+    // IGNORE_VERSION_BASE, so later saves leave them alone and overwrites replace them.
+    // Rows from before exchanges were stored hold the base `clean_symbol` cut the native symbol
+    // down to (`BTC` for `BTCUSDT`). They get the exchange and instance of `legacy`, and the native
+    // symbol of `legacy` that cleans to their base, with its canonical id as symbol. Bases no
+    // configured symbol cleans to, or every base without `legacy`, keep the stored symbol and
+    // stay unreadable by native symbol.
+    // The old table is kept as `kline_data_backup_<unix time>` until it is dropped by hand.
+    async fn migrate_kline_table(&self, engine: &str, legacy: Option<&ExchangeSettings>) -> Result<()> {
+        info!("🚚 Migrating ClickHouse kline_data from {} to versioned ReplacingMergeTree...", engine);
+
+        // Tables created before exchanges, the buy/sell split, the 24h snapshot, canonical symbols and forward fill
         self.client
             .query(
-                "CREATE TABLE IF NOT EXISTS kline_data (
-                    symbol String,
-                    interval String,
-                    open Float64,
-                    high Float64,
-                    low Float64,
-                    close Float64,
-                    volume Float64,
-                    timestamp DateTime,
-                    trade_count Int64
-                ) ENGINE = MergeTree()
-                PARTITION BY toYYYYMM(timestamp)
-                ORDER BY (symbol, interval, timestamp)",
+                "ALTER TABLE kline_data
+                    ADD COLUMN IF NOT EXISTS exchange LowCardinality(String) DEFAULT '' FIRST,
+                    ADD COLUMN IF NOT EXISTS native_symbol String DEFAULT '' AFTER symbol,
+                    ADD COLUMN IF NOT EXISTS buy_volume Float64 DEFAULT 0,
+                    ADD COLUMN IF NOT EXISTS sell_volume Float64 DEFAULT 0,
+                    ADD COLUMN IF NOT EXISTS volume_24h Nullable(Float64),
+                    ADD COLUMN IF NOT EXISTS is_synthetic Bool DEFAULT false,
+                    ADD COLUMN IF NOT EXISTS instance LowCardinality(String) DEFAULT ''",
             )
             .execute()
             .await?;
 
-        info!("📊 ClickHouse kline_data table created/verified");
-        Ok(())
-    }
+        let bases: Vec<String> = self
+            .client
+            .query("SELECT DISTINCT symbol FROM kline_data WHERE exchange = ''")
+            .fetch_all()
+            .await?;
+        let (exchange, instance) = legacy.map_or((String::new(), String::new()), |l| (l.exchange.to_lowercase(), l.instance.clone()));
+        let natives: HashMap<String, &String> = legacy
+            .map(|l| l.symbols.iter().map(|s| (legacy_base(s), s)).collect())
+            .unwrap_or_default();
+
+        let mut keys = Vec::new();
+        let mut native = Vec::new();
+        let mut canonical = Vec::new();
+        let mut unmapped = 0;
+        for base in bases {
+            match natives.get(&base) {
+                Some(&symbol) => {
+                    canonical.push(canonical_symbol(&exchange, symbol));
+                    native.push(symbol.clone());
+                    keys.push(base);
+                }
+                None => unmapped += 1,
+            }
+        }
+        match legacy {
+            Some(l) if unmapped > 0 => warn!(
+                "⚠️ {} legacy ClickHouse symbols match no symbol of {}, they keep their stored base symbol",
+                unmapped, l.exchange
+            ),
+            None if unmapped > 0 => warn!(
+                "⚠️ {} legacy ClickHouse symbols have no exchange configured, they are copied without one",
+                unmapped
+            ),
+            _ => {}
+        }
 
-    /// Insert batch of Klines
-    async fn insert_klines(&self, klines: &[KlineRow]) -> Result<()> {
-        if klines.is_empty() {
-            info!("📭 No klines to insert into ClickHouse");
-            return Ok(());
+        // transform() can't take empty arrays
+        let (symbol, native_symbol) = if keys.is_empty() {
+            ("symbol", "if(native_symbol = '', symbol, native_symbol)")
+        } else {
+            (
+                "if(exchange = '', transform(symbol, ?, ?, symbol), symbol)",
+                "if(exchange = '', transform(symbol, ?, ?, symbol), if(native_symbol = '', symbol, native_symbol))",
+            )
+        };
+
+        // A copy left over by an interrupted migration is rebuilt
+        self.client.query("DROP TABLE IF EXISTS kline_data_migrating").execute().await?;
+        self.client.query(&kline_table_ddl("kline_data_migrating")).execute().await?;
+        let mut copy = self
+            .client
+            .query(&format!(
+                "INSERT INTO kline_data_migrating
+                    (exchange, instance, symbol, native_symbol, interval, open, high, low, close, volume,
+                     volume_24h, timestamp, trade_count, buy_volume, sell_volume, is_synthetic, version)
+                 SELECT if(exchange = '', ?, exchange), if(exchange = '', ?, instance), {}, {}, interval,
+                     open, high, low, close, volume, volume_24h, toDateTime64(timestamp, 3, 'UTC'), trade_count,
+                     buy_volume, sell_volume, is_synthetic, ?
+                 FROM kline_data",
+                symbol, native_symbol
+            ))
+            .bind(&exchange)
+            .bind(&instance);
+        if !keys.is_empty() {
+            copy = copy.bind(&keys).bind(&canonical).bind(&keys).bind(&native);
         }
+        copy.bind(IGNORE_VERSION_BASE).execute().await?;
+        let backup = format!("kline_data_backup_{}", Utc::now().timestamp());
+        self.client
+            .query(&format!("RENAME TABLE kline_data TO {}, kline_data_migrating TO kline_data", backup))
+            .execute()
+            .await?;
 
         info!(
-            "📝 Starting batch insert of {} klines into ClickHouse",
-            klines.len()
+            "✅ ClickHouse kline_data migrated ({} klines), the old table is kept as {}",
+            self.count_klines().await?,
+            backup
         );
+        Ok(())
+    }
 
-        // Small test batch first
-        let batch_size = std::cmp::min(10, klines.len());
-        let test_batch = &klines[0..batch_size];
+    /// Insert rows with the typed RowBinary inserter, one INSERT per `max_rows_per_insert` rows or
+    /// `max_bytes_per_insert` bytes. A failed INSERT is retried with doubling backoff, starting
+    /// after the rows of the INSERTs that already went through.
+    pub async fn insert_klines(&self, klines: &[KlineRow]) -> Result<()> {
+        if klines.is_empty() {
+            return Ok(());
+        }
 
-        match self.insert_batch_with_retry(test_batch).await {
-            Ok(_) => {
-                info!("✅ Test batch successful, inserting remaining data...");
-                if klines.len() > batch_size {
-                    let remaining = &klines[batch_size..];
-                    self.insert_batch_with_retry(remaining).await?;
+        let mut committed = 0;
+        let mut attempt = 0;
+        loop {
+            let start = committed;
+            match self.insert_rows(&klines[start..], &mut committed).await {
+                Ok(()) => break,
+                Err(e) if attempt < self.retries => {
+                    let wait = self.backoff * 2u32.saturating_pow(attempt);
+                    attempt += 1;
+                    warn!(
+                        "⚠️ ClickHouse insert failed after {}/{} rows, retry {}/{} in {:?}: {}",
+                        committed,
+                        klines.len(),
+                        attempt,
+                        self.retries,
+                        wait,
+                        e
+                    );
+                    tokio::time::sleep(wait).await;
                 }
-                info!(
-                    "💾 Successfully saved {} klines into ClickHouse",
-                    klines.len()
-                );
-                Ok(())
-            }
-            Err(e) => {
-                log::error!("❌ Even small test batch failed: {}", e);
-                if let Some(first_row) = klines.first() {
-                    log::error!("   First row details: {:?}", first_row);
+                Err(e) => {
+                    return Err(anyhow::anyhow!(
+                        "ClickHouse insert failed after {}/{} rows: {}",
+                        committed,
+                        klines.len(),
+                        e
+                    ));
                 }
-                Err(e)
             }
         }
-    }
 
-    /// Insert batch helper with retry
-    async fn insert_batch_with_retry(&self, klines: &[KlineRow]) -> Result<()> {
-        info!("🔧 Attempting to insert {} rows", klines.len());
-
-        // Use raw SQL for all batches to avoid RowBinary size limit
-        let mut query_values = Vec::with_capacity(klines.len());
-        for row in klines {
-            // Escape single quotes in strings just in case
-            let symbol = row.symbol.replace('\'', "''");
-            let interval = row.interval.replace('\'', "''");
-
-            query_values.push(format!(
-                "('{}','{}',{},{},{},{},{},'{}',{})",
-                symbol,
-                interval,
-                row.open,
-                row.high,
-                row.low,
-                row.close,
-                row.volume,
-                row.timestamp.format("%Y-%m-%d %H:%M:%S"),
-                row.trade_count
-            ));
-        }
-
-        let query = format!(
-            "INSERT INTO kline_data (symbol, interval, open, high, low, close, volume, timestamp, trade_count) VALUES {}",
-            query_values.join(",")
-        );
+        info!("💾 Inserted {} klines into ClickHouse", klines.len());
+        Ok(())
+    }
 
-        info!("🧪 Executing SQL INSERT batch with {} rows", klines.len());
-        self.client.query(&query).execute().await?;
-        info!("✅ Batch insert successful");
+    // Helper: one inserter pass over `rows`, adding the rows of every INSERT that ended to `committed`
+    async fn insert_rows(&self, rows: &[KlineRow], committed: &mut usize) -> Result<()> {
+        let mut inserter = self
+            .client
+            .inserter::<KlineRow>("kline_data")?
+            .with_max_rows(self.max_rows)
+            .with_max_bytes(self.max_bytes);
 
+        for row in rows {
+            inserter.write(row)?;
+            *committed += inserter.commit().await?.rows as usize;
+        }
+        *committed += inserter.end().await?.rows as usize;
         Ok(())
     }
 
-    /// Save SymbolKlineData into ClickHouse
-    pub async fn save_symbol_klines(&self, data: &[SymbolKlineData], instance: &str) -> Result<()> {
+    /// Write candles, handling stored ones as `policy` says. Merging reads the stored candles
+    /// first and writes the combination as an overwrite.
+    pub async fn write_symbol_klines(&self, data: &[SymbolKlineData], instance: &str, policy: UpdatePolicy) -> Result<()> {
         if data.is_empty() {
             info!("📭 No klines to insert for instance {}", instance);
             return Ok(());
         }
 
-        // Ensure table exists
-        if let Err(e) = self.create_kline_table().await {
-            log::warn!("⚠️ Failed to ensure table exists: {}", e);
-        }
+        let merged;
+        let data = if policy == UpdatePolicy::Merge {
+            merged = self.merge_with_stored(data).await?;
+            &merged[..]
+        } else {
+            data
+        };
 
-        // Convert & validate
-        let rows: Result<Vec<KlineRow>, _> = data
+        let version = write_version(policy, Utc::now());
+        let validated_rows = data
             .iter()
-            .map(|k| {
-                if k.symbol.is_empty() || k.interval.is_empty() {
-                    return Err(anyhow::anyhow!("Invalid data: empty symbol or interval"));
-                }
-                if !k.open.is_finite()
-                    || !k.high.is_finite()
-                    || !k.low.is_finite()
-                    || !k.close.is_finite()
-                    || !k.volume.is_finite()
-                {
-                    return Err(anyhow::anyhow!(
-                        "Invalid data: NaN or infinite values detected"
-                    ));
-                }
-                Ok(KlineRow {
-                    symbol: clean_symbol(&k.symbol),
-                    interval: k.interval.clone(),
-                    open: k.open,
-                    high: k.high,
-                    low: k.low,
-                    close: k.close,
-                    volume: k.volume,
-                    timestamp: k.open_time,
-                    trade_count: k.trade_count, // direct i64
-                })
-            })
-            .collect();
-
-        let validated_rows = rows?;
+            .map(|k| KlineRow::from_kline(k, instance, version))
+            .collect::<Result<Vec<_>>>()?;
         info!(
-            "🔄 Converting {} SymbolKlineData records for instance: {}",
+            "🔄 Converting {} SymbolKlineData records for instance: {} ({:?})",
             validated_rows.len(),
-            instance
+            instance,
+            policy
         );
 
         self.insert_klines(&validated_rows).await?;
@@ -222,48 +415,181 @@ impl ClickHouseClient {
 
         Ok(())
     }
-    /*pub async fn get_klines(&self, symbol: &str, interval: &str, limit: Option<u32>) -> Result<Vec<KlineRow>> {
-        let limit_clause = match limit {
-            Some(l) => format!("LIMIT {}", l),
-            None => String::new(),
+
+    // Helper: every candle merged into its stored version, if there is one. Not atomic: a
+    // concurrent write to the same candle between the read and the insert is lost.
+    async fn merge_with_stored(&self, data: &[SymbolKlineData]) -> Result<Vec<SymbolKlineData>> {
+        let key = |k: &SymbolKlineData| {
+            (
+                k.exchange.clone(),
+                canonical_symbol(&k.exchange, &k.symbol),
+                k.interval.clone(),
+                k.open_time.timestamp_millis(),
+            )
         };
 
-        let query = format!(
-            "SELECT symbol, interval, open, high, low, close, volume, timestamp
-             FROM kline_data 
-             WHERE symbol = ? AND interval = ? 
-             ORDER BY timestamp DESC {}",
-            limit_clause
-        );
+        let mut stored = HashMap::new();
+        for chunk in data.chunks(1_000) {
+            let mut exchanges = Vec::with_capacity(chunk.len());
+            let mut symbols = Vec::with_capacity(chunk.len());
+            let mut intervals = Vec::with_capacity(chunk.len());
+            let mut open_times = Vec::with_capacity(chunk.len());
+            for k in chunk {
+                let (exchange, symbol, interval, ms) = key(k);
+                exchanges.push(exchange);
+                symbols.push(symbol);
+                intervals.push(interval);
+                open_times.push(ms);
+            }
+            // Keys go in as bound arrays, zipped back into tuples on the server
+            let rows = self
+                .client
+                .query(
+                    "SELECT ?fields FROM kline_data FINAL
+                     WHERE (exchange, symbol, interval, timestamp) IN (
+                         SELECT k.1, k.2, k.3, fromUnixTimestamp64Milli(k.4, 'UTC')
+                         FROM (SELECT arrayJoin(arrayZip(?, ?, ?, ?)) AS k)
+                     )",
+                )
+                .bind(&exchanges)
+                .bind(&symbols)
+                .bind(&intervals)
+                .bind(&open_times)
+                .fetch_all::<KlineRow>()
+                .await?;
+            for row in rows {
+                let kline = row.into_kline();
+                stored.insert(key(&kline), kline);
+            }
+        }
+
+        // Candles repeated within the batch merge into each other in order
+        Ok(data
+            .iter()
+            .map(|k| {
+                let merged = match stored.get(&key(k)) {
+                    Some(s) => k.merged_into(s),
+                    None => k.clone(),
+                };
+                stored.insert(key(k), merged.clone());
+                merged
+            })
+            .collect())
+    }
+
+    /// Up to `limit` candles of one native symbol/interval within [start, end] opened after `after`, ascending
+    pub async fn get_klines(&self, range: &KlineRange, after: Option<DateTime<Utc>>, limit: usize) -> Result<Vec<SymbolKlineData>> {
+        let from_ms = match after {
+            Some(after) => range.start.timestamp_millis().max(after.timestamp_millis() + 1),
+            None => range.start.timestamp_millis(),
+        };
+        let rows = self
+            .client
+            .query(
+                "SELECT ?fields FROM kline_data FINAL
+                 WHERE exchange = ? AND symbol = ? AND interval = ?
+                   AND timestamp >= fromUnixTimestamp64Milli(?) AND timestamp <= fromUnixTimestamp64Milli(?)
+                 ORDER BY timestamp LIMIT ?",
+            )
+            .bind(&range.exchange)
+            .bind(canonical_symbol(&range.exchange, &range.symbol))
+            .bind(&range.interval)
+            .bind(from_ms)
+            .bind(range.end.timestamp_millis())
+            .bind(limit as u64)
+            .fetch_all::<KlineRow>()
+            .await?;
+
+        Ok(rows.into_iter().map(KlineRow::into_kline).collect())
+    }
 
-        let rows = self.client
-            .query(&query)
-            .bind(symbol)
+    /// The `count` most recent candles of one native symbol/interval, ascending
+    pub async fn get_latest_klines(&self, exchange: &str, symbol: &str, interval: &str, count: usize) -> Result<Vec<SymbolKlineData>> {
+        let rows = self
+            .client
+            .query(
+                "SELECT ?fields FROM kline_data FINAL
+                 WHERE exchange = ? AND symbol = ? AND interval = ?
+                 ORDER BY timestamp DESC LIMIT ?",
+            )
+            .bind(exchange)
+            .bind(canonical_symbol(exchange, symbol))
             .bind(interval)
+            .bind(count as u64)
             .fetch_all::<KlineRow>()
             .await?;
 
-        info!("📈 Retrieved {} kline records for {}-{}", rows.len(), symbol, interval);
-        Ok(rows)
+        Ok(rows.into_iter().rev().map(KlineRow::into_kline).collect())
+    }
+
+    /// Open time of the newest candle per native symbol of an exchange; symbols without candles are left out
+    pub async fn get_last_open_times(
+        &self,
+        exchange: &str,
+        symbols: &[String],
+        interval: &str,
+    ) -> Result<HashMap<String, DateTime<Utc>>> {
+        let canonical: Vec<String> = symbols.iter().map(|s| canonical_symbol(exchange, s)).collect();
+        // max() needs no FINAL: every version of a row has the same timestamp
+        let rows: Vec<(String, i64)> = self
+            .client
+            .query(
+                "SELECT symbol, toUnixTimestamp64Milli(max(timestamp)) FROM kline_data
+                 WHERE exchange = ? AND symbol IN ? AND interval = ?
+                 GROUP BY symbol",
+            )
+            .bind(exchange)
+            .bind(&canonical)
+            .bind(interval)
+            .fetch_all()
+            .await?;
+
+        let last: HashMap<String, i64> = rows.into_iter().collect();
+        Ok(symbols
+            .iter()
+            .zip(&canonical)
+            .filter_map(|(native, canonical)| {
+                let at = DateTime::<Utc>::from_timestamp_millis(*last.get(canonical)?)?;
+                Some((native.clone(), at))
+            })
+            .collect())
     }
 
-    /// Get latest timestamp for a symbol and interval (useful for incremental updates)
-    pub async fn get_latest_timestamp(&self, symbol: &str, interval: &str) -> Result<Option<NaiveDateTime>> {
-        let result: Option<NaiveDateTime> = self.client
-            .query("SELECT MAX(timestamp) FROM kline_data WHERE symbol = ? AND interval = ?")
-            .bind(symbol)
+    /// Open times stored for one native symbol/interval of an exchange within [start, end], ascending
+    pub async fn get_open_times(
+        &self,
+        exchange: &str,
+        symbol: &str,
+        interval: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<DateTime<Utc>>> {
+        let secs: Vec<u32> = self
+            .client
+            .query(
+                "SELECT toUnixTimestamp(timestamp) FROM kline_data FINAL
+                 WHERE exchange = ? AND symbol = ? AND interval = ? AND timestamp >= toDateTime(?) AND timestamp <= toDateTime(?)
+                 ORDER BY timestamp",
+            )
+            .bind(exchange)
+            .bind(canonical_symbol(exchange, symbol))
             .bind(interval)
-            .fetch_optional()
+            .bind(start.timestamp())
+            .bind(end.timestamp())
+            .fetch_all()
             .await?;
 
-        Ok(result)
-    }*/
+        Ok(secs
+            .into_iter()
+            .filter_map(|s| DateTime::<Utc>::from_timestamp(s as i64, 0))
+            .collect())
+    }
 
     /// Count total records for monitoring
     pub async fn count_klines(&self) -> Result<u64> {
         let count: u64 = self
             .client
-            .query("SELECT COUNT(*) FROM kline_data")
+            .query("SELECT count() FROM kline_data FINAL")
             .fetch_one()
             .await?;
 
@@ -271,6 +597,7 @@ impl ClickHouseClient {
     }
 
     /// Health check method
+    #[allow(dead_code)]
     pub async fn health_check(&self) -> Result<bool> {
         match self.client.query("SELECT 1").fetch_one::<u8>().await {
             Ok(_) => Ok(true),
@@ -281,3 +608,188 @@ impl ClickHouseClient {
         }
     }
 }
+
+#[async_trait]
+impl KlineSink for ClickHouseClient {
+    fn name(&self) -> &str {
+        "ClickHouse"
+    }
+
+    async fn write(&self, policy: UpdatePolicy, kline_data: &[SymbolKlineData], instance: &str) -> Result<()> {
+        self.write_symbol_klines(kline_data, instance, policy).await
+    }
+}
+
+#[async_trait]
+impl KlineStore for ClickHouseClient {
+    fn name(&self) -> &str {
+        "ClickHouse"
+    }
+
+    async fn klines(&self, range: &KlineRange, after: Option<DateTime<Utc>>, limit: usize) -> Result<KlinePage> {
+        let limit = limit.max(1);
+        Ok(KlinePage::new(self.get_klines(range, after, limit).await?, limit))
+    }
+
+    async fn latest_klines(&self, exchange: &str, symbol: &str, interval: &str, count: usize) -> Result<Vec<SymbolKlineData>> {
+        self.get_latest_klines(exchange, symbol, interval, count).await
+    }
+
+    async fn last_open_times(
+        &self,
+        exchange: &str,
+        symbols: &[String],
+        interval: &str,
+    ) -> Result<HashMap<String, DateTime<Utc>>> {
+        self.get_last_open_times(exchange, symbols, interval).await
+    }
+
+    async fn open_times(&self, range: &KlineRange) -> Result<Vec<DateTime<Utc>>> {
+        self.get_open_times(&range.exchange, &range.symbol, &range.interval, range.start, range.end).await
+    }
+}
+
+// Helper: DDL of the kline table under `table`
+fn kline_table_ddl(table: &str) -> String {
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+            exchange LowCardinality(String),
+            instance LowCardinality(String) DEFAULT '',
+            symbol String,
+            native_symbol String,
+            interval String,
+            open Float64,
+            high Float64,
+            low Float64,
+            close Float64,
+            volume Float64,
+            volume_24h Nullable(Float64),
+            timestamp DateTime64(3, 'UTC'),
+            trade_count Int64,
+            buy_volume Float64 DEFAULT 0,
+            sell_volume Float64 DEFAULT 0,
+            is_synthetic Bool DEFAULT false,
+            version UInt64
+        ) ENGINE = ReplacingMergeTree(version)
+        PARTITION BY toYYYYMM(timestamp)
+        ORDER BY (exchange, symbol, interval, timestamp)",
+        table
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overwrites_outrank_ignored_writes_and_the_first_ignored_write_wins() {
+        let at = |secs| DateTime::<Utc>::from_timestamp(secs, 0).unwrap();
+        let saved = write_version(UpdatePolicy::Ignore, at(1_700_000_000));
+        let saved_later = write_version(UpdatePolicy::Ignore, at(1_700_000_060));
+        let overwrite = write_version(UpdatePolicy::Overwrite, at(1_700_000_000));
+        let overwrite_later = write_version(UpdatePolicy::Overwrite, at(1_700_000_060));
+
+        assert!(saved > saved_later);
+        assert!(saved < IGNORE_VERSION_BASE && IGNORE_VERSION_BASE < overwrite);
+        assert!(overwrite < overwrite_later);
+    }
+
+    #[test]
+    fn the_first_of_two_ignored_writes_in_the_same_second_wins() {
+        let first = write_version(UpdatePolicy::Ignore, DateTime::<Utc>::from_timestamp_millis(1_700_000_000_100).unwrap());
+        let second = write_version(UpdatePolicy::Ignore, DateTime::<Utc>::from_timestamp_millis(1_700_000_000_900).unwrap());
+
+        assert!(first > second);
+    }
+
+    #[test]
+    fn legacy_bases_match_the_old_clean_symbol() {
+        assert_eq!(legacy_base("BTCUSDT"), "BTC");
+        assert_eq!(legacy_base("btc-usdt-swap"), "BTC");
+        assert_eq!(legacy_base("ETHUSDT_UMCBL"), "ETH");
+        assert_eq!(legacy_base("XBTUSDM"), "XBTUSDM");
+    }
+
+    // Needs a scratch ClickHouse: `CLICKHOUSE_TEST_URL` (plus optional `CLICKHOUSE_TEST_USER` /
+    // `CLICKHOUSE_TEST_PASSWORD`), run with `cargo test -- --ignored`
+    #[tokio::test]
+    #[ignore = "needs a ClickHouse server in CLICKHOUSE_TEST_URL"]
+    async fn legacy_rows_are_readable_after_migration() {
+        let url = std::env::var("CLICKHOUSE_TEST_URL").expect("CLICKHOUSE_TEST_URL is not set");
+        let user = std::env::var("CLICKHOUSE_TEST_USER").unwrap_or_else(|_| "default".to_string());
+        let password = std::env::var("CLICKHOUSE_TEST_PASSWORD").unwrap_or_default();
+        let database = "tick_aggregator_migration_test";
+
+        let admin = Client::default().with_url(&url).with_user(&user).with_password(&password);
+        admin.query(&format!("DROP DATABASE IF EXISTS {}", database)).execute().await.unwrap();
+        admin.query(&format!("CREATE DATABASE {}", database)).execute().await.unwrap();
+
+        // Schema and cleaned symbols written before exchanges, native symbols and versions
+        let legacy = admin.clone().with_database(database);
+        legacy
+            .query(
+                "CREATE TABLE kline_data (
+                    symbol String,
+                    interval String,
+                    open Float64,
+                    high Float64,
+                    low Float64,
+                    close Float64,
+                    volume Float64,
+                    timestamp DateTime,
+                    trade_count Int64
+                ) ENGINE = MergeTree()
+                PARTITION BY toYYYYMM(timestamp)
+                ORDER BY (symbol, interval, timestamp)",
+            )
+            .execute()
+            .await
+            .unwrap();
+        legacy
+            .query("INSERT INTO kline_data VALUES ('BTC', '1m', 1, 2, 0.5, 1.5, 10, 1700000040, 3)")
+            .execute()
+            .await
+            .unwrap();
+
+        let ch = ClickHouseClient::init(&crate::pkg::config::ClickHouseConfig {
+            enabled: true,
+            url,
+            user,
+            password,
+            database: database.to_string(),
+            max_rows_per_insert: 1_000,
+            max_bytes_per_insert: 1 << 20,
+            insert_retries: 0,
+            retry_backoff_millis: 0,
+            lz4: false,
+        })
+        .await
+        .unwrap();
+        let section = ExchangeSettings {
+            exchange: "Binance".to_string(),
+            instance: "binance".to_string(),
+            refresh_seconds: 20,
+            feed_mode: Default::default(),
+            intervals: vec!["1m".to_string()],
+            symbols: vec!["BTCUSDT".to_string(), "ETHUSDT".to_string()],
+            blacklisted_symbols: Vec::new(),
+            symbol_rules: Default::default(),
+        };
+        ch.create_kline_table(Some(&section)).await.unwrap();
+
+        let at = DateTime::<Utc>::from_timestamp(1_700_000_040, 0).unwrap();
+        let range = KlineRange {
+            exchange: "binance".to_string(),
+            symbol: "BTCUSDT".to_string(),
+            interval: "1m".to_string(),
+            start: at,
+            end: at,
+        };
+        let klines = ch.get_klines(&range, None, 10).await.unwrap();
+        admin.query(&format!("DROP DATABASE {}", database)).execute().await.unwrap();
+
+        assert_eq!(klines.len(), 1);
+        assert_eq!((klines[0].exchange.as_str(), klines[0].symbol.as_str()), ("binance", "BTCUSDT"));
+        assert_eq!(klines[0].close, 1.5);
+    }
+}
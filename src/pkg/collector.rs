@@ -0,0 +1,550 @@
+use crate::pkg::aggregator::intervals::interval_to_ms;
+use crate::pkg::aggregator::kline_rollup::FlushedKlines;
+use crate::pkg::aggregator::snapshot::SnapshotStore;
+use crate::pkg::aggregator::{symbol_rotator::SymbolRotator, ticker_aggregator::KlineAggregator};
+use crate::pkg::config::{ExchangeSettings, FeedMode, SETTINGS};
+use crate::pkg::dbcontext::entities::SymbolKlineData;
+use crate::pkg::exchanges::exchange::ExchangeApi;
+use crate::pkg::exchanges::exchange_client::create_exchange_api;
+use crate::pkg::exchanges::exchange_entities::{TickerInfo, TradeData};
+use crate::pkg::gap_scanner::{GAP_SCAN_SETTLE_MINUTES, GapScanRequest, scan_and_repair};
+use crate::pkg::save_config::save_config;
+use crate::pkg::sink::UpdatePolicy;
+use crate::pkg::storage::StorageBackend;
+use crate::pkg::symbol_selector::select_symbols;
+
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use log::{error, info, warn};
+use rand::Rng;
+use std::{collections::{HashMap, HashSet}, sync::Arc, time::Duration};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, interval_at};
+
+// Native kline mode: how far the first pull looks back, and parallel requests per cycle
+const NATIVE_LOOKBACK_MINUTES: i64 = 5;
+const NATIVE_CONCURRENCY: usize = 4;
+// Built candles are flushed this long after every minute closes, so trades in flight still land
+const FLUSH_GRACE_MS: i64 = 1_500;
+
+/// Collect candles for one exchange section until `shutdown` flips to true, then stop intake
+/// and write what is still buffered. Every section gets its own rotator and aggregator;
+/// storage is shared. Errors when the collector couldn't start or its last flush failed.
+pub async fn run_collector(
+    section: ExchangeSettings,
+    storage: Arc<StorageBackend>,
+    mut shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let settings_ref = Arc::clone(&SETTINGS);
+
+    let exchange = section.exchange.clone();
+    let instance = section.instance.clone();
+    let mut symbols = section.symbols.clone();
+
+    let mut invalid_symbols: HashSet<String> = section
+        .blacklisted_symbols
+        .iter()
+        .map(|s| s.to_uppercase())
+        .collect();
+
+    let api: Arc<dyn ExchangeApi> = match create_exchange_api(&exchange) {
+        Ok(api) => Arc::from(api),
+        Err(e) => {
+            error!("❌ [{}] Failed to create exchange API: {:?}", exchange, e);
+            return Err(e);
+        }
+    };
+
+    // Rule based symbol list, falling back to `symbol` if the instruments can't be fetched yet
+    let rules = section.symbol_rules.clone();
+    if rules.enabled {
+        match select_symbols(api.as_ref(), &rules, &invalid_symbols).await {
+            Ok(selected) if !selected.is_empty() => {
+                info!("🧭 [{}] {} symbols match the symbol rules", exchange, selected.len());
+                symbols = selected;
+            }
+            Ok(_) => warn!("⚠️ [{}] No symbols match the symbol rules, using the configured list", exchange),
+            Err(e) => warn!("⚠️ [{}] Symbol selection failed, using the configured list: {:?}", exchange, e),
+        }
+    }
+    let symbol_refresh_every = Duration::from_secs(rules.refresh_minutes.max(1) * 60);
+    let mut symbol_refresh = interval_at(Instant::now() + symbol_refresh_every, symbol_refresh_every);
+
+    let batch_size = 50;
+    let mut rotator = SymbolRotator::new(symbols.clone(), batch_size);
+
+    let allowed_lateness_ms = settings_ref.aggregator.allowed_lateness_seconds as i64 * 1000;
+    let k_agg = KlineAggregator::new(&exchange, &section.intervals, allowed_lateness_ms, settings_ref.aggregator.forward_fill, settings_ref.debug);
+    let mut dropped_late_seen = 0;
+    let mut dropped_future_seen = 0;
+
+    // Candles in progress survive restarts when snapshots are on
+    let snapshot_settings = &settings_ref.aggregator.snapshot;
+    let snapshots = snapshot_settings
+        .enabled
+        .then(|| SnapshotStore::new(&snapshot_settings.directory, &instance));
+    if let Some(store) = &snapshots {
+        match store.restore(&k_agg).await {
+            Ok(true) => info!("📂 [{}] Restored in-progress candles from {}", exchange, store.path().display()),
+            Ok(false) => info!("ℹ️ [{}] No recent aggregator snapshot, starting empty", exchange),
+            Err(e) => warn!("⚠️ [{}] Failed to restore aggregator snapshot: {:?}", exchange, e),
+        }
+    }
+    let snapshot_every = Duration::from_secs(snapshot_settings.interval_seconds.max(1));
+    let mut snapshot_ticker = interval_at(Instant::now() + snapshot_every, snapshot_every);
+
+    let refresh_interval_secs = if section.refresh_seconds < 4 {
+        4
+    } else {
+        section.refresh_seconds
+    };
+    let interval_duration = Duration::from_secs(refresh_interval_secs as u64);
+
+    let mut ticker = interval_at(Instant::now() + interval_duration, interval_duration);
+
+    // Native candles are pulled a few seconds after every minute closes
+    let now_ms = Utc::now().timestamp_millis();
+    let first_native_pull = Duration::from_millis((60_000 - now_ms % 60_000 + 3_000) as u64);
+    let mut native_ticker = interval_at(Instant::now() + first_native_pull, Duration::from_secs(60));
+    let mut native_last_open: HashMap<String, DateTime<Utc>> = HashMap::new();
+
+    // Built candles are flushed right after every minute boundary; any interval whose bucket
+    // ends on that boundary is closed in the same flush
+    let first_flush = Duration::from_millis((60_000 - now_ms % 60_000 + FLUSH_GRACE_MS) as u64);
+    let mut flush_ticker = interval_at(Instant::now() + first_flush, Duration::from_secs(60));
+
+    // Gaps are checked on the finest stored interval
+    let gap_interval = section
+        .intervals
+        .iter()
+        .filter(|i| interval_to_ms(i).is_some())
+        .min_by_key(|i| interval_to_ms(i))
+        .cloned()
+        .unwrap_or_else(|| "1m".to_string());
+
+    // Stored candles are checked for holes periodically; one scan at a time
+    let gap_scan_every = Duration::from_secs(settings_ref.gap_scan.interval_minutes.max(1) * 60);
+    let mut gap_ticker = interval_at(Instant::now() + gap_scan_every, gap_scan_every);
+    let mut gap_task: Option<JoinHandle<()>> = None;
+
+    // WebSocket feeds; polling stays as the fallback if the stream task dies
+    let mut feed_mode = section.feed_mode;
+    let mut stream = MarketStream::start(&api, &exchange, feed_mode, &symbols, &invalid_symbols);
+
+    info!("⏳ Starting periodic fetch loop for exchange: {}", exchange);
+
+    loop {
+        tokio::select! {
+                    t = stream.ticker_rx.recv(), if feed_mode == FeedMode::Websocket => {
+                        match t {
+                            Some(t) => feed_ticker(&k_agg, &t),
+                            None => {
+                                warn!("⚠️ [{}] Ticker stream ended, falling back to REST polling", exchange);
+                                feed_mode = FeedMode::Polling;
+                            }
+                        }
+                    },
+                    t = stream.trade_rx.recv(), if feed_mode == FeedMode::Trades => {
+                        match t {
+                            Some(t) => k_agg.add_trade(&t),
+                            None => {
+                                warn!("⚠️ [{}] Trade stream ended, falling back to REST polling", exchange);
+                                feed_mode = FeedMode::Polling;
+                            }
+                        }
+                    },
+                    _ = native_ticker.tick(), if feed_mode == FeedMode::Native => {
+                        let native_symbols: Vec<String> = symbols
+                            .iter()
+                            .filter(|s| !invalid_symbols.contains(&s.to_uppercase()))
+                            .cloned()
+                            .collect();
+
+                        info!("🌐 Pulling closed 1m klines for {} symbols from {}", native_symbols.len(), exchange);
+                        let native = fetch_native_klines(api.as_ref(), &native_symbols, &mut native_last_open).await;
+                        // Buckets missing their last minute get one more pull before closing without it
+                        let settled = Utc::now().timestamp_millis() - 60_000;
+                        let kline_data = k_agg.roll_up(native, Vec::new(), settled, settled);
+                        if kline_data.closed.is_empty() && kline_data.amended.is_empty() {
+                            info!("ℹ️ No new native klines this cycle");
+                        } else {
+                            // Exchange candles are authoritative, they replace whatever was stored
+                            save_to_storage(&storage, &kline_data, &instance, UpdatePolicy::Overwrite).await;
+                            save_snapshot(snapshots.as_ref(), &k_agg, &exchange).await;
+                        }
+                    },
+                    _ = symbol_refresh.tick(), if rules.enabled => {
+                        let selected = match select_symbols(api.as_ref(), &rules, &invalid_symbols).await {
+                            Ok(selected) if !selected.is_empty() => selected,
+                            Ok(_) => {
+                                warn!("⚠️ [{}] Symbol refresh matched nothing, keeping the current list", exchange);
+                                continue;
+                            }
+                            Err(e) => {
+                                error!("❌ [{}] Symbol refresh failed: {:?}", exchange, e);
+                                continue;
+                            }
+                        };
+
+                        let current: HashSet<&String> = symbols.iter().collect();
+                        let next: HashSet<&String> = selected.iter().collect();
+                        let listed = next.difference(&current).count();
+                        let retired = current.difference(&next).count();
+                        if listed == 0 && retired == 0 {
+                            continue;
+                        }
+
+                        info!("🧭 [{}] Symbol refresh: {} added, {} retired, {} total", exchange, listed, retired, selected.len());
+                        native_last_open.retain(|s, _| next.contains(s));
+                        symbols = selected;
+                        rotator.set_symbols(symbols.clone());
+                        k_agg.retain_symbols(&symbols);
+                        if matches!(feed_mode, FeedMode::Websocket | FeedMode::Trades) {
+                            stream.stop();
+                            stream = MarketStream::start(&api, &exchange, feed_mode, &symbols, &invalid_symbols);
+                        }
+                    },
+                    _ = gap_ticker.tick(), if settings_ref.gap_scan.enabled => {
+                        if gap_task.as_ref().is_some_and(|t| !t.is_finished()) {
+                            warn!("⚠️ Previous gap scan still running, skipping this one");
+                            continue;
+                        }
+
+                        let end = Utc::now() - chrono::Duration::minutes(GAP_SCAN_SETTLE_MINUTES);
+                        let req = GapScanRequest {
+                            exchange: exchange.clone(),
+                            instance: instance.clone(),
+                            symbols: symbols
+                                .iter()
+                                .filter(|s| !invalid_symbols.contains(&s.to_uppercase()))
+                                .cloned()
+                                .collect(),
+                            interval: gap_interval.clone(),
+                            start: end - chrono::Duration::minutes(settings_ref.gap_scan.lookback_minutes),
+                            end,
+                            repair: settings_ref.gap_scan.repair,
+                        };
+                        let api = Arc::clone(&api);
+                        let storage = Arc::clone(&storage);
+                        gap_task = Some(tokio::spawn(async move {
+                            if let Err(e) = scan_and_repair(api.as_ref(), &storage, &req).await {
+                                error!("❌ Gap scan failed: {:?}", e);
+                            }
+                        }));
+                    },
+                    _ = flush_ticker.tick(), if feed_mode != FeedMode::Native => {
+                        flush_klines(&k_agg, &storage, &instance).await;
+                        // Right after a flush, so a restart knows which candles are written
+                        save_snapshot(snapshots.as_ref(), &k_agg, &exchange).await;
+
+                        let dropped_late = k_agg.dropped_late();
+                        if dropped_late > dropped_late_seen {
+                            warn!(
+                                "⏱️ [{}] {} ticks arrived past the watermark and were dropped ({} since start)",
+                                exchange,
+                                dropped_late - dropped_late_seen,
+                                dropped_late
+                            );
+                            dropped_late_seen = dropped_late;
+                        }
+                        let dropped_future = k_agg.dropped_future();
+                        if dropped_future > dropped_future_seen {
+                            warn!(
+                                "⏱️ [{}] {} ticks were stamped too far in the future and were dropped ({} since start)",
+                                exchange,
+                                dropped_future - dropped_future_seen,
+                                dropped_future
+                            );
+                            dropped_future_seen = dropped_future;
+                        }
+                    },
+                    _ = ticker.tick(), if feed_mode == FeedMode::Polling => {
+
+                        info!("🔄 [{}] New fetch cycle started", exchange);
+
+                        // Random jitter
+                        let jitter = rand::thread_rng().gen_range(0..500);
+                        tokio::time::sleep(Duration::from_millis(jitter)).await;
+
+                        // Get next batch
+                        let raw_batch = rotator.next_batch();
+                        let batch: Vec<String> = raw_batch
+                            .into_iter()
+                            .flat_map(|slice| slice.iter())
+                            .filter(|sym| !invalid_symbols.contains(&sym.to_uppercase()))
+                            .cloned()
+                            .collect();
+
+                        info!("📦 [{}] Processing batch of {} symbols", exchange, batch.len());
+
+                        if batch.is_empty() {
+                            warn!("⚠️ [{}] No valid symbols in batch to process", exchange);
+                            continue;
+                        }
+
+                        // Fetch tickers
+                        info!("🌐 Fetching tickers from {}", exchange);
+                        let tickers_res = api.get_all_tickers().await;
+                        let tickers = match tickers_res {
+                            Ok(data) => {
+                                info!("✅ Retrieved {} total tickers from {}", data.len(), exchange);
+                                data
+                            }
+                            Err(e) => {
+                                error!("❌ [{}] Failed to fetch tickers: {:?}", exchange, e);
+                                continue;
+                            }
+                        };
+
+                        // Filter tickers for batch
+                        let batch_set: HashSet<String> = batch.iter().map(|s| s.to_uppercase()).collect();
+                        let filtered: Vec<TickerInfo> = tickers.into_iter()
+                            .filter(|t| batch_set.contains(&t.symbol.to_uppercase()))
+                            .collect();
+
+                        info!("📥 [{}] Matched {} tickers from batch request", exchange, filtered.len());
+
+                        // Blacklist missing symbols
+                        let found_symbols: HashSet<String> = filtered.iter()
+                            .map(|t| t.symbol.to_uppercase())
+                            .collect();
+
+                        for sym in &batch {
+                            let up = sym.to_uppercase();
+                            if !found_symbols.contains(&up) && !invalid_symbols.contains(&up) {
+                                warn!("🚫 [{}] Symbol {} not found, blacklisting", exchange, up);
+                                invalid_symbols.insert(up.clone());
+
+                                if let Err(e) = save_config("appsettings.yaml").await {
+                                    error!("❌ Failed to save config: {:?}", e);
+                                }
+                            }
+                        }
+
+                        // Feed aggregator
+                        for t in &filtered {
+                            feed_ticker(&k_agg, t);
+                        }
+                        info!("📊 [{}] Aggregator updated with {} tickers", exchange, filtered.len());
+                    },
+                    _ = snapshot_ticker.tick(), if snapshots.is_some() => {
+                        save_snapshot(snapshots.as_ref(), &k_agg, &exchange).await;
+                    },
+                    _ = shutdown.changed() => {
+                        info!("🛑 [{}] Collector stopping", exchange);
+                        break;
+                    }
+                }
+    }
+
+    // Stop intake first, then wait for writes in flight and flush what's buffered
+    stream.stop();
+    if let Some(task) = gap_task.take() {
+        if !task.is_finished() {
+            info!("⏳ [{}] Waiting for the running gap scan to finish", exchange);
+        }
+        let _ = task.await;
+    }
+
+    flush_on_shutdown(&k_agg, &storage, &instance, snapshots.as_ref()).await?;
+    info!("👋 [{}] Collector stopped", exchange);
+    Ok(())
+}
+
+/// WebSocket feed for one symbol set; replaced wholesale when the symbol list changes
+struct MarketStream {
+    task: Option<JoinHandle<()>>,
+    ticker_rx: mpsc::Receiver<TickerInfo>,
+    trade_rx: mpsc::Receiver<TradeData>,
+}
+
+impl MarketStream {
+    /// Spawn the stream for streaming feed modes; other modes get closed, unused channels
+    fn start(
+        api: &Arc<dyn ExchangeApi>,
+        exchange: &str,
+        feed_mode: FeedMode,
+        symbols: &[String],
+        invalid_symbols: &HashSet<String>,
+    ) -> Self {
+        let (ticker_tx, ticker_rx) = mpsc::channel::<TickerInfo>(10_000);
+        let (trade_tx, trade_rx) = mpsc::channel::<TradeData>(50_000);
+        if !matches!(feed_mode, FeedMode::Websocket | FeedMode::Trades) {
+            return Self { task: None, ticker_rx, trade_rx };
+        }
+
+        let api = Arc::clone(api);
+        let stream_symbols: Vec<String> = symbols
+            .iter()
+            .filter(|s| !invalid_symbols.contains(&s.to_uppercase()))
+            .cloned()
+            .collect();
+
+        info!(
+            "📡 Streaming {} symbols from {} over WebSocket ({:?})",
+            stream_symbols.len(),
+            exchange,
+            feed_mode
+        );
+        let exchange = exchange.to_string();
+        let task = tokio::spawn(async move {
+            let result = if feed_mode == FeedMode::Trades {
+                drop(ticker_tx);
+                api.stream_trades(&stream_symbols, trade_tx).await
+            } else {
+                drop(trade_tx);
+                api.stream_tickers(&stream_symbols, ticker_tx).await
+            };
+            if let Err(e) = result {
+                error!("❌ [{}] Market data stream stopped: {:?}", exchange, e);
+            }
+        });
+
+        Self {
+            task: Some(task),
+            ticker_rx,
+            trade_rx,
+        }
+    }
+
+    fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+fn feed_ticker(k_agg: &KlineAggregator, t: &TickerInfo) {
+    match (t.last_price.parse::<f64>(), t.vol_24h.as_deref()) {
+        (Ok(price), Some(vol_str)) => {
+            if let Ok(volume_24h) = vol_str.parse::<f64>() {
+                // The aggregator turns successive 24h readings into per-candle volume
+                k_agg.add_price(&t.symbol, price, volume_24h, t.timestamp);
+            } else {
+                warn!("❌ Failed to parse volume for symbol {}", t.symbol);
+            }
+        }
+        _ => warn!("❌ Failed to parse price or volume for symbol {}", t.symbol),
+    }
+}
+
+async fn flush_klines(k_agg: &KlineAggregator, storage: &StorageBackend, instance: &str) {
+    let kline_data = k_agg.extract_ohlc();
+
+    if kline_data.closed.is_empty() && kline_data.amended.is_empty() {
+        info!("ℹ️ No OHLC data to save this cycle");
+        return;
+    }
+
+    save_to_storage(storage, &kline_data, instance, UpdatePolicy::Ignore).await;
+}
+
+/// Last flush before exiting. Candles still open go into the snapshot when snapshots are on,
+/// so the next run continues them; otherwise they are closed early and written as they are.
+/// With the spool on, batches a sink doesn't take stay spooled for the next run.
+async fn flush_on_shutdown(
+    k_agg: &KlineAggregator,
+    storage: &StorageBackend,
+    instance: &str,
+    snapshots: Option<&SnapshotStore>,
+) -> anyhow::Result<()> {
+    let kline_data = match snapshots {
+        Some(_) => k_agg.extract_ohlc(),
+        None => k_agg.drain(),
+    };
+
+    if !kline_data.closed.is_empty() {
+        storage.save(&kline_data.closed, instance).await?;
+    }
+    if !kline_data.amended.is_empty() {
+        storage.write(UpdatePolicy::Overwrite, &kline_data.amended, instance).await?;
+    }
+    info!(
+        "💾 [{}] Flushed {} candles on shutdown",
+        instance,
+        kline_data.closed.len() + kline_data.amended.len()
+    );
+
+    if let Some(store) = snapshots {
+        store.save(k_agg).await?;
+        info!("📂 [{}] Saved open candles to {}", instance, store.path().display());
+    }
+    Ok(())
+}
+
+async fn save_snapshot(store: Option<&SnapshotStore>, k_agg: &KlineAggregator, exchange: &str) {
+    if let Some(store) = store
+        && let Err(e) = store.save(k_agg).await
+    {
+        error!("❌ [{}] Failed to save aggregator snapshot: {:?}", exchange, e);
+    }
+}
+
+// Closed candles are written with `closed_policy`; amended ones were written before and changed
+// since, with late ticks, so they overwrite
+async fn save_to_storage(storage: &StorageBackend, kline_data: &FlushedKlines, instance: &str, closed_policy: UpdatePolicy) {
+    for (amended, policy, klines) in [
+        (false, closed_policy, &kline_data.closed),
+        (true, UpdatePolicy::Overwrite, &kline_data.amended),
+    ] {
+        if klines.is_empty() {
+            continue;
+        }
+        if !amended {
+            info!("📝 [{}] Preparing to save {} OHLC records", instance, klines.len());
+        }
+
+        match storage.write(policy, klines, instance).await {
+            Ok(()) if !amended => {
+                info!("💾 [{}] Successfully saved {} OHLC entries to {}", instance, klines.len(), storage.name())
+            }
+            Ok(()) => info!("♻️ [{}] Amended {} OHLC entries with late ticks in {}", instance, klines.len(), storage.name()),
+            Err(e) => error!("❌ [{}] Failed to write klines to {}: {:?}", instance, storage.name(), e),
+        }
+    }
+}
+
+/// Pull the 1m candles closed since the previous pull for every symbol straight from the exchange
+async fn fetch_native_klines(
+    api: &dyn ExchangeApi,
+    symbols: &[String],
+    last_open: &mut HashMap<String, DateTime<Utc>>,
+) -> Vec<SymbolKlineData> {
+    let now = Utc::now();
+
+    // Owned (symbol, start) pairs keep the request futures `Send` inside a spawned collector
+    let requests: Vec<(String, DateTime<Utc>)> = symbols
+        .iter()
+        .map(|symbol| {
+            let start = last_open
+                .get(symbol)
+                .map(|t| *t + chrono::Duration::minutes(1))
+                .unwrap_or(now - chrono::Duration::minutes(NATIVE_LOOKBACK_MINUTES));
+            (symbol.clone(), start)
+        })
+        .collect();
+
+    let results: Vec<(String, anyhow::Result<Vec<SymbolKlineData>>)> = futures_util::stream::iter(requests)
+        .map(|(symbol, start)| async move {
+            let result = api.get_klines(&symbol, "1m", start, now).await;
+            (symbol, result)
+        })
+        .buffer_unordered(NATIVE_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut kline_data = Vec::new();
+    for (symbol, result) in results {
+        match result {
+            Ok(klines) => {
+                if let Some(last) = klines.last() {
+                    last_open.insert(symbol, last.open_time);
+                }
+                kline_data.extend(klines);
+            }
+            Err(e) => warn!("❌ Failed to fetch klines for {}: {:?}", symbol, e),
+        }
+    }
+    kline_data
+}
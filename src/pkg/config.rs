@@ -12,6 +12,41 @@ pub struct AggregatorSettings {
     pub enable_batch_stats: bool,
     #[serde(rename = "PersistRawTicks")]
     pub persist_raw_ticks: PersistRawTicks,
+    /// How long after a minute closes (on exchange time) its candle still takes late ticks
+    #[serde(rename = "AllowedLatenessSeconds", default = "default_allowed_lateness_seconds")]
+    pub allowed_lateness_seconds: u64,
+    /// Write a synthetic candle (previous close, zero volume) for minutes without ticks
+    #[serde(rename = "ForwardFill", default)]
+    pub forward_fill: bool,
+    #[serde(rename = "Snapshot", default)]
+    pub snapshot: SnapshotSettings,
+}
+
+/// In-progress candles saved to local disk, so a restart doesn't lose the current minute
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnapshotSettings {
+    #[serde(rename = "Enabled")]
+    pub enabled: bool,
+    /// One `<instance>.json` per exchange section goes here
+    #[serde(rename = "Directory")]
+    pub directory: String,
+    #[serde(rename = "IntervalSeconds")]
+    pub interval_seconds: u64,
+}
+
+impl Default for SnapshotSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: "snapshots".to_string(),
+            interval_seconds: 15,
+        }
+    }
+}
+
+fn default_allowed_lateness_seconds() -> u64 {
+    10
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -54,28 +89,169 @@ pub struct KafkaConfig {
     pub topic: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DatabaseConfig {
     #[serde(rename = "provider")]
     pub provider: String,
     #[serde(rename = "connectionString")]
     pub connection_string: String,
+    /// Schema of the candle table, created if missing
+    #[serde(rename = "schema", default = "default_db_schema")]
+    pub schema: String,
+    /// Candle table name, e.g. a `Dev_` table next to the production one
+    #[serde(rename = "table", default = "default_db_table")]
+    pub table: String,
+    /// Apply pending migrations on startup; when off, startup fails until `migrate` has run
+    #[serde(rename = "autoMigrate", default = "default_true")]
+    pub auto_migrate: bool,
+    /// Batches of at least this many candles are written with COPY, smaller ones with INSERT; 0 never uses COPY
+    #[serde(rename = "copyMinRows", default = "default_copy_min_rows")]
+    pub copy_min_rows: usize,
+    #[serde(rename = "timescale", default)]
+    pub timescale: TimescaleSettings,
+}
+
+/// TimescaleDB policies and continuous aggregates of the candle table, applied with the migrations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimescaleSettings {
+    /// Intervals to keep a continuous aggregate of the 1m candles for: 5m, 15m, 1h, 1d, ...
+    #[serde(rename = "continuousAggregates", default)]
+    pub continuous_aggregates: Vec<String>,
+    /// Compress chunks older than this many days, 0 for no compression policy
+    #[serde(rename = "compressAfterDays", default)]
+    pub compress_after_days: u32,
+    /// Drop chunks older than this many days, 0 to keep everything
+    #[serde(rename = "dropAfterDays", default)]
+    pub drop_after_days: u32,
+    /// Buckets back the refresh policy recomputes, at least 3; older 1m changes wait for an explicit refresh
+    #[serde(rename = "refreshLookbackBuckets", default = "default_refresh_lookback_buckets")]
+    pub refresh_lookback_buckets: u32,
+}
+
+impl Default for TimescaleSettings {
+    fn default() -> Self {
+        Self {
+            continuous_aggregates: Vec::new(),
+            compress_after_days: 0,
+            drop_after_days: 0,
+            refresh_lookback_buckets: default_refresh_lookback_buckets(),
+        }
+    }
+}
+
+fn default_refresh_lookback_buckets() -> u32 {
+    3
+}
+
+fn default_copy_min_rows() -> usize {
+    500
+}
+
+fn default_db_schema() -> String {
+    "public".to_string()
+}
+
+fn default_db_table() -> String {
+    "Dev_SymbolKlineData".to_string()
+}
+
+/// One exchange to collect from; every section runs as its own collector task
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExchangeSettings {
+    #[serde(rename = "exchange")]
+    pub exchange: String,
+    /// Tag stored with every candle, defaults to the exchange name
+    #[serde(rename = "instance", default)]
+    pub instance: String,
+    #[serde(rename = "RefreshSeconds", default = "default_refresh_seconds")]
+    pub refresh_seconds: i32,
+    #[serde(rename = "FeedMode", default)]
+    pub feed_mode: FeedMode,
+    /// Candle intervals to store: 1m, 3m, 5m, 15m, 1h, 4h, 1d
+    #[serde(rename = "Intervals", default = "default_intervals")]
+    pub intervals: Vec<String>,
+    #[serde(rename = "symbol", default)]
+    pub symbols: Vec<String>,
+    #[serde(rename = "blacklisted_symbols", default)]
+    pub blacklisted_symbols: Vec<String>,
+    /// Pick symbols from the exchange's instrument list instead of `symbol`
+    #[serde(rename = "SymbolRules", default)]
+    pub symbol_rules: SymbolRules,
+}
+
+/// Rule based symbol selection, refreshed periodically to follow listings and delistings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SymbolRules {
+    #[serde(rename = "Enabled")]
+    pub enabled: bool,
+    /// Quote asset to keep, e.g. USDT; empty keeps all
+    #[serde(rename = "QuoteAsset")]
+    pub quote_asset: String,
+    #[serde(rename = "PerpetualOnly")]
+    pub perpetual_only: bool,
+    /// Minimum 24h turnover in the quote asset
+    #[serde(rename = "MinQuoteVolume24h")]
+    pub min_quote_volume_24h: f64,
+    /// Keep only the most traded N symbols, 0 for no cap
+    #[serde(rename = "MaxSymbols")]
+    pub max_symbols: usize,
+    #[serde(rename = "RefreshMinutes")]
+    pub refresh_minutes: u64,
+}
+
+impl Default for SymbolRules {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            quote_asset: "USDT".to_string(),
+            perpetual_only: true,
+            min_quote_volume_24h: 0.0,
+            max_symbols: 0,
+            refresh_minutes: 60,
+        }
+    }
+}
+
+fn default_refresh_seconds() -> i32 {
+    20
+}
+
+pub fn default_intervals() -> Vec<String> {
+    vec!["1m".to_string()]
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppSettings {
-    #[serde(rename = "exchange")]
+    #[serde(rename = "exchanges", default, skip_serializing_if = "Vec::is_empty")]
+    pub exchanges: Vec<ExchangeSettings>,
+
+    // Single-exchange layout, still read when `exchanges` is not set
+    #[serde(rename = "exchange", default, skip_serializing_if = "String::is_empty")]
     pub exchange: String,
-    #[serde(rename = "instance")]
+    #[serde(rename = "instance", default, skip_serializing_if = "String::is_empty")]
     pub instance: String,
-    #[serde(rename = "RefreshSeconds")]
+    #[serde(rename = "RefreshSeconds", default = "default_refresh_seconds")]
     pub refresh_seconds: i32,
-    #[serde(rename = "symbol")]
+    #[serde(rename = "FeedMode", default)]
+    pub feed_mode: FeedMode,
+    #[serde(rename = "symbol", default, skip_serializing_if = "Vec::is_empty")]
     pub symbols: Vec<String>,
-    #[serde(rename = "blacklisted_symbols")]
+    #[serde(rename = "blacklisted_symbols", default, skip_serializing_if = "Vec::is_empty")]
     pub blacklisted_symbols: Vec<String>,
+
     #[serde(rename = "Aggregator")]
     pub aggregator: AggregatorSettings,
+    #[serde(rename = "GapScan", default)]
+    pub gap_scan: GapScanSettings,
+    #[serde(rename = "Shutdown", default)]
+    pub shutdown: ShutdownSettings,
+    #[serde(rename = "Spool", default)]
+    pub spool: SpoolSettings,
+    /// Backends candles are written to, all of them; connection details stay in
+    /// `database` and `clickhouse`
+    #[serde(rename = "Sinks", default, skip_serializing_if = "Vec::is_empty")]
+    pub sinks: Vec<SinkSettings>,
     #[serde(rename = "Streaming")]
     pub streaming: StreamingConfig,
     #[serde(rename = "Debug")]
@@ -87,13 +263,223 @@ pub struct AppSettings {
     pub clickhouse: ClickHouseConfig,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl AppSettings {
+    /// Configured exchange sections, falling back to the top-level single-exchange fields
+    pub fn exchange_sections(&self) -> Vec<ExchangeSettings> {
+        let mut sections = if !self.exchanges.is_empty() {
+            self.exchanges.clone()
+        } else if !self.exchange.is_empty() {
+            vec![ExchangeSettings {
+                exchange: self.exchange.clone(),
+                instance: self.instance.clone(),
+                refresh_seconds: self.refresh_seconds,
+                feed_mode: self.feed_mode,
+                intervals: default_intervals(),
+                symbols: self.symbols.clone(),
+                blacklisted_symbols: self.blacklisted_symbols.clone(),
+                symbol_rules: SymbolRules::default(),
+            }]
+        } else {
+            Vec::new()
+        };
+
+        for section in &mut sections {
+            section.exchange = section.exchange.to_lowercase();
+            if section.instance.is_empty() {
+                section.instance = section.exchange.clone();
+            }
+        }
+        sections
+    }
+
+    /// Enabled sinks, falling back to ClickHouse when `clickhouse.enabled` is set and Postgres otherwise
+    pub fn sink_settings(&self) -> Vec<SinkSettings> {
+        if self.sinks.is_empty() {
+            let kind = if self.clickhouse.enabled { SinkKind::Clickhouse } else { SinkKind::Postgres };
+            return vec![SinkSettings::new(kind)];
+        }
+        self.sinks.iter().filter(|s| s.enabled).cloned().collect()
+    }
+
+    /// Section for `exchange`, or the first one when none is given
+    pub fn exchange_section(&self, exchange: Option<&str>) -> Option<ExchangeSettings> {
+        let sections = self.exchange_sections();
+        match exchange {
+            Some(name) => sections.into_iter().find(|s| s.exchange.eq_ignore_ascii_case(name)),
+            None => sections.into_iter().next(),
+        }
+    }
+}
+
+/// Periodic check of stored candles for missing minutes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GapScanSettings {
+    #[serde(rename = "Enabled")]
+    pub enabled: bool,
+    #[serde(rename = "IntervalMinutes")]
+    pub interval_minutes: u64,
+    #[serde(rename = "LookbackMinutes")]
+    pub lookback_minutes: i64,
+    /// Fetch missing candles from the exchange instead of only reporting them
+    #[serde(rename = "Repair")]
+    pub repair: bool,
+}
+
+impl Default for GapScanSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_minutes: 30,
+            lookback_minutes: 180,
+            repair: true,
+        }
+    }
+}
+
+/// What the collectors do between a stop signal and exiting
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShutdownSettings {
+    /// How long the last flush and pending writes may take before the process exits anyway
+    #[serde(rename = "TimeoutSeconds")]
+    pub timeout_seconds: u64,
+}
+
+impl Default for ShutdownSettings {
+    fn default() -> Self {
+        Self { timeout_seconds: 30 }
+    }
+}
+
+/// One storage backend candles are written to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SinkSettings {
+    #[serde(rename = "Type")]
+    pub kind: SinkKind,
+    #[serde(rename = "Enabled", default = "default_true")]
+    pub enabled: bool,
+    /// Retries of a failed write when the spool is off
+    #[serde(rename = "Retries", default = "default_sink_retries")]
+    pub retries: u32,
+    /// Wait before the first retry, doubled per retry
+    #[serde(rename = "RetryBackoffMillis", default = "default_sink_retry_backoff_millis")]
+    pub retry_backoff_millis: u64,
+}
+
+impl SinkSettings {
+    pub fn new(kind: SinkKind) -> Self {
+        Self {
+            kind,
+            enabled: true,
+            retries: default_sink_retries(),
+            retry_backoff_millis: default_sink_retry_backoff_millis(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkKind {
+    Postgres,
+    Clickhouse,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_sink_retries() -> u32 {
+    2
+}
+
+fn default_sink_retry_backoff_millis() -> u64 {
+    500
+}
+
+/// Local write-ahead log every candle batch goes through, so a storage outage loses nothing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpoolSettings {
+    #[serde(rename = "Enabled")]
+    pub enabled: bool,
+    /// Each sink spools into `<Directory>/<sink>`
+    #[serde(rename = "Directory")]
+    pub directory: String,
+    /// Oldest batches are evicted beyond this
+    #[serde(rename = "MaxMegabytes")]
+    pub max_megabytes: u64,
+    #[serde(rename = "SegmentMegabytes")]
+    pub segment_megabytes: u64,
+    /// First retry delay after a failed write, doubled per failure up to 5 minutes
+    #[serde(rename = "RetrySeconds")]
+    pub retry_seconds: u64,
+}
+
+impl Default for SpoolSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: "spool".to_string(),
+            max_megabytes: 512,
+            segment_megabytes: 16,
+            retry_seconds: 5,
+        }
+    }
+}
+
+/// How tickers reach the aggregator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedMode {
+    /// Poll the REST tickers endpoint every `RefreshSeconds`
+    #[default]
+    Polling,
+    /// Subscribe to the public WebSocket ticker channels
+    Websocket,
+    /// Subscribe to the public trade channels and build candles from every trade
+    Trades,
+    /// Pull closed 1m candles from the exchange's klines endpoint instead of building them
+    Native,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClickHouseConfig {
     pub enabled: bool,
     pub url: String,
     pub user: String,
     pub password: String,
     pub database: String,
+    /// Rows per INSERT statement, larger batches are split
+    #[serde(default = "default_clickhouse_max_rows")]
+    pub max_rows_per_insert: u64,
+    /// Uncompressed bytes per INSERT statement, larger batches are split
+    #[serde(default = "default_clickhouse_max_bytes")]
+    pub max_bytes_per_insert: u64,
+    /// Retries of a failed INSERT, resuming after the rows already committed
+    #[serde(default = "default_clickhouse_insert_retries")]
+    pub insert_retries: u32,
+    #[serde(default = "default_clickhouse_retry_backoff_millis")]
+    pub retry_backoff_millis: u64,
+    /// LZ4-compress inserts and query results
+    #[serde(default = "default_true")]
+    pub lz4: bool,
+}
+
+fn default_clickhouse_max_rows() -> u64 {
+    100_000
+}
+
+fn default_clickhouse_max_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_clickhouse_insert_retries() -> u32 {
+    3
+}
+
+fn default_clickhouse_retry_backoff_millis() -> u64 {
+    500
 }
 
 /// Global, thread-safe settings shared across the app.
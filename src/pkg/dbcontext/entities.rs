@@ -1,38 +1,100 @@
+use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use chrono::{DateTime, Utc};
+use log::debug;
 
-#[derive(Debug, Clone, FromRow)]
+use crate::pkg::exchanges::instrument::Instrument;
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct SymbolKlineData {
-    pub symbol: String,
+    pub exchange: String, // lowercase exchange name, resolves `symbol` to an Instrument
+    pub symbol: String,   // native symbol as the exchange names it
     pub interval: String,
     pub open: f64,
     pub high: f64,
     pub low: f64,
     pub close: f64,
     pub open_time: DateTime<Utc>,
-    pub volume: f64,
+    pub volume: f64,              // volume traded within the candle
+    pub volume_24h: Option<f64>,  // last rolling 24h volume snapshot, ticker feeds only
     pub trade_count: i64,
+    pub buy_volume: f64,  // taker buy volume, 0 for candles built from ticker snapshots
+    pub sell_volume: f64, // taker sell volume, 0 for candles built from ticker snapshots
+    pub is_synthetic: bool, // forward-filled minute without ticks: previous close as OHLC, zero volume
 }
 
-pub fn clean_symbol(raw: &str) -> String {
-    let symbol = raw.to_uppercase();
-
-    let suffixes = [
-        "-USDT-SWAP", "-USDT", "-USD-SWAP", "-USD", "-PERP", "-FUTURE", "-SWAP",
-    ];
+/// Canonical id (`BTC/USDT:USDT`) of a native symbol, used as the stored `symbol`.
+/// Symbols the exchange's naming rules don't cover are kept as the uppercased native symbol.
+pub fn canonical_symbol(exchange: &str, native: &str) -> String {
+    match Instrument::from_native(exchange, native) {
+        Ok(instrument) => instrument.canonical(),
+        Err(e) => {
+            debug!("Keeping native symbol {} on {}: {}", native, exchange, e);
+            native.to_uppercase()
+        }
+    }
+}
 
-    for suffix in suffixes.iter() {
-        if symbol.ends_with(suffix) {
-            return symbol.trim_end_matches(suffix).to_string();
+impl SymbolKlineData {
+    /// This candle written over `stored` with the merge update policy: max high, min low, this
+    /// close and summed volumes. A synthetic side never contributes, the real one is kept as is.
+    pub fn merged_into(&self, stored: &SymbolKlineData) -> SymbolKlineData {
+        if self.is_synthetic {
+            return stored.clone();
+        }
+        if stored.is_synthetic {
+            return self.clone();
+        }
+        SymbolKlineData {
+            open: stored.open,
+            high: stored.high.max(self.high),
+            low: stored.low.min(self.low),
+            close: self.close,
+            volume: stored.volume + self.volume,
+            volume_24h: self.volume_24h.or(stored.volume_24h),
+            trade_count: stored.trade_count + self.trade_count,
+            buy_volume: stored.buy_volume + self.buy_volume,
+            sell_volume: stored.sell_volume + self.sell_volume,
+            ..self.clone()
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let quote_assets = ["USDT", "USD", "BUSD", "USDC", "TUSD", "DAI", "USDT_UMCBL"];
-    for quote in quote_assets.iter() {
-        if symbol.ends_with(quote) {
-            return symbol.trim_end_matches(quote).to_string();
+    fn candle(open: f64, high: f64, low: f64, close: f64, volume: f64, is_synthetic: bool) -> SymbolKlineData {
+        SymbolKlineData {
+            exchange: "binance".to_string(),
+            symbol: "BTCUSDT".to_string(),
+            interval: "1m".to_string(),
+            open,
+            high,
+            low,
+            close,
+            open_time: DateTime::<Utc>::from_timestamp(60, 0).unwrap(),
+            volume,
+            volume_24h: None,
+            trade_count: 2,
+            buy_volume: volume,
+            sell_volume: 0.0,
+            is_synthetic,
         }
     }
 
-    symbol
-}
\ No newline at end of file
+    #[test]
+    fn merge_widens_the_range_takes_the_new_close_and_sums_volumes() {
+        let stored = candle(100.0, 105.0, 99.0, 104.0, 3.0, false);
+        let merged = candle(103.0, 108.0, 101.0, 102.0, 2.0, false).merged_into(&stored);
+
+        assert_eq!((merged.open, merged.high, merged.low, merged.close), (100.0, 108.0, 99.0, 102.0));
+        assert_eq!((merged.volume, merged.buy_volume, merged.trade_count), (5.0, 5.0, 4));
+
+        // A forward-filled candle neither replaces nor widens a real one, and is replaced by one
+        let synthetic = candle(104.0, 104.0, 104.0, 104.0, 0.0, true);
+        assert_eq!(synthetic.merged_into(&stored).high, 105.0);
+        let real = candle(103.0, 108.0, 101.0, 102.0, 2.0, false).merged_into(&synthetic);
+        assert_eq!((real.open, real.low, real.is_synthetic), (103.0, 101.0, false));
+    }
+}
@@ -1,10 +1,109 @@
-use crate::pkg::dbcontext::entities::{SymbolKlineData, clean_symbol};
+use crate::pkg::dbcontext::entities::{SymbolKlineData, canonical_symbol};
+use crate::pkg::dbcontext::migration::quote_ident;
+use crate::pkg::sink::UpdatePolicy;
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use log::info;
 use sqlx::{PgPool, Postgres, Transaction, postgres::PgQueryResult};
+use std::collections::HashMap;
 
+/// Candle table, `database.schema` / `database.table` in the config
+#[derive(Debug, Clone)]
+pub struct KlineTable {
+    pub schema: String,
+    pub name: String,
+}
+
+impl KlineTable {
+    pub fn new(schema: &str, name: &str) -> Self {
+        Self {
+            schema: schema.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    /// `"schema"."name"`, quoted for use in SQL
+    pub fn qualified(&self) -> String {
+        format!("{}.{}", quote_ident(&self.schema), quote_ident(&self.name))
+    }
+
+    /// Unlogged table COPY streams into before merging, `"schema"."name_staging"`
+    pub fn staging_table(&self) -> String {
+        format!("{}.{}", quote_ident(&self.schema), quote_ident(&format!("{}_staging", self.name)))
+    }
+
+    /// Continuous aggregate of the 1m candles for `interval`, `"schema"."name_5m"`
+    pub fn aggregate_view(&self, interval: &str) -> String {
+        format!("{}.{}", quote_ident(&self.schema), quote_ident(&format!("{}_{}", self.name, interval)))
+    }
+
+    /// Table recording the migrations applied to this one, `"schema"."name_migrations"`
+    pub fn migrations_table(&self) -> String {
+        format!("{}.{}", quote_ident(&self.schema), quote_ident(&format!("{}_migrations", self.name)))
+    }
+}
+
+// Conflict handling per update policy; the stored row is `k`. Merging never lets a forward-filled
+// candle in over a real one, and a real one replaces a forward-filled one outright.
+fn on_conflict(policy: UpdatePolicy) -> &'static str {
+    match policy {
+        UpdatePolicy::Ignore => " ON CONFLICT (exchange, symbol, interval, open_time) DO NOTHING",
+        UpdatePolicy::Overwrite => {
+            " ON CONFLICT (exchange, symbol, interval, open_time) DO UPDATE SET \
+            open = EXCLUDED.open, high = EXCLUDED.high, low = EXCLUDED.low, close = EXCLUDED.close, \
+            volume = EXCLUDED.volume, volume_24h = EXCLUDED.volume_24h, trade_count = EXCLUDED.trade_count, \
+            buy_volume = EXCLUDED.buy_volume, sell_volume = EXCLUDED.sell_volume, \
+            is_synthetic = EXCLUDED.is_synthetic"
+        }
+        UpdatePolicy::Merge => {
+            " ON CONFLICT (exchange, symbol, interval, open_time) DO UPDATE SET \
+            open = CASE WHEN k.is_synthetic THEN EXCLUDED.open ELSE k.open END, \
+            high = CASE WHEN k.is_synthetic THEN EXCLUDED.high ELSE GREATEST(k.high, EXCLUDED.high) END, \
+            low = CASE WHEN k.is_synthetic THEN EXCLUDED.low ELSE LEAST(k.low, EXCLUDED.low) END, \
+            close = EXCLUDED.close, \
+            volume = k.volume + EXCLUDED.volume, \
+            volume_24h = COALESCE(EXCLUDED.volume_24h, k.volume_24h), \
+            trade_count = k.trade_count + EXCLUDED.trade_count, \
+            buy_volume = k.buy_volume + EXCLUDED.buy_volume, \
+            sell_volume = k.sell_volume + EXCLUDED.sell_volume, \
+            is_synthetic = FALSE \
+            WHERE NOT EXCLUDED.is_synthetic"
+        }
+    }
+}
+
+// Columns written per candle, in the order of the VALUES tuples and the COPY stream
+const KLINE_COLUMNS: &str = "exchange, symbol, native_symbol, interval, open, high, low, close, open_time, instance, \
+    volume, volume_24h, trade_count, buy_volume, sell_volume, is_synthetic";
+
+// Seconds from the Unix epoch to PostgreSQL's, 2000-01-01
+const PG_EPOCH_OFFSET_SECS: i64 = 946_684_800;
 
-pub async fn save_klines(pool: &PgPool, data: &[SymbolKlineData], instance: &str) -> Result<()> {
+/// Write candles, handling stored ones as `policy` says.
+/// Batches of at least `copy_min_rows` candles go through COPY (0 never uses it).
+pub async fn write_klines(
+    pool: &PgPool,
+    table: &KlineTable,
+    data: &[SymbolKlineData],
+    instance: &str,
+    policy: UpdatePolicy,
+    copy_min_rows: usize,
+) -> Result<()> {
+    if copy_min_rows > 0 && data.len() >= copy_min_rows {
+        copy_klines(pool, table, data, instance, policy).await
+    } else {
+        insert_klines(pool, table, data, instance, policy).await
+    }
+}
+
+/// Write candles with 100-row `INSERT ... VALUES` statements, in one transaction
+pub async fn insert_klines(
+    pool: &PgPool,
+    table: &KlineTable,
+    data: &[SymbolKlineData],
+    instance: &str,
+    policy: UpdatePolicy,
+) -> Result<()> {
     if data.is_empty() {
         info!("📭 No klines to insert for instance {}", instance);
         return Ok(());
@@ -15,7 +114,9 @@ pub async fn save_klines(pool: &PgPool, data: &[SymbolKlineData], instance: &str
         .iter()
         .map(|kline| {
             (
-                clean_symbol(&kline.symbol),
+                kline.exchange.clone(),
+                canonical_symbol(&kline.exchange, &kline.symbol),
+                kline.symbol.clone(),
                 kline.interval.clone(),
                 kline.open,
                 kline.high,
@@ -24,7 +125,11 @@ pub async fn save_klines(pool: &PgPool, data: &[SymbolKlineData], instance: &str
                 kline.open_time,
                 instance.to_string(), // moved outside loop chunk processing
                 kline.volume,
+                kline.volume_24h,
                 kline.trade_count,
+                kline.buy_volume,
+                kline.sell_volume,
+                kline.is_synthetic,
             )
         })
         .collect();
@@ -33,16 +138,16 @@ pub async fn save_klines(pool: &PgPool, data: &[SymbolKlineData], instance: &str
     let mut tx: Transaction<'_, Postgres> = pool.begin().await?;
 
     for chunk in params.chunks(batch_size) {
-        let mut query_builder = sqlx::QueryBuilder::<Postgres>::new(
-            "INSERT INTO \"Dev_SymbolKlineData\" \
-        (symbol, interval, open, high, low, close, open_time, instance, volume, trade_count) ",
-        );
+        let mut query_builder =
+            sqlx::QueryBuilder::<Postgres>::new(format!("INSERT INTO {} AS k ({}) ", table.qualified(), KLINE_COLUMNS));
 
         query_builder.push_values(
             chunk,
             |mut b,
              (
+                exchange,
                 symbol,
+                native_symbol,
                 interval,
                 open,
                 high,
@@ -51,9 +156,15 @@ pub async fn save_klines(pool: &PgPool, data: &[SymbolKlineData], instance: &str
                 open_time,
                 instance,
                 volume,
+                volume_24h,
                 trade_count,
+                buy_volume,
+                sell_volume,
+                is_synthetic,
             )| {
-                b.push_bind(symbol)
+                b.push_bind(exchange)
+                    .push_bind(symbol)
+                    .push_bind(native_symbol)
                     .push_bind(interval)
                     .push_bind(open)
                     .push_bind(high)
@@ -62,11 +173,15 @@ pub async fn save_klines(pool: &PgPool, data: &[SymbolKlineData], instance: &str
                     .push_bind(open_time)
                     .push_bind(instance)
                     .push_bind(volume)
-                    .push_bind(trade_count);
+                    .push_bind(volume_24h)
+                    .push_bind(trade_count)
+                    .push_bind(buy_volume)
+                    .push_bind(sell_volume)
+                    .push_bind(is_synthetic);
             },
         );
 
-        query_builder.push(" ON CONFLICT (symbol, interval, open_time) DO NOTHING");
+        query_builder.push(on_conflict(policy));
 
         // Borrow tx only for this statement
         {
@@ -84,3 +199,265 @@ pub async fn save_klines(pool: &PgPool, data: &[SymbolKlineData], instance: &str
     tx.commit().await?;
     Ok(())
 }
+
+/// Write candles by streaming them with binary COPY into the unlogged staging table and merging
+/// them into the candle table from there, in one transaction. Concurrent writers share the staging
+/// table: each merge only sees its own uncommitted rows, and takes them out before committing.
+pub async fn copy_klines(
+    pool: &PgPool,
+    table: &KlineTable,
+    data: &[SymbolKlineData],
+    instance: &str,
+    policy: UpdatePolicy,
+) -> Result<()> {
+    if data.is_empty() {
+        info!("📭 No klines to insert for instance {}", instance);
+        return Ok(());
+    }
+
+    let staging = table.staging_table();
+    let buf = encode_copy_binary(data, instance);
+    let mut tx: Transaction<'_, Postgres> = pool.begin().await?;
+
+    let mut copy = tx
+        .copy_in_raw(&format!("COPY {} ({}) FROM STDIN (FORMAT binary)", staging, KLINE_COLUMNS))
+        .await?;
+    for part in buf.chunks(1 << 20) {
+        copy.send(part).await?;
+    }
+    copy.finish().await?;
+
+    let merge = format!(
+        "WITH batch AS (DELETE FROM {} RETURNING {}) INSERT INTO {} AS k ({}) SELECT {} FROM batch{}",
+        staging,
+        KLINE_COLUMNS,
+        table.qualified(),
+        KLINE_COLUMNS,
+        KLINE_COLUMNS,
+        on_conflict(policy)
+    );
+    let result: PgQueryResult = sqlx::query(&merge).execute(&mut *tx).await?;
+    tx.commit().await?;
+
+    info!(
+        "✅ Saved {} klines for instance {} via COPY (attempted {})",
+        result.rows_affected(),
+        instance,
+        data.len()
+    );
+    Ok(())
+}
+
+// Helper: candles as a binary COPY stream, fields in KLINE_COLUMNS order. Prices travel as float8,
+// the merge casts them to the table's numeric columns.
+fn encode_copy_binary(data: &[SymbolKlineData], instance: &str) -> Vec<u8> {
+    fn text(buf: &mut Vec<u8>, value: &str) {
+        buf.extend_from_slice(&(value.len() as i32).to_be_bytes());
+        buf.extend_from_slice(value.as_bytes());
+    }
+    fn float(buf: &mut Vec<u8>, value: f64) {
+        buf.extend_from_slice(&8i32.to_be_bytes());
+        buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    let mut buf = Vec::with_capacity(19 + data.len() * 200 + 2);
+    buf.extend_from_slice(b"PGCOPY\n\xff\r\n\0");
+    buf.extend_from_slice(&0i32.to_be_bytes()); // flags
+    buf.extend_from_slice(&0i32.to_be_bytes()); // header extension length
+
+    for k in data {
+        buf.extend_from_slice(&16i16.to_be_bytes());
+        text(&mut buf, &k.exchange);
+        text(&mut buf, &canonical_symbol(&k.exchange, &k.symbol));
+        text(&mut buf, &k.symbol);
+        text(&mut buf, &k.interval);
+        float(&mut buf, k.open);
+        float(&mut buf, k.high);
+        float(&mut buf, k.low);
+        float(&mut buf, k.close);
+        buf.extend_from_slice(&8i32.to_be_bytes());
+        let micros = k.open_time.timestamp_micros() - PG_EPOCH_OFFSET_SECS * 1_000_000;
+        buf.extend_from_slice(&micros.to_be_bytes());
+        text(&mut buf, instance);
+        float(&mut buf, k.volume);
+        match k.volume_24h {
+            Some(v) => float(&mut buf, v),
+            None => buf.extend_from_slice(&(-1i32).to_be_bytes()),
+        }
+        buf.extend_from_slice(&8i32.to_be_bytes());
+        buf.extend_from_slice(&k.trade_count.to_be_bytes());
+        float(&mut buf, k.buy_volume);
+        float(&mut buf, k.sell_volume);
+        buf.extend_from_slice(&1i32.to_be_bytes());
+        buf.push(k.is_synthetic as u8);
+    }
+
+    buf.extend_from_slice(&(-1i16).to_be_bytes());
+    buf
+}
+
+/// Open times stored for one native symbol/interval of an exchange within [start, end], ascending
+pub async fn get_open_times(
+    pool: &PgPool,
+    table: &KlineTable,
+    exchange: &str,
+    symbol: &str,
+    interval: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Vec<DateTime<Utc>>> {
+    let query = format!(
+        "SELECT open_time FROM {} \
+        WHERE exchange = $1 AND symbol = $2 AND interval = $3 AND open_time >= $4 AND open_time <= $5 \
+        ORDER BY open_time",
+        table.qualified()
+    );
+    let open_times = sqlx::query_scalar::<_, DateTime<Utc>>(&query)
+    .bind(exchange)
+    .bind(canonical_symbol(exchange, symbol))
+    .bind(interval)
+    .bind(start)
+    .bind(end)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(open_times)
+}
+
+// Stored columns as `SymbolKlineData` fields: the native symbol (the canonical one for rows written
+// before native symbols were kept) and float prices
+const KLINE_SELECT: &str = "exchange, COALESCE(NULLIF(native_symbol, ''), symbol) AS symbol, interval, \
+    open::float8 AS open, high::float8 AS high, low::float8 AS low, close::float8 AS close, open_time, \
+    volume, volume_24h, trade_count, buy_volume, sell_volume, is_synthetic";
+
+/// Up to `limit` candles of one native symbol/interval within [start, end] opened after `after`, ascending
+#[allow(clippy::too_many_arguments)]
+pub async fn get_klines(
+    pool: &PgPool,
+    table: &KlineTable,
+    exchange: &str,
+    symbol: &str,
+    interval: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    after: Option<DateTime<Utc>>,
+    limit: usize,
+) -> Result<Vec<SymbolKlineData>> {
+    let query = format!(
+        "SELECT {} FROM {} \
+        WHERE exchange = $1 AND symbol = $2 AND interval = $3 AND open_time >= $4 AND open_time <= $5 \
+        AND ($6::timestamptz IS NULL OR open_time > $6) \
+        ORDER BY open_time LIMIT $7",
+        KLINE_SELECT,
+        table.qualified()
+    );
+    let klines = sqlx::query_as::<_, SymbolKlineData>(&query)
+        .bind(exchange)
+        .bind(canonical_symbol(exchange, symbol))
+        .bind(interval)
+        .bind(start)
+        .bind(end)
+        .bind(after)
+        .bind(limit as i64)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(klines)
+}
+
+/// The `count` most recent candles of one native symbol/interval, ascending
+pub async fn get_latest_klines(
+    pool: &PgPool,
+    table: &KlineTable,
+    exchange: &str,
+    symbol: &str,
+    interval: &str,
+    count: usize,
+) -> Result<Vec<SymbolKlineData>> {
+    let query = format!(
+        "SELECT {} FROM {} WHERE exchange = $1 AND symbol = $2 AND interval = $3 ORDER BY open_time DESC LIMIT $4",
+        KLINE_SELECT,
+        table.qualified()
+    );
+    let mut klines = sqlx::query_as::<_, SymbolKlineData>(&query)
+        .bind(exchange)
+        .bind(canonical_symbol(exchange, symbol))
+        .bind(interval)
+        .bind(count as i64)
+        .fetch_all(pool)
+        .await?;
+    klines.reverse();
+
+    Ok(klines)
+}
+
+/// Open time of the newest candle per native symbol of an exchange; symbols without candles are left out
+pub async fn get_last_open_times(
+    pool: &PgPool,
+    table: &KlineTable,
+    exchange: &str,
+    symbols: &[String],
+    interval: &str,
+) -> Result<HashMap<String, DateTime<Utc>>> {
+    let canonical: Vec<String> = symbols.iter().map(|s| canonical_symbol(exchange, s)).collect();
+    // One index lookup per symbol instead of a grouped scan
+    let query = format!(
+        "SELECT s.symbol, (SELECT max(open_time) FROM {} WHERE exchange = $1 AND symbol = s.symbol AND interval = $3) \
+        FROM unnest($2::text[]) AS s(symbol)",
+        table.qualified()
+    );
+    let rows: Vec<(String, Option<DateTime<Utc>>)> = sqlx::query_as(&query)
+        .bind(exchange)
+        .bind(&canonical)
+        .bind(interval)
+        .fetch_all(pool)
+        .await?;
+
+    let last: HashMap<String, DateTime<Utc>> =
+        rows.into_iter().filter_map(|(symbol, at)| at.map(|at| (symbol, at))).collect();
+    Ok(symbols
+        .iter()
+        .zip(&canonical)
+        .filter_map(|(native, canonical)| last.get(canonical).map(|at| (native.clone(), *at)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn copy_stream_has_header_fields_and_trailer() {
+        let kline = SymbolKlineData {
+            exchange: "binance".to_string(),
+            symbol: "BTCUSDT".to_string(),
+            interval: "1m".to_string(),
+            open: 1.0,
+            high: 2.0,
+            low: 0.5,
+            close: 1.5,
+            open_time: Utc.with_ymd_and_hms(2000, 1, 1, 0, 1, 0).unwrap(),
+            volume: 3.0,
+            volume_24h: None,
+            trade_count: 7,
+            buy_volume: 2.0,
+            sell_volume: 1.0,
+            is_synthetic: true,
+        };
+        let buf = encode_copy_binary(&[kline], "binance");
+
+        assert!(buf.starts_with(b"PGCOPY\n\xff\r\n\0\0\0\0\0\0\0\0\0"));
+        assert_eq!(&buf[19..21], &16i16.to_be_bytes());
+        assert!(buf.ends_with(&[0, 0, 0, 1, 1, 0xff, 0xff]));
+
+        // One minute after the PostgreSQL epoch, right after the four text fields and four prices
+        let canonical = canonical_symbol("binance", "BTCUSDT");
+        let texts = ["binance", canonical.as_str(), "BTCUSDT", "1m"].iter().map(|t| 4 + t.len()).sum::<usize>();
+        let at = 21 + texts + 4 * 12 + 4;
+        assert_eq!(&buf[at..at + 8], &60_000_000i64.to_be_bytes());
+        // NULL volume_24h
+        let at = at + 8 + 4 + "binance".len() + 12;
+        assert_eq!(&buf[at..at + 4], &(-1i32).to_be_bytes());
+    }
+}
@@ -1,85 +1,343 @@
-use sqlx::PgPool;
-
-pub async fn auto_migrate(pool: &PgPool) -> Result<(), sqlx::Error> {
-    // 1️⃣ Create the table if it does not exist
-    let create_table = r#"
-    CREATE TABLE IF NOT EXISTS "Dev_SymbolKlineData" (
-        id BIGSERIAL,
-        symbol TEXT NOT NULL,
-        interval TEXT NOT NULL DEFAULT '1m',
-        open NUMERIC(18,8) NOT NULL,
-        high NUMERIC(18,8) NOT NULL,
-        low NUMERIC(18,8) NOT NULL,
-        close NUMERIC(18,8) NOT NULL,
-        open_time TIMESTAMPTZ NOT NULL,
-        instance TEXT,
-        volume DOUBLE PRECISION NOT NULL,
-        trade_count BIGINT NOT NULL
-    );
-    "#;
-    sqlx::query(create_table).execute(pool).await?;
-
-    // 2️⃣ Drop old primary key or unique constraint if exists
-    let drop_pk = r#"
-    DO $$
-    BEGIN
-        IF EXISTS (
-            SELECT 1
-            FROM information_schema.table_constraints
-            WHERE table_name='Dev_SymbolKlineData'
-            AND constraint_type='PRIMARY KEY'
-        ) THEN
-            ALTER TABLE "Dev_SymbolKlineData" DROP CONSTRAINT "Dev_SymbolKlineData_pkey";
-        END IF;
-        
-        IF EXISTS (
-            SELECT 1
-            FROM information_schema.table_constraints
-            WHERE table_name='Dev_SymbolKlineData'
-            AND constraint_type='UNIQUE'
-            AND constraint_name='idx_symbol_interval_time'
-        ) THEN
-            ALTER TABLE "Dev_SymbolKlineData" DROP CONSTRAINT idx_symbol_interval_time;
-        END IF;
-    END$$;
-    "#;
-    sqlx::query(drop_pk).execute(pool).await?;
-
-    // 3️⃣ Add composite primary key (symbol, interval, open_time)
-    let add_pk = r#"
-    ALTER TABLE "Dev_SymbolKlineData"
-    ADD CONSTRAINT idx_symbol_interval_time PRIMARY KEY (symbol, interval, open_time);
-    "#;
-    sqlx::query(add_pk).execute(pool).await?;
-
-    // 4️⃣ Convert table into a hypertable (if not already)
-    let create_hypertable = r#"
-    DO $$
-    BEGIN
-        IF NOT EXISTS (
-            SELECT 1 FROM timescaledb_information.hypertables
-            WHERE hypertable_name = 'Dev_SymbolKlineData'
-        ) THEN
-            PERFORM create_hypertable(
-                '"Dev_SymbolKlineData"',
-                'open_time',
-                chunk_time_interval => INTERVAL '1 day',
-                migrate_data => true
-            );
-        END IF;
-    END$$;
-    "#;
-    sqlx::query(create_hypertable).execute(pool).await?;
-
-    // 5️⃣ Enable compression for historical chunks
-    let enable_compression = r#"
-    ALTER TABLE "Dev_SymbolKlineData"
-    SET (
-        timescaledb.compress,
-        timescaledb.compress_segmentby = 'symbol, interval'
-    );
-    "#;
-    sqlx::query(enable_compression).execute(pool).await?;
-
-    Ok(())
-}
\ No newline at end of file
+use crate::pkg::dbcontext::kline::KlineTable;
+use anyhow::{Result, bail};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use sqlx::{Executor, PgPool};
+
+/// One schema change of the candle table. Its steps are SQL templates: `{table}` is the
+/// schema-qualified table, `{table_literal}`, `{schema_literal}` and `{name_literal}` the same names
+/// as string literals, `{pk_name}` the primary key constraint and `{staging}` the COPY staging table. The checksum covers the
+/// templates, so the same migration applied to a dev and a prod table has the same checksum.
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    steps: &'static [&'static str],
+}
+
+/// Where a migration stands on a table
+#[derive(Debug, Clone, PartialEq)]
+pub enum MigrationState {
+    Pending,
+    Applied(DateTime<Utc>),
+    /// Applied, but its SQL changed since
+    Modified(DateTime<Utc>),
+    /// Applied by a newer build, this one doesn't know it
+    Unknown(DateTime<Utc>),
+}
+
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i32,
+    pub name: String,
+    pub state: MigrationState,
+}
+
+// Every step is written to also pass over tables created by the unversioned startup migration
+// that came before, so the first run against them only records the versions.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_kline_table",
+        steps: &[r#"
+        CREATE TABLE IF NOT EXISTS {table} (
+            id BIGSERIAL,
+            exchange TEXT NOT NULL DEFAULT '',
+            symbol TEXT NOT NULL,
+            native_symbol TEXT NOT NULL DEFAULT '',
+            interval TEXT NOT NULL DEFAULT '1m',
+            open NUMERIC(18,8) NOT NULL,
+            high NUMERIC(18,8) NOT NULL,
+            low NUMERIC(18,8) NOT NULL,
+            close NUMERIC(18,8) NOT NULL,
+            open_time TIMESTAMPTZ NOT NULL,
+            instance TEXT,
+            volume DOUBLE PRECISION NOT NULL,
+            volume_24h DOUBLE PRECISION,
+            trade_count BIGINT NOT NULL,
+            buy_volume DOUBLE PRECISION NOT NULL DEFAULT 0,
+            sell_volume DOUBLE PRECISION NOT NULL DEFAULT 0,
+            is_synthetic BOOLEAN NOT NULL DEFAULT FALSE
+        )"#],
+    },
+    Migration {
+        // Taker buy/sell split, raw 24h volume snapshot, forward fill and the canonical symbol model.
+        // Rows written before canonical symbols carry the old cleaned symbol; their exchange is
+        // taken from the instance.
+        version: 2,
+        name: "add_volume_and_symbol_columns",
+        steps: &[
+            r#"
+            ALTER TABLE {table}
+                ADD COLUMN IF NOT EXISTS buy_volume DOUBLE PRECISION NOT NULL DEFAULT 0,
+                ADD COLUMN IF NOT EXISTS sell_volume DOUBLE PRECISION NOT NULL DEFAULT 0,
+                ADD COLUMN IF NOT EXISTS volume_24h DOUBLE PRECISION,
+                ADD COLUMN IF NOT EXISTS is_synthetic BOOLEAN NOT NULL DEFAULT FALSE,
+                ADD COLUMN IF NOT EXISTS exchange TEXT NOT NULL DEFAULT '',
+                ADD COLUMN IF NOT EXISTS native_symbol TEXT NOT NULL DEFAULT ''"#,
+            r#"
+            UPDATE {table}
+            SET exchange = lower(coalesce(instance, '')), native_symbol = symbol
+            WHERE exchange = '' AND native_symbol = ''"#,
+        ],
+    },
+    Migration {
+        // Replace any older primary key, looked up by its columns since earlier schemas named it differently
+        version: 3,
+        name: "primary_key_exchange_symbol_interval_time",
+        steps: &[r#"
+        DO $$
+        DECLARE
+            pk_name TEXT;
+            pk_columns TEXT;
+        BEGIN
+            SELECT c.conname, string_agg(a.attname, ',' ORDER BY k.ord)
+            INTO pk_name, pk_columns
+            FROM pg_constraint c
+            CROSS JOIN LATERAL unnest(c.conkey) WITH ORDINALITY AS k(attnum, ord)
+            JOIN pg_attribute a ON a.attrelid = c.conrelid AND a.attnum = k.attnum
+            WHERE c.conrelid = {table_literal}::regclass AND c.contype = 'p'
+            GROUP BY c.conname;
+
+            IF pk_columns IS DISTINCT FROM 'exchange,symbol,interval,open_time' THEN
+                IF pk_name IS NOT NULL THEN
+                    EXECUTE format('ALTER TABLE %s DROP CONSTRAINT %I', {table_literal}::regclass, pk_name);
+                END IF;
+                ALTER TABLE {table}
+                ADD CONSTRAINT {pk_name} PRIMARY KEY (exchange, symbol, interval, open_time);
+            END IF;
+        END$$"#],
+    },
+    Migration {
+        version: 4,
+        name: "hypertable",
+        steps: &[r#"
+        DO $$
+        BEGIN
+            IF NOT EXISTS (
+                SELECT 1 FROM timescaledb_information.hypertables
+                WHERE hypertable_schema = {schema_literal} AND hypertable_name = {name_literal}
+            ) THEN
+                PERFORM create_hypertable(
+                    {table_literal},
+                    'open_time',
+                    chunk_time_interval => INTERVAL '1 day',
+                    migrate_data => true
+                );
+            END IF;
+        END$$"#],
+    },
+    Migration {
+        // Compression for historical chunks; can't be set again once chunks are compressed
+        version: 5,
+        name: "compression",
+        steps: &[r#"
+        ALTER TABLE {table}
+        SET (
+            timescaledb.compress,
+            timescaledb.compress_segmentby = 'exchange, symbol, interval'
+        )"#],
+    },
+    Migration {
+        // Bulk writes COPY into this table and merge from it, rows only live within a transaction
+        version: 6,
+        name: "copy_staging_table",
+        steps: &[r#"
+        CREATE UNLOGGED TABLE IF NOT EXISTS {staging} (
+            exchange TEXT NOT NULL,
+            symbol TEXT NOT NULL,
+            native_symbol TEXT NOT NULL,
+            interval TEXT NOT NULL,
+            open DOUBLE PRECISION NOT NULL,
+            high DOUBLE PRECISION NOT NULL,
+            low DOUBLE PRECISION NOT NULL,
+            close DOUBLE PRECISION NOT NULL,
+            open_time TIMESTAMPTZ NOT NULL,
+            instance TEXT,
+            volume DOUBLE PRECISION NOT NULL,
+            volume_24h DOUBLE PRECISION,
+            trade_count BIGINT NOT NULL,
+            buy_volume DOUBLE PRECISION NOT NULL,
+            sell_volume DOUBLE PRECISION NOT NULL,
+            is_synthetic BOOLEAN NOT NULL
+        )"#],
+    },
+];
+
+/// Migrations this build ships, by version
+pub fn migrations() -> &'static [Migration] {
+    MIGRATIONS
+}
+
+impl Migration {
+    /// Hex SHA-256 of the migration's SQL templates
+    pub fn checksum(&self) -> String {
+        let mut hasher = Sha256::new();
+        for step in self.steps {
+            hasher.update(step.as_bytes());
+            hasher.update([0]);
+        }
+        hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// SQL of the migration for `table`, one statement per step
+    pub fn render(&self, table: &KlineTable) -> Vec<String> {
+        self.steps
+            .iter()
+            .map(|step| {
+                step.replace("{table_literal}", &sql_literal(&table.qualified()))
+                    .replace("{schema_literal}", &sql_literal(&table.schema))
+                    .replace("{name_literal}", &sql_literal(&table.name))
+                    .replace("{pk_name}", &quote_ident(&format!("{}_pkey", table.name)))
+                    .replace("{staging}", &table.staging_table())
+                    .replace("{table}", &table.qualified())
+            })
+            .collect()
+    }
+}
+
+/// State of every shipped migration on `table`, plus versions recorded by a newer build
+pub async fn migration_status(pool: &PgPool, table: &KlineTable) -> Result<Vec<MigrationStatus>> {
+    let versions_table = table.migrations_table();
+    let exists: Option<String> = sqlx::query_scalar("SELECT to_regclass($1)::text")
+        .bind(&versions_table)
+        .fetch_one(pool)
+        .await?;
+    let applied: Vec<(i32, String, String, DateTime<Utc>)> = if exists.is_some() {
+        sqlx::query_as(&format!(
+            "SELECT version, name, checksum, applied_at FROM {} ORDER BY version",
+            versions_table
+        ))
+        .fetch_all(pool)
+        .await?
+    } else {
+        Vec::new()
+    };
+
+    let mut status: Vec<MigrationStatus> = MIGRATIONS
+        .iter()
+        .map(|m| {
+            let state = match applied.iter().find(|(v, ..)| *v == m.version) {
+                Some((_, _, checksum, at)) if *checksum == m.checksum() => MigrationState::Applied(*at),
+                Some((_, _, _, at)) => MigrationState::Modified(*at),
+                None => MigrationState::Pending,
+            };
+            MigrationStatus {
+                version: m.version,
+                name: m.name.to_string(),
+                state,
+            }
+        })
+        .collect();
+    for (version, name, _, at) in applied {
+        if !MIGRATIONS.iter().any(|m| m.version == version) {
+            status.push(MigrationStatus {
+                version,
+                name,
+                state: MigrationState::Unknown(at),
+            });
+        }
+    }
+    Ok(status)
+}
+
+/// Apply pending migrations to `table` in version order, each in its own transaction together
+/// with its version row. Fails without applying anything if an applied migration was modified.
+/// Returns how many were applied.
+pub async fn run_migrations(pool: &PgPool, table: &KlineTable) -> Result<usize> {
+    let status = migration_status(pool, table).await?;
+    if let Some(modified) = status.iter().find(|s| matches!(s.state, MigrationState::Modified(_))) {
+        bail!(
+            "Migration {} ({}) was applied to {} with different SQL, refusing to migrate",
+            modified.version,
+            modified.name,
+            table.qualified()
+        );
+    }
+    for unknown in status.iter().filter(|s| matches!(s.state, MigrationState::Unknown(_))) {
+        warn!("⚠️ Migration {} ({}) on {} is from a newer build", unknown.version, unknown.name, table.qualified());
+    }
+    if status.iter().all(|s| s.state != MigrationState::Pending) {
+        return Ok(0);
+    }
+
+    let versions_table = table.migrations_table();
+    sqlx::query(&format!("CREATE SCHEMA IF NOT EXISTS {}", quote_ident(&table.schema)))
+        .execute(pool)
+        .await?;
+    sqlx::query(&format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            checksum TEXT NOT NULL,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        )",
+        versions_table
+    ))
+    .execute(pool)
+    .await?;
+
+    let mut applied = 0;
+    for migration in MIGRATIONS {
+        let mut tx = pool.begin().await?;
+        // Another instance starting at the same time waits here, then sees the version recorded
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+            .bind(&versions_table)
+            .execute(&mut *tx)
+            .await?;
+        let done: Option<i32> = sqlx::query_scalar(&format!("SELECT version FROM {} WHERE version = $1", versions_table))
+            .bind(migration.version)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if done.is_some() {
+            continue;
+        }
+
+        info!("🛠️ Applying migration {} ({}) to {}", migration.version, migration.name, table.qualified());
+        for sql in migration.render(table) {
+            (&mut *tx).execute(sql.as_str()).await?;
+        }
+        sqlx::query(&format!("INSERT INTO {} (version, name, checksum) VALUES ($1, $2, $3)", versions_table))
+            .bind(migration.version)
+            .bind(migration.name)
+            .bind(migration.checksum())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        applied += 1;
+    }
+    Ok(applied)
+}
+
+// Helper: double-quoted SQL identifier
+pub(crate) fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+// Helper: single-quoted SQL string literal
+fn sql_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_ascend_and_templates_render_for_any_table() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as i32 + 1);
+        }
+
+        let table = KlineTable::new("prod", "SymbolKlineData");
+        let sql = MIGRATIONS[2].render(&table).concat();
+        assert!(sql.contains(r#"'"prod"."SymbolKlineData"'::regclass"#));
+        assert!(sql.contains(r#"ADD CONSTRAINT "SymbolKlineData_pkey""#));
+        for placeholder in ["{table}", "{table_literal}", "{schema_literal}", "{name_literal}", "{pk_name}", "{staging}"] {
+            assert!(MIGRATIONS.iter().flat_map(|m| m.render(&table)).all(|sql| !sql.contains(placeholder)));
+        }
+
+        let checksums: std::collections::HashSet<_> = MIGRATIONS.iter().map(|m| m.checksum()).collect();
+        assert_eq!(checksums.len(), MIGRATIONS.len());
+    }
+}
@@ -1,3 +1,4 @@
 pub mod entities;
 pub mod kline;
 pub mod migration;
+pub mod timescale;
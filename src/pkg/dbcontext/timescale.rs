@@ -0,0 +1,200 @@
+use crate::pkg::aggregator::intervals::interval_to_ms;
+use crate::pkg::config::TimescaleSettings;
+use crate::pkg::dbcontext::kline::KlineTable;
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use sqlx::PgPool;
+
+// The refresh policy runs every bucket, but at least this often, so daily aggregates don't lag
+// a day behind their bucket closing
+const MAX_REFRESH_SCHEDULE_SECS: i64 = 3600;
+
+/// Bring the compression and retention policies and the continuous aggregates of `table` in line
+/// with `settings`. Runs after the migrations; unchanged policies and existing aggregates are left
+/// as they are. Aggregates no longer configured are kept, drop them by hand.
+pub async fn apply_timescale_settings(pool: &PgPool, table: &KlineTable, settings: &TimescaleSettings) -> Result<()> {
+    sync_policy(pool, table, Policy::Compression, settings.compress_after_days).await?;
+    sync_policy(pool, table, Policy::Retention, settings.drop_after_days).await?;
+    for interval in &settings.continuous_aggregates {
+        create_aggregate(pool, table, interval, settings.refresh_lookback_buckets).await?;
+    }
+    Ok(())
+}
+
+/// Recompute the continuous aggregates of `intervals` for the 1m candles opened in [start, end],
+/// widened to whole buckets. Never reaches back past `drop_after_days`: refreshing over dropped
+/// 1m chunks would delete the aggregate rows built from them.
+pub async fn refresh_aggregates(
+    pool: &PgPool,
+    table: &KlineTable,
+    settings: &TimescaleSettings,
+    intervals: &[String],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<()> {
+    let retained_from = (settings.drop_after_days > 0)
+        .then(|| Utc::now().timestamp_millis() - settings.drop_after_days as i64 * 86_400_000);
+
+    for interval in intervals {
+        let bucket_ms = interval_to_ms(interval).ok_or_else(|| anyhow!("unknown aggregate interval {}", interval))?;
+        let Some((from_ms, to_ms)) = refresh_window(start, end, bucket_ms, retained_from) else {
+            warn!("⚠️ Not refreshing {} before the retention window, its 1m candles are dropped", interval);
+            continue;
+        };
+        let view = table.aggregate_view(interval);
+        let (from, to) = (millis(from_ms)?, millis(to_ms)?);
+        info!("🔁 Refreshing {} from {} to {}", view, from, to);
+        sqlx::query("CALL refresh_continuous_aggregate($1::text::regclass, $2, $3)")
+            .bind(&view)
+            .bind(from)
+            .bind(to)
+            .execute(pool)
+            .await?;
+    }
+    Ok(())
+}
+
+// Helper: whole buckets covering 1m candles opened in [start, end], starting no earlier than the
+// first whole bucket after `retained_from`; None when nothing of it is retained
+fn refresh_window(start: DateTime<Utc>, end: DateTime<Utc>, bucket_ms: i64, retained_from: Option<i64>) -> Option<(i64, i64)> {
+    let mut from = start.timestamp_millis() - start.timestamp_millis().rem_euclid(bucket_ms);
+    let last_minute_end = end.timestamp_millis() + 60_000;
+    let to = last_minute_end + (bucket_ms - last_minute_end.rem_euclid(bucket_ms)) % bucket_ms;
+    if let Some(retained) = retained_from {
+        from = from.max(retained + (bucket_ms - retained.rem_euclid(bucket_ms)) % bucket_ms);
+    }
+    (from < to).then_some((from, to))
+}
+
+fn millis(ms: i64) -> Result<DateTime<Utc>> {
+    DateTime::<Utc>::from_timestamp_millis(ms).ok_or_else(|| anyhow!("timestamp out of range: {}", ms))
+}
+
+#[derive(Clone, Copy)]
+enum Policy {
+    Compression,
+    Retention,
+}
+
+impl Policy {
+    // (job proc, config key, add function, remove function)
+    fn functions(self) -> (&'static str, &'static str, &'static str, &'static str) {
+        match self {
+            Policy::Compression => ("policy_compression", "compress_after", "add_compression_policy", "remove_compression_policy"),
+            Policy::Retention => ("policy_retention", "drop_after", "add_retention_policy", "remove_retention_policy"),
+        }
+    }
+}
+
+// Helper: replace the table's policy when its age differs from `days`, remove it for 0
+async fn sync_policy(pool: &PgPool, table: &KlineTable, policy: Policy, days: u32) -> Result<()> {
+    let (proc_name, key, add, remove) = policy.functions();
+    let current: Option<bool> = sqlx::query_scalar(&format!(
+        "SELECT (config->>'{}')::interval = make_interval(days => $1) FROM timescaledb_information.jobs \
+        WHERE proc_name = $2 AND hypertable_schema = $3 AND hypertable_name = $4",
+        key
+    ))
+    .bind(days as i32)
+    .bind(proc_name)
+    .bind(&table.schema)
+    .bind(&table.name)
+    .fetch_optional(pool)
+    .await?;
+
+    match (current, days) {
+        (None, 0) | (Some(true), _) => return Ok(()),
+        (Some(false), _) => {
+            sqlx::query(&format!("SELECT {}($1::text::regclass, if_exists => true)", remove))
+                .bind(table.qualified())
+                .execute(pool)
+                .await?;
+        }
+        (None, _) => {}
+    }
+    if days > 0 {
+        sqlx::query(&format!("SELECT {}($1::text::regclass, make_interval(days => $2))", add))
+            .bind(table.qualified())
+            .bind(days as i32)
+            .execute(pool)
+            .await?;
+        info!("🗜️ {} on {} set to {} days", proc_name, table.qualified(), days);
+    } else {
+        info!("🗜️ {} removed from {}", proc_name, table.qualified());
+    }
+    Ok(())
+}
+
+// Helper: continuous aggregate of the 1m candles for `interval` with its refresh policy
+async fn create_aggregate(pool: &PgPool, table: &KlineTable, interval: &str, lookback_buckets: u32) -> Result<()> {
+    let bucket_ms = interval_to_ms(interval).ok_or_else(|| anyhow!("unknown aggregate interval {}", interval))?;
+    if bucket_ms <= 60_000 {
+        bail!("continuous aggregate interval {} must be longer than 1m", interval);
+    }
+    let bucket_secs = bucket_ms / 1000;
+    let view = table.aggregate_view(interval);
+
+    sqlx::query(&format!(
+        "CREATE MATERIALIZED VIEW IF NOT EXISTS {view}
+        WITH (timescaledb.continuous) AS
+        SELECT exchange, symbol, native_symbol,
+            time_bucket(INTERVAL '{bucket_secs} seconds', open_time) AS open_time,
+            first(open, open_time) AS open,
+            max(high) AS high,
+            min(low) AS low,
+            last(close, open_time) AS close,
+            sum(volume) AS volume,
+            last(volume_24h, open_time) AS volume_24h,
+            sum(trade_count) AS trade_count,
+            sum(buy_volume) AS buy_volume,
+            sum(sell_volume) AS sell_volume,
+            bool_and(is_synthetic) AS is_synthetic
+        FROM {table}
+        WHERE interval = '1m'
+        GROUP BY exchange, symbol, native_symbol, time_bucket(INTERVAL '{bucket_secs} seconds', open_time)
+        WITH NO DATA",
+        table = table.qualified()
+    ))
+    .execute(pool)
+    .await?;
+
+    // Replaced on every start so changes to `refreshLookbackBuckets` take effect
+    sqlx::query("SELECT remove_continuous_aggregate_policy($1::text::regclass, if_exists => true)")
+        .bind(&view)
+        .execute(pool)
+        .await?;
+    sqlx::query(
+        "SELECT add_continuous_aggregate_policy($1::text::regclass, \
+        start_offset => make_interval(secs => $2), end_offset => make_interval(secs => $3), \
+        schedule_interval => make_interval(secs => $4))",
+    )
+    .bind(&view)
+    // The refreshed window has to span at least two buckets after the one-bucket end offset
+    .bind((bucket_secs * lookback_buckets.max(3) as i64) as f64)
+    .bind(bucket_secs as f64)
+    .bind(bucket_secs.min(MAX_REFRESH_SCHEDULE_SECS) as f64)
+    .execute(pool)
+    .await?;
+
+    info!("📊 Continuous aggregate {} ready", view);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refresh_window_covers_whole_buckets_inside_retention() {
+        let at = |ms| DateTime::<Utc>::from_timestamp_millis(ms).unwrap();
+        let hour = 3_600_000;
+
+        // 1m candles 10:05..=10:59 refresh the 10:00 hour, 10:05..=11:00 the 11:00 one too
+        assert_eq!(refresh_window(at(10 * hour + 300_000), at(11 * hour - 60_000), hour, None), Some((10 * hour, 11 * hour)));
+        assert_eq!(refresh_window(at(10 * hour + 300_000), at(11 * hour), hour, None), Some((10 * hour, 12 * hour)));
+
+        // Buckets partly dropped by retention are left alone
+        assert_eq!(refresh_window(at(8 * hour), at(11 * hour - 60_000), hour, Some(9 * hour + 1)), Some((10 * hour, 11 * hour)));
+        assert_eq!(refresh_window(at(8 * hour), at(9 * hour), hour, Some(10 * hour)), None);
+    }
+}
@@ -1,9 +1,28 @@
-use crate::pkg::exchanges::binance::rate_limited_client::RateLimitedClient;
-use crate::pkg::exchanges::exchange_entities::BinanceTickerInfo;
-use crate::pkg::exchanges::exchange_entities::TickerInfo;
+use crate::pkg::aggregator::intervals::interval_to_ms;
+use crate::pkg::dbcontext::entities::SymbolKlineData;
+use crate::pkg::exchanges::binance::rate_limit::BinanceRateLimit;
+use crate::pkg::exchanges::rate_limited_client::RateLimitedClient;
+use crate::pkg::exchanges::exchange_client::{kline_open_time, listing_time, retain_closed};
+use crate::pkg::exchanges::exchange_entities::{
+    BinanceExchangeInfo, BinanceKline, BinanceSymbolFilter, BinanceTickerInfo, BinanceWsAggTrade, BinanceWsTicker,
+    InstrumentInfo, InstrumentStatus,
+};
+use crate::pkg::exchanges::binance::symbols;
+use crate::pkg::exchanges::instrument::{Instrument, InstrumentKind};
+use crate::pkg::exchanges::exchange_entities::{TickerInfo, TradeData, TradeSide};
 use crate::pkg::exchanges::exchange::ExchangeApi;
+use crate::pkg::exchanges::ws_client::{WsSubscription, run_ws_streams};
 use anyhow::{Result, anyhow};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const EXCHANGE: &str = "binance";
+const WS_URL: &str = "wss://fstream.binance.com/ws";
+// Binance futures allows at most 200 streams per connection
+const WS_MAX_STREAMS: usize = 200;
+const KLINES_LIMIT: usize = 1500;
 
 pub struct BinanceApi {
     client: RateLimitedClient,
@@ -12,11 +31,17 @@ pub struct BinanceApi {
 impl BinanceApi {
     pub fn new() -> Self {
         Self {
-            client: RateLimitedClient::new(None),
+            client: RateLimitedClient::new(None, BinanceRateLimit),
         }
     }
 }
 
+impl Default for BinanceApi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[async_trait]
 impl ExchangeApi for BinanceApi {
     async fn get_all_tickers(&self) -> Result<Vec<TickerInfo>> {
@@ -26,7 +51,7 @@ impl ExchangeApi for BinanceApi {
             .timeout(std::time::Duration::from_secs(10))
             .build()?;
 
-        let resp = self.client.send_with_retry(req, 1).await?;
+        let resp = self.client.send_with_retry(req).await?;
         let status = resp.status();
         let bytes = resp.bytes().await?;
 
@@ -43,38 +68,205 @@ impl ExchangeApi for BinanceApi {
                 symbol: b.symbol,
                 last_price: b.last_price,
                 vol_24h: Some(b.volume),
+                timestamp: b.close_time,
             })
             .collect();
 
         Ok(standard_tickers)
     }
 
+    async fn get_instruments(&self) -> Result<Vec<InstrumentInfo>> {
+        let url = "https://fapi.binance.com/fapi/v1/exchangeInfo";
+        let req = reqwest::Client::new()
+            .get(url)
+            .timeout(std::time::Duration::from_secs(10))
+            .build()?;
 
-    /*fn name(&self) -> &str {
-        "Binance"
-    }*/
+        let resp = self.client.send_with_retry(req).await?;
+        let status = resp.status();
+        let bytes = resp.bytes().await?;
 
-    /*  pub async fn get_ticker_info(&self, symbol: &str) -> Result<TickerInfo> {
+        if !status.is_success() {
+            let body = String::from_utf8_lossy(&bytes);
+            return Err(anyhow!("Non-200 response: {} - {}", status.as_u16(), body));
+        }
+
+        let info: BinanceExchangeInfo = serde_json::from_slice(&bytes)?;
+
+        let instruments = info
+            .symbols
+            .into_iter()
+            .map(|s| {
+                let mut tick_size = 0.0;
+                let mut lot_size = 0.0;
+                for filter in &s.filters {
+                    match filter {
+                        BinanceSymbolFilter::Price { tick_size: t } => tick_size = t.parse().unwrap_or(0.0),
+                        BinanceSymbolFilter::LotSize { step_size } => lot_size = step_size.parse().unwrap_or(0.0),
+                        BinanceSymbolFilter::Other => {}
+                    }
+                }
+
+                let kind = if s.contract_type == "PERPETUAL" {
+                    InstrumentKind::Perpetual
+                } else {
+                    InstrumentKind::DatedFuture
+                };
+                InstrumentInfo {
+                    instrument: Instrument {
+                        exchange: EXCHANGE.to_string(),
+                        expiry: symbols::parse(&s.symbol).ok().and_then(|i| i.expiry),
+                        base: s.base_asset,
+                        quote: s.quote_asset,
+                        settle: s.margin_asset,
+                        kind,
+                        native_symbol: s.symbol,
+                    },
+                    status: match s.status.as_str() {
+                        "TRADING" => InstrumentStatus::Trading,
+                        "PENDING_TRADING" => InstrumentStatus::PreLaunch,
+                        "CLOSE" | "DELIVERED" | "SETTLING" => InstrumentStatus::Delisted,
+                        _ => InstrumentStatus::Suspended,
+                    },
+                    tick_size,
+                    lot_size,
+                    contract_value: 1.0,
+                    listing_time: s.onboard_date.and_then(|ms| listing_time(&ms.to_string())),
+                }
+            })
+            .collect();
+
+        Ok(instruments)
+    }
+
+    async fn get_klines(
+        &self,
+        symbol: &str,
+        interval: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<SymbolKlineData>> {
+        let interval_ms = interval_to_ms(interval).ok_or_else(|| anyhow!("unsupported interval: {}", interval))?;
+        // Weight grows with `limit`, so ask for no more candles than the range holds
+        let candles = (end.timestamp_millis() - start.timestamp_millis()).max(0) / interval_ms + 1;
+        let limit = (candles as usize).min(KLINES_LIMIT);
         let url = format!(
-            "https://fapi.binance.com/fapi/v1/ticker/24hr?symbol={}",
-            symbol
+            "https://fapi.binance.com/fapi/v1/klines?symbol={}&interval={}&startTime={}&endTime={}&limit={}",
+            symbol,
+            interval,
+            start.timestamp_millis(),
+            end.timestamp_millis(),
+            limit
         );
         let req = reqwest::Client::new()
             .get(&url)
-            .timeout(Duration::from_secs(10))
+            .timeout(std::time::Duration::from_secs(10))
             .build()?;
 
-        let ticker: TickerInfo = self
-            .client
-            .send_with_retry(req, 1)
-            .await
-            .context("Send failed")?
-            .error_for_status()
-            .context("Bad status")?
-            .json()
-            .await
-            .context("JSON parse failed")?;
-
-        Ok(ticker)
-    }  */
+        let resp = self.client.send_with_retry(req).await?;
+        let status = resp.status();
+        let bytes = resp.bytes().await?;
+
+        if !status.is_success() {
+            let body = String::from_utf8_lossy(&bytes);
+            return Err(anyhow!("Non-200 response: {} - {}", status.as_u16(), body));
+        }
+
+        let rows: Vec<BinanceKline> = serde_json::from_slice(&bytes)?;
+
+        let mut klines = rows
+            .into_iter()
+            .map(|r| {
+                let volume = r.volume.parse::<f64>()?;
+                let buy_volume = r.taker_buy_volume.parse::<f64>()?;
+                Ok(SymbolKlineData {
+                    exchange: EXCHANGE.to_string(),
+                    symbol: symbol.to_string(),
+                    interval: interval.to_string(),
+                    open: r.open.parse()?,
+                    high: r.high.parse()?,
+                    low: r.low.parse()?,
+                    close: r.close.parse()?,
+                    open_time: kline_open_time(r.open_time)?,
+                    volume,
+                    volume_24h: None,
+                    trade_count: r.trade_count,
+                    buy_volume,
+                    sell_volume: (volume - buy_volume).max(0.0),
+                    is_synthetic: false,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        retain_closed(&mut klines, interval_ms);
+        Ok(klines)
+    }
+
+    fn klines_page_limit(&self) -> usize {
+        KLINES_LIMIT
+    }
+
+    async fn stream_tickers(&self, symbols: &[String], tx: mpsc::Sender<TickerInfo>) -> Result<()> {
+        run_ws_streams("binance", ws_subscriptions(symbols, "ticker"), parse_ws_ticker, tx).await
+    }
+
+    async fn stream_trades(&self, symbols: &[String], tx: mpsc::Sender<TradeData>) -> Result<()> {
+        run_ws_streams("binance", ws_subscriptions(symbols, "aggTrade"), parse_ws_trade, tx).await
+    }
+}
+
+fn parse_ws_ticker(text: &str) -> Vec<TickerInfo> {
+    // Subscription acks ({"result":null,"id":1}) don't match and are skipped
+    match serde_json::from_str::<BinanceWsTicker>(text) {
+        Ok(t) => vec![TickerInfo {
+            symbol: t.symbol,
+            last_price: t.last_price,
+            vol_24h: Some(t.volume),
+            timestamp: t.event_time,
+        }],
+        Err(_) => Vec::new(),
+    }
+}
+
+/// One subscription per connection-sized chunk of symbols, for the given channel
+fn ws_subscriptions(symbols: &[String], channel: &str) -> Vec<WsSubscription> {
+    symbols
+        .chunks(WS_MAX_STREAMS)
+        .map(|chunk| {
+            let params: Vec<String> = chunk
+                .iter()
+                .map(|s| format!("{}@{}", s.to_lowercase(), channel))
+                .collect();
+            let subscribe = serde_json::json!({ "method": "SUBSCRIBE", "params": params, "id": 1 });
+
+            // Binance pings us, the client only has to answer with pongs
+            WsSubscription {
+                url: WS_URL.to_string(),
+                subscribe_messages: vec![subscribe.to_string()],
+                ping_message: None,
+                ping_interval: Duration::from_secs(180),
+            }
+        })
+        .collect()
+}
+
+fn parse_ws_trade(text: &str) -> Vec<TradeData> {
+    let t = match serde_json::from_str::<BinanceWsAggTrade>(text) {
+        Ok(t) => t,
+        Err(_) => return Vec::new(),
+    };
+
+    match (t.price.parse::<f64>(), t.qty.parse::<f64>()) {
+        (Ok(price), Ok(qty)) => vec![TradeData {
+            symbol: t.symbol,
+            price,
+            qty,
+            // Buyer as maker means the aggressor sold
+            side: if t.buyer_is_maker { TradeSide::Sell } else { TradeSide::Buy },
+            trade_id: t.agg_trade_id.to_string(),
+            timestamp: t.trade_time,
+            count: (t.last_trade_id - t.first_trade_id + 1).max(1),
+        }],
+        _ => Vec::new(),
+    }
 }
@@ -1,2 +1,3 @@
 pub mod binance_api;
-pub mod rate_limited_client;
\ No newline at end of file
+pub mod rate_limit;
+pub mod symbols;
\ No newline at end of file
@@ -0,0 +1,56 @@
+use crate::pkg::exchanges::rate_limited_client::{Limit, Observed, RateLimitPolicy, header_num, query_param};
+use reqwest::Url;
+use reqwest::header::HeaderMap;
+use std::time::Duration;
+
+const WEIGHT_BUCKET: &str = "weight";
+// USDⓈ-M futures: 2400 request weight per minute per IP
+const WEIGHT_LIMIT: Limit = Limit::new(2400, Duration::from_secs(60));
+
+/// Binance futures: one IP-wide weight pool, reported back in `X-MBX-USED-WEIGHT-1M`
+pub struct BinanceRateLimit;
+
+impl RateLimitPolicy for BinanceRateLimit {
+    fn name(&self) -> &'static str {
+        "binance"
+    }
+
+    fn costs(&self, url: &Url) -> Vec<(String, u32)> {
+        let weight = match url.path() {
+            "/fapi/v1/klines" => {
+                let limit = query_param(url, "limit").and_then(|l| l.parse::<u32>().ok()).unwrap_or(500);
+                match limit {
+                    0..100 => 1,
+                    100..500 => 2,
+                    500..=1000 => 5,
+                    _ => 10,
+                }
+            }
+            // All symbols at once costs 40, a single symbol 1
+            "/fapi/v1/ticker/24hr" if query_param(url, "symbol").is_none() => 40,
+            _ => 1,
+        };
+        vec![(WEIGHT_BUCKET.to_string(), weight)]
+    }
+
+    fn hard_limit(&self, bucket: &str) -> Option<Limit> {
+        (bucket == WEIGHT_BUCKET).then_some(WEIGHT_LIMIT)
+    }
+
+    fn observe(&self, _url: &Url, headers: &HeaderMap) -> Vec<(String, Observed)> {
+        let Some(used) = header_num::<u32>(headers, "X-MBX-USED-WEIGHT-1M") else {
+            return Vec::new();
+        };
+        // The weight window is the calendar minute
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        let reset_in = Duration::from_millis((60_000 - now_ms.rem_euclid(60_000)) as u64);
+        vec![(
+            WEIGHT_BUCKET.to_string(),
+            Observed {
+                used,
+                limit: None,
+                reset_in: Some(reset_in),
+            },
+        )]
+    }
+}
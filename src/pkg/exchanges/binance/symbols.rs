@@ -0,0 +1,44 @@
+use crate::pkg::exchanges::instrument::{Instrument, InstrumentKind, split_quote};
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+
+// USDⓈ-M futures: BTCUSDT (perpetual), BTCUSDT_250926 (quarterly)
+
+pub fn parse(native: &str) -> Result<Instrument> {
+    let native = native.to_uppercase();
+    let (pair, expiry) = match native.split_once('_') {
+        Some((pair, date)) => (pair, Some(parse_expiry(date)?)),
+        None => (native.as_str(), None),
+    };
+    let (base, quote) = split_quote(pair).ok_or_else(|| anyhow!("unknown Binance symbol: {}", native))?;
+
+    Ok(Instrument {
+        exchange: "binance".to_string(),
+        base: base.to_string(),
+        quote: quote.to_string(),
+        settle: quote.to_string(),
+        kind: if expiry.is_some() {
+            InstrumentKind::DatedFuture
+        } else {
+            InstrumentKind::Perpetual
+        },
+        expiry,
+        native_symbol: native.clone(),
+    })
+}
+
+pub fn format(instrument: &Instrument) -> Result<String> {
+    if instrument.settle != instrument.quote {
+        return Err(anyhow!("Binance USDⓈ-M has no {}", instrument.canonical()));
+    }
+    let pair = format!("{}{}", instrument.base, instrument.quote);
+    match (instrument.kind, instrument.expiry) {
+        (InstrumentKind::Perpetual, _) => Ok(pair),
+        (InstrumentKind::DatedFuture, Some(expiry)) => Ok(format!("{}_{}", pair, expiry.format("%y%m%d"))),
+        _ => Err(anyhow!("Binance USDⓈ-M has no {}", instrument.canonical())),
+    }
+}
+
+fn parse_expiry(date: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%y%m%d").map_err(|_| anyhow!("invalid Binance expiry: {}", date))
+}
@@ -1,10 +1,31 @@
-use crate::pkg::exchanges::bitget::rate_limited_client::RateLimitedClient;
-use crate::pkg::exchanges::exchange_entities::BitgetTickerInfo;
-use crate::pkg::exchanges::exchange_entities::TickerInfo;
+use crate::pkg::aggregator::intervals::interval_to_ms;
+use crate::pkg::dbcontext::entities::SymbolKlineData;
+use crate::pkg::exchanges::bitget::rate_limit::BitgetRateLimit;
+use crate::pkg::exchanges::bitget::symbols;
+use crate::pkg::exchanges::instrument::{Instrument, InstrumentKind};
+use crate::pkg::exchanges::rate_limited_client::RateLimitedClient;
+use crate::pkg::exchanges::exchange_client::{kline_open_time, retain_closed};
+use crate::pkg::exchanges::exchange_entities::{
+    BitgetContract, BitgetTickerInfo, BitgetWsTicker, BitgetWsTradeMessage, InstrumentInfo, InstrumentStatus,
+    WsDataMessage,
+};
+use crate::pkg::exchanges::exchange_entities::{TickerInfo, TradeData, TradeSide};
 use crate::pkg::exchanges::exchange::ExchangeApi;
+use crate::pkg::exchanges::ws_client::{WsSubscription, run_ws_streams};
 use anyhow::{Result, anyhow};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const EXCHANGE: &str = "bitget";
+const WS_URL: &str = "wss://ws.bitget.com/v2/ws/public";
+// Bitget recommends fewer than 50 channels per connection
+const WS_SYMBOLS_PER_CONNECTION: usize = 50;
+// REST v1 symbols carry the product suffix, the v2 socket uses bare instIds
+const V1_SUFFIX: &str = "_UMCBL";
+const KLINES_LIMIT: usize = 1000;
 
 #[derive(Debug, Deserialize)]
 struct BitgetResponse<T> {
@@ -20,11 +41,17 @@ pub struct BitgetApi {
 impl BitgetApi {
     pub fn new() -> Self {
         Self {
-            client: RateLimitedClient::new(None),
+            client: RateLimitedClient::new(None, BitgetRateLimit),
         }
     }
 }
 
+impl Default for BitgetApi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 
 #[async_trait]
 impl ExchangeApi for BitgetApi {
@@ -35,7 +62,7 @@ impl ExchangeApi for BitgetApi {
             .timeout(std::time::Duration::from_secs(10))
             .build()?;
 
-        let resp = self.client.send_with_retry(req, 1).await?;
+        let resp = self.client.send_with_retry(req).await?;
         let status = resp.status();
         let bytes = resp.bytes().await?;
 
@@ -58,37 +85,234 @@ impl ExchangeApi for BitgetApi {
                 symbol: b.symbol,
                 last_price: b.last_price,
                 vol_24h: Some(b.base_volume),
+                timestamp: b.timestamp.and_then(|ts| ts.parse().ok()),
             })
             .collect();
 
         Ok(standard_tickers)
     }
 
-    /*fn name(&self) -> &str {
-        "Bitget"
-    }*/
+    async fn get_instruments(&self) -> Result<Vec<InstrumentInfo>> {
+        let url = "https://api.bitget.com/api/mix/v1/market/contracts?productType=umcbl";
+        let req = reqwest::Client::new()
+            .get(url)
+            .timeout(std::time::Duration::from_secs(10))
+            .build()?;
+
+        let resp = self.client.send_with_retry(req).await?;
+        let status = resp.status();
+        let bytes = resp.bytes().await?;
 
-    /*pub async fn get_ticker_info(&self, symbol: &str) -> Result<BitgetTickerInfo> {
+        if !status.is_success() {
+            let body = String::from_utf8_lossy(&bytes);
+            return Err(anyhow!("Non-200 response: {} - {}", status.as_u16(), body));
+        }
+
+        let parsed: BitgetResponse<Vec<BitgetContract>> = serde_json::from_slice(&bytes)?;
+
+        let instruments = parsed
+            .data
+            .into_iter()
+            .map(|b| {
+                // Tick size is priceEndStep at pricePlace decimals, e.g. 5 @ 1 => 0.5
+                let price_place: i32 = b.price_place.parse().unwrap_or(0);
+                let end_step: f64 = b.price_end_step.parse().unwrap_or(1.0);
+
+                InstrumentInfo {
+                    instrument: Instrument {
+                        exchange: EXCHANGE.to_string(),
+                        expiry: symbols::parse(&b.symbol).ok().and_then(|i| i.expiry),
+                        settle: b
+                            .support_margin_coins
+                            .first()
+                            .cloned()
+                            .unwrap_or_else(|| b.quote_coin.clone()),
+                        base: b.base_coin,
+                        quote: b.quote_coin,
+                        kind: if b.symbol_type == "delivery" {
+                            InstrumentKind::DatedFuture
+                        } else {
+                            InstrumentKind::Perpetual
+                        },
+                        native_symbol: b.symbol,
+                    },
+                    status: match b.symbol_status.as_str() {
+                        // Older payloads leave the status out for live contracts
+                        "normal" | "" => InstrumentStatus::Trading,
+                        "off" => InstrumentStatus::Delisted,
+                        _ => InstrumentStatus::Suspended,
+                    },
+                    tick_size: end_step * 10f64.powi(-price_place),
+                    lot_size: b.size_multiplier.parse().unwrap_or(0.0),
+                    contract_value: 1.0,
+                    // v1 contracts carry no listing time
+                    listing_time: None,
+                }
+            })
+            .collect();
+
+        Ok(instruments)
+    }
+
+    async fn get_klines(
+        &self,
+        symbol: &str,
+        interval: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<SymbolKlineData>> {
+        let interval_ms = interval_to_ms(interval).ok_or_else(|| anyhow!("unsupported interval: {}", interval))?;
         let url = format!(
-            "https://api.bitget.com/api/v2/market/ticker?symbol={}",
-            symbol
+            "https://api.bitget.com/api/mix/v1/market/candles?symbol={}&granularity={}&startTime={}&endTime={}&limit={}",
+            symbol,
+            bitget_granularity(interval)?,
+            start.timestamp_millis(),
+            end.timestamp_millis(),
+            KLINES_LIMIT
         );
         let req = reqwest::Client::new()
             .get(&url)
-            .timeout(Duration::from_secs(10))
+            .timeout(std::time::Duration::from_secs(10))
             .build()?;
 
-        let ticker: BitgetTickerInfo = self
-            .client
-            .send_with_retry(req, 1)
-            .await
-            .context("Send failed")?
-            .error_for_status()
-            .context("Bad status")?
-            .json()
-            .await
-            .context("JSON parse failed")?;
-
-        Ok(ticker)
-    }*/
+        let resp = self.client.send_with_retry(req).await?;
+        let status = resp.status();
+        let bytes = resp.bytes().await?;
+
+        if !status.is_success() {
+            let body = String::from_utf8_lossy(&bytes);
+            return Err(anyhow!(
+                "Non-200 response: {} - {}",
+                status.as_u16(),
+                body
+            ));
+        }
+
+        // [ts, open, high, low, close, base volume, quote volume]; v1 answers with a bare array
+        let rows: Vec<Vec<String>> = serde_json::from_slice(&bytes)
+            .or_else(|_| serde_json::from_slice::<BitgetResponse<Vec<Vec<String>>>>(&bytes).map(|r| r.data))?;
+
+        let mut klines = rows
+            .into_iter()
+            .filter(|r| r.len() >= 6)
+            .map(|r| {
+                Ok(SymbolKlineData {
+                    exchange: EXCHANGE.to_string(),
+                    symbol: symbol.to_string(),
+                    interval: interval.to_string(),
+                    open: r[1].parse()?,
+                    high: r[2].parse()?,
+                    low: r[3].parse()?,
+                    close: r[4].parse()?,
+                    open_time: kline_open_time(r[0].parse()?)?,
+                    volume: r[5].parse()?,
+                    volume_24h: None,
+                    trade_count: 0,
+                    buy_volume: 0.0,
+                    sell_volume: 0.0,
+                    is_synthetic: false,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        retain_closed(&mut klines, interval_ms);
+        Ok(klines)
+    }
+
+    fn klines_page_limit(&self) -> usize {
+        KLINES_LIMIT
+    }
+
+    async fn stream_tickers(&self, symbols: &[String], tx: mpsc::Sender<TickerInfo>) -> Result<()> {
+        run_ws_streams("bitget", ws_subscriptions(symbols, "ticker"), parse_ws_ticker, tx).await
+    }
+
+    async fn stream_trades(&self, symbols: &[String], tx: mpsc::Sender<TradeData>) -> Result<()> {
+        run_ws_streams("bitget", ws_subscriptions(symbols, "trade"), parse_ws_trade, tx).await
+    }
+}
+
+fn parse_ws_ticker(text: &str) -> Vec<TickerInfo> {
+    match serde_json::from_str::<WsDataMessage<BitgetWsTicker>>(text) {
+        Ok(msg) => msg
+            .data
+            .into_iter()
+            .map(|b| TickerInfo {
+                symbol: format!("{}{}", b.instrument_id, V1_SUFFIX),
+                last_price: b.last_price,
+                vol_24h: Some(b.base_volume),
+                timestamp: b.ts.and_then(|ts| ts.parse().ok()),
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// One subscription per connection-sized chunk of symbols, for the given channel
+fn ws_subscriptions(symbols: &[String], channel: &str) -> Vec<WsSubscription> {
+    symbols
+        .chunks(WS_SYMBOLS_PER_CONNECTION)
+        .map(|chunk| {
+            let args: Vec<serde_json::Value> = chunk
+                .iter()
+                .map(|s| {
+                    serde_json::json!({
+                        "instType": "USDT-FUTURES",
+                        "channel": channel,
+                        "instId": s.trim_end_matches(V1_SUFFIX),
+                    })
+                })
+                .collect();
+            let subscribe = serde_json::json!({ "op": "subscribe", "args": args });
+
+            WsSubscription {
+                url: WS_URL.to_string(),
+                subscribe_messages: vec![subscribe.to_string()],
+                ping_message: Some("ping".to_string()),
+                ping_interval: Duration::from_secs(30),
+            }
+        })
+        .collect()
+}
+
+fn parse_ws_trade(text: &str) -> Vec<TradeData> {
+    let msg = match serde_json::from_str::<BitgetWsTradeMessage>(text) {
+        Ok(m) => m,
+        Err(_) => return Vec::new(),
+    };
+
+    // The snapshot replays recent trades on every (re)subscribe, only live updates count
+    if msg.action.as_deref() == Some("snapshot") {
+        return Vec::new();
+    }
+
+    let symbol = format!("{}{}", msg.arg.instrument_id, V1_SUFFIX);
+    msg.data
+        .into_iter()
+        .filter_map(|b| {
+            Some(TradeData {
+                symbol: symbol.clone(),
+                price: b.price.parse().ok()?,
+                qty: b.size.parse().ok()?,
+                side: if b.side == "buy" { TradeSide::Buy } else { TradeSide::Sell },
+                trade_id: b.trade_id,
+                timestamp: b.ts.parse().ok()?,
+                count: 1,
+            })
+        })
+        .collect()
+}
+
+// Bitget granularity names; daily candles use the UTC-aligned variant
+fn bitget_granularity(interval: &str) -> Result<&'static str> {
+    match interval {
+        "1m" => Ok("1m"),
+        "3m" => Ok("3m"),
+        "5m" => Ok("5m"),
+        "15m" => Ok("15m"),
+        "1h" => Ok("1H"),
+        "4h" => Ok("4H"),
+        "1d" => Ok("1Dutc"),
+        _ => Err(anyhow!("unsupported interval for Bitget: {}", interval)),
+    }
 }
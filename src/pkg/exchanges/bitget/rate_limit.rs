@@ -0,0 +1,41 @@
+use crate::pkg::exchanges::rate_limited_client::{Limit, Observed, RateLimitPolicy, header_num};
+use reqwest::Url;
+use reqwest::header::HeaderMap;
+use std::time::Duration;
+
+/// Bitget market endpoints allow 20 requests per second per IP each; responses may
+/// carry `X-Bitget-Ratelimit-{Limit,Remain,Reset}` for the endpoint.
+pub struct BitgetRateLimit;
+
+impl RateLimitPolicy for BitgetRateLimit {
+    fn name(&self) -> &'static str {
+        "bitget"
+    }
+
+    fn costs(&self, url: &Url) -> Vec<(String, u32)> {
+        vec![(url.path().to_string(), 1)]
+    }
+
+    fn hard_limit(&self, _bucket: &str) -> Option<Limit> {
+        Some(Limit::new(20, Duration::from_secs(1)))
+    }
+
+    fn observe(&self, url: &Url, headers: &HeaderMap) -> Vec<(String, Observed)> {
+        let (Some(remain), Some(limit), Some(reset_secs)) = (
+            header_num::<u32>(headers, "X-Bitget-Ratelimit-Remain"),
+            header_num::<u32>(headers, "X-Bitget-Ratelimit-Limit"),
+            header_num::<u64>(headers, "X-Bitget-Ratelimit-Reset"),
+        ) else {
+            return Vec::new();
+        };
+
+        vec![(
+            url.path().to_string(),
+            Observed {
+                used: limit.saturating_sub(remain),
+                limit: Some(limit),
+                reset_in: Some(Duration::from_secs(reset_secs)),
+            },
+        )]
+    }
+}
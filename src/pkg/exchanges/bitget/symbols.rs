@@ -0,0 +1,65 @@
+use crate::pkg::exchanges::instrument::{Instrument, InstrumentKind, split_quote};
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+
+// v1 mix products: BTCUSDT_UMCBL (USDT-M), BTCPERP_CMCBL (USDC-M), BTCUSD_DMCBL (coin-M),
+// with a trailing _YYMMDD on delivery contracts
+
+pub fn parse(native: &str) -> Result<Instrument> {
+    let native = native.to_uppercase();
+    let unknown = || anyhow!("unknown Bitget symbol: {}", native);
+
+    let parts: Vec<&str> = native.split('_').collect();
+    let (pair, product, expiry) = match parts.as_slice() {
+        [pair, product] => (*pair, *product, None),
+        [pair, product, date] => (
+            *pair,
+            *product,
+            Some(NaiveDate::parse_from_str(date, "%y%m%d").map_err(|_| unknown())?),
+        ),
+        _ => return Err(unknown()),
+    };
+
+    let (base, quote, settle) = match product {
+        "UMCBL" => {
+            let (base, quote) = split_quote(pair).ok_or_else(unknown)?;
+            (base, quote, quote)
+        }
+        "CMCBL" => {
+            let base = pair.strip_suffix("PERP").ok_or_else(unknown)?;
+            (base, "USDC", "USDC")
+        }
+        "DMCBL" => {
+            let base = pair.strip_suffix("USD").ok_or_else(unknown)?;
+            (base, "USD", base)
+        }
+        _ => return Err(unknown()),
+    };
+
+    Ok(Instrument {
+        exchange: "bitget".to_string(),
+        base: base.to_string(),
+        quote: quote.to_string(),
+        settle: settle.to_string(),
+        kind: if expiry.is_some() {
+            InstrumentKind::DatedFuture
+        } else {
+            InstrumentKind::Perpetual
+        },
+        expiry,
+        native_symbol: native.clone(),
+    })
+}
+
+pub fn format(instrument: &Instrument) -> Result<String> {
+    let product = match instrument.quote.as_str() {
+        "USDC" => format!("{}PERP_CMCBL", instrument.base),
+        "USD" => format!("{}USD_DMCBL", instrument.base),
+        quote => format!("{}{}_UMCBL", instrument.base, quote),
+    };
+    match (instrument.kind, instrument.expiry) {
+        (InstrumentKind::Perpetual, _) => Ok(product),
+        (InstrumentKind::DatedFuture, Some(expiry)) => Ok(format!("{}_{}", product, expiry.format("%y%m%d"))),
+        _ => Err(anyhow!("Bitget mix has no {}", instrument.canonical())),
+    }
+}
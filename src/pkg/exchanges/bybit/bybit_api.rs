@@ -1,10 +1,28 @@
 use anyhow::{Result, anyhow};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+use tokio::sync::mpsc;
 
-
-use crate::pkg::exchanges::bybit::rate_limited_client::RateLimitedClient;
+use crate::pkg::aggregator::intervals::interval_to_ms;
+use crate::pkg::dbcontext::entities::SymbolKlineData;
+use crate::pkg::exchanges::bybit::rate_limit::BybitRateLimit;
+use crate::pkg::exchanges::bybit::symbols;
+use crate::pkg::exchanges::instrument::{Instrument, InstrumentKind};
+use crate::pkg::exchanges::rate_limited_client::RateLimitedClient;
 use crate::pkg::exchanges::exchange::ExchangeApi;
-use crate::pkg::exchanges::exchange_entities::{BybitTickerInfo, TickerInfo};
+use crate::pkg::exchanges::exchange_client::{kline_open_time, listing_time, retain_closed};
+use crate::pkg::exchanges::exchange_entities::{
+    BybitInstrument, BybitTickerInfo, BybitWsMessage, BybitWsTicker, BybitWsTrade, InstrumentInfo, InstrumentStatus, TickerInfo, TradeData, TradeSide,
+};
+use crate::pkg::exchanges::ws_client::{WsSubscription, run_ws_streams};
+
+const EXCHANGE: &str = "bybit";
+const WS_URL: &str = "wss://stream.bybit.com/v5/public/linear";
+const WS_SYMBOLS_PER_CONNECTION: usize = 100;
+// Bybit rejects subscribe requests with more than 10 args
+const WS_ARGS_PER_MESSAGE: usize = 10;
+const KLINES_LIMIT: usize = 1000;
 
 #[derive(Debug, serde::Deserialize)]
 struct BybitResponse {
@@ -13,6 +31,8 @@ struct BybitResponse {
     #[serde(rename = "retMsg")]
     ret_msg: String,
     result: BybitResult,
+    #[serde(default)]
+    time: Option<i64>,
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -21,6 +41,37 @@ struct BybitResult {
     list: Vec<BybitTickerInfo>,
 }
 
+#[derive(Debug, serde::Deserialize)]
+struct BybitKlineResponse {
+    #[serde(rename = "retCode")]
+    ret_code: i32,
+    #[serde(rename = "retMsg")]
+    ret_msg: String,
+    result: BybitKlineResult,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct BybitKlineResult {
+    // [startTime, open, high, low, close, volume, turnover], newest first
+    list: Vec<Vec<String>>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct BybitInstrumentsResponse {
+    #[serde(rename = "retCode")]
+    ret_code: i32,
+    #[serde(rename = "retMsg")]
+    ret_msg: String,
+    result: BybitInstrumentsResult,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct BybitInstrumentsResult {
+    list: Vec<BybitInstrument>,
+    #[serde(rename = "nextPageCursor", default)]
+    next_page_cursor: String,
+}
+
 pub struct BybitApi {
     client: RateLimitedClient,
 }
@@ -28,11 +79,17 @@ pub struct BybitApi {
 impl BybitApi {
     pub fn new() -> Self {
         Self {
-            client: RateLimitedClient::new(None),
+            client: RateLimitedClient::new(None, BybitRateLimit),
         }
     }
 }
 
+impl Default for BybitApi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[async_trait]
 impl ExchangeApi for BybitApi {
     async fn get_all_tickers(&self) -> Result<Vec<TickerInfo>> {
@@ -42,7 +99,7 @@ impl ExchangeApi for BybitApi {
             .timeout(std::time::Duration::from_secs(10))
             .build()?;
 
-        let resp = self.client.send_with_retry(req, 1).await?;
+        let resp = self.client.send_with_retry(req).await?;
         let status = resp.status();
         let bytes = resp.bytes().await?;
 
@@ -61,6 +118,8 @@ impl ExchangeApi for BybitApi {
             .unwrap()
             .as_millis();*/
 
+        // Tickers carry no time of their own, the response time stands in
+        let time = parsed.time;
         let standard_tickers = parsed
             .result
             .list
@@ -69,37 +128,225 @@ impl ExchangeApi for BybitApi {
                 symbol: b.symbol,
                 last_price: b.last_price,
                 vol_24h: Some(b.volume_24h),
+                timestamp: time,
             })
             .collect();
 
         Ok(standard_tickers)
     }
 
-    /*fn name(&self) -> &str {
-        "Bybit"
-    }*/
+    async fn get_instruments(&self) -> Result<Vec<InstrumentInfo>> {
+        let mut instruments = Vec::new();
+        let mut cursor = String::new();
+
+        // Paged by cursor, 1000 instruments per page
+        loop {
+            let url = format!(
+                "https://api.bybit.com/v5/market/instruments-info?category=linear&limit=1000&cursor={}",
+                cursor
+            );
+            let req = reqwest::Client::new()
+                .get(&url)
+                .timeout(std::time::Duration::from_secs(10))
+                .build()?;
+
+            let resp = self.client.send_with_retry(req).await?;
+            let status = resp.status();
+            let bytes = resp.bytes().await?;
+
+            if !status.is_success() {
+                let body = String::from_utf8_lossy(&bytes);
+                return Err(anyhow!("Non-200 response: {} - {}", status.as_u16(), body));
+            }
 
-    /*pub async fn get_ticker_info(&self, symbol: &str) -> Result<BybitTickerInfo> {
+            let parsed: BybitInstrumentsResponse = serde_json::from_slice(&bytes)?;
+            if parsed.ret_code != 0 {
+                return Err(anyhow!("Bybit API error: {}", parsed.ret_msg));
+            }
+
+            instruments.extend(parsed.result.list.into_iter().map(|b| InstrumentInfo {
+                instrument: Instrument {
+                    exchange: EXCHANGE.to_string(),
+                    expiry: symbols::parse(&b.symbol).ok().and_then(|i| i.expiry),
+                    base: b.base_coin,
+                    quote: b.quote_coin,
+                    settle: b.settle_coin,
+                    kind: if b.contract_type == "LinearPerpetual" {
+                        InstrumentKind::Perpetual
+                    } else {
+                        InstrumentKind::DatedFuture
+                    },
+                    native_symbol: b.symbol,
+                },
+                status: match b.status.as_str() {
+                    "Trading" => InstrumentStatus::Trading,
+                    "PreLaunch" => InstrumentStatus::PreLaunch,
+                    "Closed" | "Delivering" | "Settling" => InstrumentStatus::Delisted,
+                    _ => InstrumentStatus::Suspended,
+                },
+                tick_size: b.price_filter.tick_size.parse().unwrap_or(0.0),
+                lot_size: b.lot_size_filter.qty_step.parse().unwrap_or(0.0),
+                contract_value: 1.0,
+                listing_time: listing_time(&b.launch_time),
+            }));
+
+            if parsed.result.next_page_cursor.is_empty() {
+                break;
+            }
+            cursor = parsed.result.next_page_cursor;
+        }
+
+        Ok(instruments)
+    }
+
+    async fn get_klines(
+        &self,
+        symbol: &str,
+        interval: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<SymbolKlineData>> {
+        let interval_ms = interval_to_ms(interval).ok_or_else(|| anyhow!("unsupported interval: {}", interval))?;
         let url = format!(
-            "https://api.bybit.com/v5/market/ticker?category=linear&symbol={}",
-            symbol
+            "https://api.bybit.com/v5/market/kline?category=linear&symbol={}&interval={}&start={}&end={}&limit={}",
+            symbol,
+            bybit_interval(interval)?,
+            start.timestamp_millis(),
+            end.timestamp_millis(),
+            KLINES_LIMIT
         );
         let req = reqwest::Client::new()
             .get(&url)
-            .timeout(Duration::from_secs(10))
+            .timeout(std::time::Duration::from_secs(10))
             .build()?;
 
-        let ticker: BybitTickerInfo = self
-            .client
-            .send_with_retry(req, 1)
-            .await
-            .context("Send failed")?
-            .error_for_status()
-            .context("Bad status")?
-            .json()
-            .await
-            .context("JSON parse failed")?;
-
-        Ok(ticker)
-    }*/
+        let resp = self.client.send_with_retry(req).await?;
+        let status = resp.status();
+        let bytes = resp.bytes().await?;
+
+        if !status.is_success() {
+            let body = String::from_utf8_lossy(&bytes);
+            return Err(anyhow!("Non-200 response: {} - {}", status.as_u16(), body));
+        }
+
+        let parsed: BybitKlineResponse = serde_json::from_slice(&bytes)?;
+        if parsed.ret_code != 0 {
+            return Err(anyhow!("Bybit API error: {}", parsed.ret_msg));
+        }
+
+        let mut klines = parsed
+            .result
+            .list
+            .into_iter()
+            .filter(|r| r.len() >= 6)
+            .map(|r| {
+                Ok(SymbolKlineData {
+                    exchange: EXCHANGE.to_string(),
+                    symbol: symbol.to_string(),
+                    interval: interval.to_string(),
+                    open: r[1].parse()?,
+                    high: r[2].parse()?,
+                    low: r[3].parse()?,
+                    close: r[4].parse()?,
+                    open_time: kline_open_time(r[0].parse()?)?,
+                    volume: r[5].parse()?,
+                    volume_24h: None,
+                    trade_count: 0,
+                    buy_volume: 0.0,
+                    sell_volume: 0.0,
+                    is_synthetic: false,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        retain_closed(&mut klines, interval_ms);
+        Ok(klines)
+    }
+
+    fn klines_page_limit(&self) -> usize {
+        KLINES_LIMIT
+    }
+
+    async fn stream_tickers(&self, symbols: &[String], tx: mpsc::Sender<TickerInfo>) -> Result<()> {
+        run_ws_streams("bybit", ws_subscriptions(symbols, "tickers"), parse_ws_ticker, tx).await
+    }
+
+    async fn stream_trades(&self, symbols: &[String], tx: mpsc::Sender<TradeData>) -> Result<()> {
+        run_ws_streams("bybit", ws_subscriptions(symbols, "publicTrade"), parse_ws_trade, tx).await
+    }
+}
+
+fn parse_ws_ticker(text: &str) -> Vec<TickerInfo> {
+    let msg: BybitWsMessage<BybitWsTicker> = match serde_json::from_str(text) {
+        Ok(m) => m,
+        Err(_) => return Vec::new(),
+    };
+
+    // Deltas without a price change are of no use to the aggregator
+    match msg.data.last_price {
+        Some(last_price) => vec![TickerInfo {
+            symbol: msg.data.symbol,
+            last_price,
+            vol_24h: msg.data.volume_24h,
+            timestamp: msg.ts,
+        }],
+        None => Vec::new(),
+    }
+}
+
+/// One subscription per connection-sized chunk of symbols, for the given channel
+fn ws_subscriptions(symbols: &[String], channel: &str) -> Vec<WsSubscription> {
+    symbols
+        .chunks(WS_SYMBOLS_PER_CONNECTION)
+        .map(|chunk| {
+            let subscribe_messages = chunk
+                .chunks(WS_ARGS_PER_MESSAGE)
+                .map(|args| {
+                    let topics: Vec<String> = args.iter().map(|s| format!("{}.{}", channel, s)).collect();
+                    serde_json::json!({ "op": "subscribe", "args": topics }).to_string()
+                })
+                .collect();
+
+            WsSubscription {
+                url: WS_URL.to_string(),
+                subscribe_messages,
+                ping_message: Some(r#"{"op":"ping"}"#.to_string()),
+                ping_interval: Duration::from_secs(20),
+            }
+        })
+        .collect()
+}
+
+fn parse_ws_trade(text: &str) -> Vec<TradeData> {
+    match serde_json::from_str::<BybitWsMessage<Vec<BybitWsTrade>>>(text) {
+        Ok(msg) => msg
+            .data
+            .into_iter()
+            .filter_map(|b| {
+                Some(TradeData {
+                    price: b.price.parse().ok()?,
+                    qty: b.qty.parse().ok()?,
+                    side: if b.side == "Buy" { TradeSide::Buy } else { TradeSide::Sell },
+                    timestamp: b.trade_time,
+                    count: 1,
+                    symbol: b.symbol,
+                    trade_id: b.trade_id,
+                })
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn bybit_interval(interval: &str) -> Result<&'static str> {
+    match interval {
+        "1m" => Ok("1"),
+        "3m" => Ok("3"),
+        "5m" => Ok("5"),
+        "15m" => Ok("15"),
+        "1h" => Ok("60"),
+        "4h" => Ok("240"),
+        "1d" => Ok("D"),
+        _ => Err(anyhow!("unsupported interval for Bybit: {}", interval)),
+    }
 }
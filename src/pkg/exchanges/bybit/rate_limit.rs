@@ -0,0 +1,45 @@
+use crate::pkg::exchanges::rate_limited_client::{Limit, Observed, RateLimitPolicy, header_num, until_unix_ms};
+use reqwest::Url;
+use reqwest::header::HeaderMap;
+use std::time::Duration;
+
+const IP_BUCKET: &str = "ip";
+// Bybit caps every IP at 600 requests per 5 seconds across all endpoints
+const IP_LIMIT: Limit = Limit::new(600, Duration::from_secs(5));
+
+/// Bybit v5: the IP-wide cap plus per-endpoint limits reported in
+/// `X-Bapi-Limit-Status` (remaining), `X-Bapi-Limit` and `X-Bapi-Limit-Reset-Timestamp`.
+pub struct BybitRateLimit;
+
+impl RateLimitPolicy for BybitRateLimit {
+    fn name(&self) -> &'static str {
+        "bybit"
+    }
+
+    fn costs(&self, url: &Url) -> Vec<(String, u32)> {
+        vec![(IP_BUCKET.to_string(), 1), (url.path().to_string(), 1)]
+    }
+
+    fn hard_limit(&self, bucket: &str) -> Option<Limit> {
+        (bucket == IP_BUCKET).then_some(IP_LIMIT)
+    }
+
+    fn observe(&self, url: &Url, headers: &HeaderMap) -> Vec<(String, Observed)> {
+        let (Some(remaining), Some(limit)) = (
+            header_num::<u32>(headers, "X-Bapi-Limit-Status"),
+            header_num::<u32>(headers, "X-Bapi-Limit"),
+        ) else {
+            return Vec::new();
+        };
+        let reset_in = header_num::<i64>(headers, "X-Bapi-Limit-Reset-Timestamp").and_then(until_unix_ms);
+
+        vec![(
+            url.path().to_string(),
+            Observed {
+                used: limit.saturating_sub(remaining),
+                limit: Some(limit),
+                reset_in,
+            },
+        )]
+    }
+}
@@ -0,0 +1,62 @@
+use crate::pkg::exchanges::instrument::{Instrument, InstrumentKind, split_quote};
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+
+// Linear: BTCUSDT, BTCPERP (USDC perpetual), BTCUSDT-26SEP25, BTC-26SEP25 (USDC future)
+// Inverse: BTCUSD
+
+pub fn parse(native: &str) -> Result<Instrument> {
+    let native = native.to_uppercase();
+    let unknown = || anyhow!("unknown Bybit symbol: {}", native);
+
+    let (pair, expiry) = match native.split_once('-') {
+        Some((pair, date)) => (
+            pair,
+            Some(NaiveDate::parse_from_str(date, "%d%b%y").map_err(|_| unknown())?),
+        ),
+        None => (native.as_str(), None),
+    };
+
+    let (base, quote) = if let Some(base) = pair.strip_suffix("PERP").filter(|b| !b.is_empty()) {
+        (base, "USDC")
+    } else if let Some((base, quote)) = split_quote(pair) {
+        (base, quote)
+    } else if expiry.is_some() {
+        // USDC futures carry only the base coin
+        (pair, "USDC")
+    } else {
+        return Err(unknown());
+    };
+    let settle = if quote == "USD" { base } else { quote };
+
+    Ok(Instrument {
+        exchange: "bybit".to_string(),
+        base: base.to_string(),
+        quote: quote.to_string(),
+        settle: settle.to_string(),
+        kind: if expiry.is_some() {
+            InstrumentKind::DatedFuture
+        } else {
+            InstrumentKind::Perpetual
+        },
+        expiry,
+        native_symbol: native.clone(),
+    })
+}
+
+pub fn format(instrument: &Instrument) -> Result<String> {
+    let usdc = instrument.quote == "USDC";
+    match (instrument.kind, instrument.expiry) {
+        (InstrumentKind::Perpetual, _) if usdc => Ok(format!("{}PERP", instrument.base)),
+        (InstrumentKind::Perpetual, _) => Ok(format!("{}{}", instrument.base, instrument.quote)),
+        (InstrumentKind::DatedFuture, Some(expiry)) => {
+            let date = expiry.format("%d%b%y").to_string().to_uppercase();
+            if usdc {
+                Ok(format!("{}-{}", instrument.base, date))
+            } else {
+                Ok(format!("{}{}-{}", instrument.base, instrument.quote, date))
+            }
+        }
+        _ => Err(anyhow!("Bybit derivatives have no {}", instrument.canonical())),
+    }
+}
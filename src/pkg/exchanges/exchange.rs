@@ -1,9 +1,30 @@
 use async_trait::async_trait;
 use anyhow::Result;
-use crate::pkg::exchanges::exchange_entities::TickerInfo;
+use chrono::{DateTime, Utc};
+use tokio::sync::mpsc;
+use crate::pkg::dbcontext::entities::SymbolKlineData;
+use crate::pkg::exchanges::exchange_entities::{InstrumentInfo, TickerInfo, TradeData};
 
 #[async_trait]
 pub trait ExchangeApi: Send + Sync {
     async fn get_all_tickers(&self) -> Result<Vec<TickerInfo>>;
+    /// Contract metadata for every instrument in the market the adapter trades (all statuses)
+    async fn get_instruments(&self) -> Result<Vec<InstrumentInfo>>;
+    /// Stream ticker updates for `symbols` over the public WebSocket into `tx`.
+    /// Reconnects and resubscribes on its own; returns once `tx` is closed.
+    async fn stream_tickers(&self, symbols: &[String], tx: mpsc::Sender<TickerInfo>) -> Result<()>;
+    /// Stream public trades for `symbols` into `tx`, same reconnect semantics as `stream_tickers`.
+    async fn stream_trades(&self, symbols: &[String], tx: mpsc::Sender<TradeData>) -> Result<()>;
+    /// Closed exchange candles with `start <= open_time <= end`, oldest first.
+    /// One request per call, so long ranges come back truncated to the endpoint's page size.
+    async fn get_klines(
+        &self,
+        symbol: &str,
+        interval: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<SymbolKlineData>>;
+    /// Most candles a single `get_klines` call can return
+    fn klines_page_limit(&self) -> usize;
     // fn name(&self) -> &str;
-}
\ No newline at end of file
+}
@@ -1,5 +1,6 @@
 use anyhow::{Result, anyhow};
-use crate::pkg::exchanges::exchange_entities::TickerInfo;
+use chrono::{TimeZone, Utc};
+use crate::pkg::dbcontext::entities::SymbolKlineData;
 use crate::pkg::exchanges::exchange::ExchangeApi;
 use crate::pkg::exchanges::binance::binance_api::BinanceApi;
 use crate::pkg::exchanges::bitget::{self};
@@ -7,7 +8,7 @@ use crate::pkg::exchanges::bybit::{self};
 use crate::pkg::exchanges::okx::{self};
 
 
-pub async fn core_futures_all_tickers(exchange: &str) -> Result<Vec<TickerInfo>> {
+pub fn create_exchange_api(exchange: &str) -> Result<Box<dyn ExchangeApi>> {
     let exchange = exchange.to_lowercase();
 
     let api: Box<dyn ExchangeApi> = match exchange.as_str() {
@@ -18,5 +19,24 @@ pub async fn core_futures_all_tickers(exchange: &str) -> Result<Vec<TickerInfo>>
         _ => return Err(anyhow!("unsupported exchange: {}", exchange)),
     };
 
-    api.get_all_tickers().await
-}
\ No newline at end of file
+    Ok(api)
+}
+
+/// Drop candles that are still open, endpoints return the current candle alongside closed ones
+pub fn retain_closed(klines: &mut Vec<SymbolKlineData>, interval_ms: i64) {
+    let now = Utc::now().timestamp_millis();
+    klines.retain(|k| k.open_time.timestamp_millis() + interval_ms <= now);
+    klines.sort_by_key(|k| k.open_time);
+}
+
+/// Listing time from a millisecond timestamp string, `None` when missing or zero
+pub fn listing_time(millis: &str) -> Option<chrono::DateTime<Utc>> {
+    millis.parse::<i64>().ok().filter(|ms| *ms > 0).and_then(|ms| kline_open_time(ms).ok())
+}
+
+/// Millisecond timestamp from an exchange payload into a candle open time
+pub fn kline_open_time(millis: i64) -> Result<chrono::DateTime<Utc>> {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .ok_or_else(|| anyhow!("invalid kline timestamp: {}", millis))
+}
@@ -1,4 +1,6 @@
 
+use crate::pkg::exchanges::instrument::Instrument;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
 
 #[derive(Debug, Clone)]
@@ -10,7 +12,45 @@ pub struct TickerInfo {
     pub vol_24h: Option<String>,
     //pub change_24h: Option<String>,
     //pub exchange: String,
-    //pub timestamp: u128, // millis since epoch
+    pub timestamp: Option<i64>, // exchange event time, millis since epoch; None if the payload has none
+}
+
+/// Taker side of a public trade
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TradeSide {
+    Buy,
+    Sell,
+}
+
+#[derive(Debug, Clone)]
+pub struct TradeData {
+    pub symbol: String,
+    pub price: f64,
+    pub qty: f64,
+    pub side: TradeSide,
+    pub trade_id: String,
+    pub timestamp: i64, // exchange trade time, millis since epoch
+    pub count: i64,     // fills behind this print (Binance aggTrade / OKX aggregated trades), else 1
+}
+
+/// Lifecycle state of an instrument, normalised across exchanges
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstrumentStatus {
+    Trading,
+    PreLaunch,
+    Suspended,
+    Delisted,
+}
+
+/// Contract metadata from the exchange's instruments endpoint
+#[derive(Debug, Clone)]
+pub struct InstrumentInfo {
+    pub instrument: Instrument,
+    pub status: InstrumentStatus,
+    pub tick_size: f64,
+    pub lot_size: f64,
+    pub contract_value: f64, // base units per contract, 1 where size is quoted in base
+    pub listing_time: Option<DateTime<Utc>>,
 }
 
 // BinanceTickerInfo
@@ -22,6 +62,25 @@ pub struct BinanceTickerInfo {
     #[serde(rename = "lastPrice")]
     pub last_price: String,
     pub volume: String,
+    #[serde(rename = "closeTime", default)]
+    pub close_time: Option<i64>,
+}
+
+// Binance /fapi/v1/klines row, deserialized from its positional array
+#[derive(Debug, Deserialize)]
+pub struct BinanceKline {
+    pub open_time: i64,
+    pub open: String,
+    pub high: String,
+    pub low: String,
+    pub close: String,
+    pub volume: String,
+    pub _close_time: i64,
+    pub _quote_volume: String,
+    pub trade_count: i64,
+    pub taker_buy_volume: String,
+    pub _taker_buy_quote_volume: String,
+    pub _ignore: serde_json::Value,
 }
 
 // BitgetTickerInfo
@@ -39,6 +98,8 @@ pub struct BitgetTickerInfo {
     //pub change_24h_percent: String,
     #[serde(rename = "baseVolume")]
     pub base_volume: String,
+    #[serde(default)]
+    pub timestamp: Option<String>,
 }
 
 // BybitTickerInfo
@@ -71,7 +132,266 @@ pub struct OKXTickerInfo {
     //pub low_24h: String,
     #[serde(rename = "vol24h")]
     pub volume_24h: String,
+    #[serde(default)]
+    pub ts: Option<String>,
     //#[serde(rename = "change24h")]
     //pub change_24h_pct: Option<String>, // may not be provided, so optional
 }
 
+// Instrument metadata payloads
+
+// Binance /fapi/v1/exchangeInfo
+#[derive(Debug, Deserialize)]
+pub struct BinanceExchangeInfo {
+    pub symbols: Vec<BinanceSymbolInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BinanceSymbolInfo {
+    pub symbol: String,
+    pub status: String, // TRADING, PENDING_TRADING, SETTLING, CLOSE, ...
+    #[serde(rename = "contractType")]
+    pub contract_type: String, // PERPETUAL, CURRENT_QUARTER, ...
+    #[serde(rename = "baseAsset")]
+    pub base_asset: String,
+    #[serde(rename = "quoteAsset")]
+    pub quote_asset: String,
+    #[serde(rename = "marginAsset")]
+    pub margin_asset: String,
+    #[serde(rename = "onboardDate")]
+    pub onboard_date: Option<i64>,
+    pub filters: Vec<BinanceSymbolFilter>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "filterType")]
+pub enum BinanceSymbolFilter {
+    #[serde(rename = "PRICE_FILTER")]
+    Price {
+        #[serde(rename = "tickSize")]
+        tick_size: String,
+    },
+    #[serde(rename = "LOT_SIZE")]
+    LotSize {
+        #[serde(rename = "stepSize")]
+        step_size: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+// OKX /api/v5/public/instruments
+#[derive(Debug, Deserialize)]
+pub struct OkxInstrument {
+    #[serde(rename = "instId")]
+    pub instrument_id: String,
+    #[serde(rename = "instType")]
+    pub instrument_type: String, // SWAP, FUTURES, SPOT
+    pub state: String, // live, suspend, preopen, test
+    #[serde(rename = "tickSz")]
+    pub tick_size: String,
+    #[serde(rename = "lotSz")]
+    pub lot_size: String,
+    #[serde(rename = "ctVal", default)]
+    pub contract_value: String,
+    #[serde(default)]
+    pub uly: String, // BTC-USDT
+    #[serde(rename = "settleCcy", default)]
+    pub settle_ccy: String,
+    #[serde(rename = "listTime", default)]
+    pub list_time: String,
+}
+
+// Bybit /v5/market/instruments-info
+#[derive(Debug, Deserialize)]
+pub struct BybitInstrument {
+    pub symbol: String,
+    #[serde(rename = "contractType")]
+    pub contract_type: String, // LinearPerpetual, LinearFutures
+    pub status: String, // Trading, PreLaunch, Settling, Delivering, Closed
+    #[serde(rename = "baseCoin")]
+    pub base_coin: String,
+    #[serde(rename = "quoteCoin")]
+    pub quote_coin: String,
+    #[serde(rename = "settleCoin")]
+    pub settle_coin: String,
+    #[serde(rename = "launchTime", default)]
+    pub launch_time: String,
+    #[serde(rename = "priceFilter")]
+    pub price_filter: BybitPriceFilter,
+    #[serde(rename = "lotSizeFilter")]
+    pub lot_size_filter: BybitLotSizeFilter,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BybitPriceFilter {
+    #[serde(rename = "tickSize")]
+    pub tick_size: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BybitLotSizeFilter {
+    #[serde(rename = "qtyStep")]
+    pub qty_step: String,
+}
+
+// Bitget /api/mix/v1/market/contracts
+#[derive(Debug, Deserialize)]
+pub struct BitgetContract {
+    pub symbol: String, // BTCUSDT_UMCBL
+    #[serde(rename = "baseCoin")]
+    pub base_coin: String,
+    #[serde(rename = "quoteCoin")]
+    pub quote_coin: String,
+    #[serde(rename = "supportMarginCoins", default)]
+    pub support_margin_coins: Vec<String>,
+    #[serde(rename = "pricePlace")]
+    pub price_place: String,
+    #[serde(rename = "priceEndStep")]
+    pub price_end_step: String,
+    #[serde(rename = "sizeMultiplier")]
+    pub size_multiplier: String,
+    #[serde(rename = "symbolType", default)]
+    pub symbol_type: String, // perpetual, delivery
+    #[serde(rename = "symbolStatus", default)]
+    pub symbol_status: String, // normal, maintain, off, ...
+}
+
+// WebSocket payloads
+
+// Binance <symbol>@ticker event
+#[derive(Debug, Deserialize)]
+pub struct BinanceWsTicker {
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "c")]
+    pub last_price: String,
+    #[serde(rename = "v")]
+    pub volume: String,
+    #[serde(rename = "E", default)]
+    pub event_time: Option<i64>,
+}
+
+// OKX / Bitget push wrapper: {"arg": {...}, "data": [...]}
+#[derive(Debug, Deserialize)]
+pub struct WsDataMessage<T> {
+    pub data: Vec<T>,
+}
+
+// OKX tickers channel
+#[derive(Debug, Deserialize)]
+pub struct OkxWsTicker {
+    #[serde(rename = "instId")]
+    pub instrument_id: String,
+    pub last: String,
+    #[serde(rename = "vol24h")]
+    pub volume_24h: String,
+    #[serde(default)]
+    pub ts: Option<String>,
+}
+
+// Bybit tickers.<symbol> topic; deltas only carry changed fields
+#[derive(Debug, Deserialize)]
+pub struct BybitWsMessage<T> {
+    pub data: T,
+    #[serde(default)]
+    pub ts: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BybitWsTicker {
+    pub symbol: String,
+    #[serde(rename = "lastPrice")]
+    pub last_price: Option<String>,
+    #[serde(rename = "volume24h")]
+    pub volume_24h: Option<String>,
+}
+
+// Bitget v2 ticker channel
+#[derive(Debug, Deserialize)]
+pub struct BitgetWsTicker {
+    #[serde(rename = "instId")]
+    pub instrument_id: String,
+    #[serde(rename = "lastPr")]
+    pub last_price: String,
+    #[serde(rename = "baseVolume")]
+    pub base_volume: String,
+    #[serde(default)]
+    pub ts: Option<String>,
+}
+
+// Binance <symbol>@aggTrade event
+#[derive(Debug, Deserialize)]
+pub struct BinanceWsAggTrade {
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "a")]
+    pub agg_trade_id: i64,
+    #[serde(rename = "p")]
+    pub price: String,
+    #[serde(rename = "q")]
+    pub qty: String,
+    #[serde(rename = "f")]
+    pub first_trade_id: i64,
+    #[serde(rename = "l")]
+    pub last_trade_id: i64,
+    #[serde(rename = "T")]
+    pub trade_time: i64,
+    #[serde(rename = "m")]
+    pub buyer_is_maker: bool,
+}
+
+// OKX trades channel
+#[derive(Debug, Deserialize)]
+pub struct OkxWsTrade {
+    #[serde(rename = "instId")]
+    pub instrument_id: String,
+    #[serde(rename = "tradeId")]
+    pub trade_id: String,
+    pub px: String,
+    pub sz: String,
+    pub side: String,
+    pub ts: String,
+    pub count: Option<String>,
+}
+
+// Bybit publicTrade.<symbol> topic
+#[derive(Debug, Deserialize)]
+pub struct BybitWsTrade {
+    #[serde(rename = "T")]
+    pub trade_time: i64,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "S")]
+    pub side: String,
+    #[serde(rename = "v")]
+    pub qty: String,
+    #[serde(rename = "p")]
+    pub price: String,
+    #[serde(rename = "i")]
+    pub trade_id: String,
+}
+
+// Bitget v2 trade channel; the first push after subscribing is a snapshot of recent trades
+#[derive(Debug, Deserialize)]
+pub struct BitgetWsTradeMessage {
+    pub action: Option<String>,
+    pub arg: BitgetWsArg,
+    pub data: Vec<BitgetWsTrade>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BitgetWsArg {
+    #[serde(rename = "instId")]
+    pub instrument_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BitgetWsTrade {
+    pub ts: String,
+    pub price: String,
+    pub size: String,
+    pub side: String,
+    #[serde(rename = "tradeId")]
+    pub trade_id: String,
+}
@@ -0,0 +1,154 @@
+use crate::pkg::exchanges::{binance, bitget, bybit, okx};
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+
+/// Quote assets recognised when a native symbol glues base and quote together (BTCUSDT).
+/// Longest first so FDUSD wins over USD.
+pub const KNOWN_QUOTES: [&str; 6] = ["FDUSD", "USDT", "USDC", "BUSD", "USD", "BTC"];
+
+/// Contract kind of an instrument
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InstrumentKind {
+    Spot,
+    Perpetual,
+    DatedFuture,
+}
+
+/// Exchange independent description of a market, plus the exchange's own name for it.
+///
+/// `canonical()` gives the id used for cross-exchange joins: `BTC/USDT` for spot,
+/// `BTC/USDT:USDT` for a USDT-settled perpetual, `BTC/USD:BTC-250926` for a coin-settled future.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Instrument {
+    pub exchange: String,
+    pub base: String,
+    pub quote: String,
+    pub settle: String,
+    pub kind: InstrumentKind,
+    pub expiry: Option<NaiveDate>, // dated futures only
+    pub native_symbol: String,
+}
+
+impl Instrument {
+    /// Parse an exchange's native symbol using that exchange's naming rules
+    pub fn from_native(exchange: &str, native: &str) -> Result<Self> {
+        match exchange.to_lowercase().as_str() {
+            "binance" => binance::symbols::parse(native),
+            "okx" => okx::symbols::parse(native),
+            "bybit" => bybit::symbols::parse(native),
+            "bitget" => bitget::symbols::parse(native),
+            other => Err(anyhow!("unsupported exchange: {}", other)),
+        }
+    }
+
+    /// Parse a canonical id and resolve the native symbol on `exchange`
+    pub fn from_canonical(exchange: &str, canonical: &str) -> Result<Self> {
+        let invalid = || anyhow!("invalid canonical symbol: {}", canonical);
+
+        let (pair, derivative) = match canonical.split_once(':') {
+            Some((pair, rest)) => (pair, Some(rest)),
+            None => (canonical, None),
+        };
+        let (base, quote) = pair.split_once('/').ok_or_else(invalid)?;
+
+        let (settle, kind, expiry) = match derivative {
+            None => (quote.to_string(), InstrumentKind::Spot, None),
+            Some(rest) => match rest.split_once('-') {
+                Some((settle, date)) => (
+                    settle.to_string(),
+                    InstrumentKind::DatedFuture,
+                    Some(NaiveDate::parse_from_str(date, "%y%m%d").map_err(|_| invalid())?),
+                ),
+                None => (rest.to_string(), InstrumentKind::Perpetual, None),
+            },
+        };
+
+        let mut instrument = Self {
+            exchange: exchange.to_lowercase(),
+            base: base.to_uppercase(),
+            quote: quote.to_uppercase(),
+            settle: settle.to_uppercase(),
+            kind,
+            expiry,
+            native_symbol: String::new(),
+        };
+        instrument.native_symbol = instrument.to_native()?;
+        Ok(instrument)
+    }
+
+    /// Native symbol on `self.exchange` for this market
+    pub fn to_native(&self) -> Result<String> {
+        match self.exchange.as_str() {
+            "binance" => binance::symbols::format(self),
+            "okx" => okx::symbols::format(self),
+            "bybit" => bybit::symbols::format(self),
+            "bitget" => bitget::symbols::format(self),
+            other => Err(anyhow!("unsupported exchange: {}", other)),
+        }
+    }
+
+    pub fn canonical(&self) -> String {
+        let pair = format!("{}/{}", self.base, self.quote);
+        match (self.kind, self.expiry) {
+            (InstrumentKind::Spot, _) => pair,
+            (_, Some(expiry)) => format!("{}:{}-{}", pair, self.settle, expiry.format("%y%m%d")),
+            _ => format!("{}:{}", pair, self.settle),
+        }
+    }
+
+    /// Linear contracts settle in the quote asset, inverse ones in the base asset
+    pub fn is_inverse(&self) -> bool {
+        self.kind != InstrumentKind::Spot && self.settle == self.base
+    }
+}
+
+/// Split a glued pair such as `BTCUSDT` into (`BTC`, `USDT`)
+pub fn split_quote(pair: &str) -> Option<(&str, &str)> {
+    KNOWN_QUOTES.iter().find_map(|quote| {
+        pair.strip_suffix(quote)
+            .filter(|base| !base.is_empty())
+            .map(|base| (base, *quote))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn native_symbols_map_to_one_canonical_id() {
+        let cases = [
+            ("binance", "BTCUSDT", "BTC/USDT:USDT"),
+            ("binance", "BTCUSDC", "BTC/USDC:USDC"),
+            ("binance", "BTCUSDT_250926", "BTC/USDT:USDT-250926"),
+            ("okx", "BTC-USDT-SWAP", "BTC/USDT:USDT"),
+            ("okx", "BTC-USD-SWAP", "BTC/USD:BTC"),
+            ("okx", "BTC-USD-250926", "BTC/USD:BTC-250926"),
+            ("okx", "BTC-USDT", "BTC/USDT"),
+            ("bybit", "BTCUSDT", "BTC/USDT:USDT"),
+            ("bybit", "BTCPERP", "BTC/USDC:USDC"),
+            ("bybit", "BTCUSD", "BTC/USD:BTC"),
+            ("bitget", "BTCUSDT_UMCBL", "BTC/USDT:USDT"),
+            ("bitget", "BTCPERP_CMCBL", "BTC/USDC:USDC"),
+            ("bitget", "BTCUSD_DMCBL", "BTC/USD:BTC"),
+        ];
+
+        for (exchange, native, canonical) in cases {
+            let instrument = Instrument::from_native(exchange, native).unwrap();
+            assert_eq!(instrument.canonical(), canonical, "{} {}", exchange, native);
+            assert_eq!(instrument.to_native().unwrap(), native, "{} {}", exchange, native);
+
+            let back = Instrument::from_canonical(exchange, canonical).unwrap();
+            assert_eq!(back.native_symbol, native, "{} {}", exchange, canonical);
+        }
+    }
+
+    #[test]
+    fn usdt_and_usdc_markets_stay_distinct() {
+        let usdt = Instrument::from_native("binance", "ETHUSDT").unwrap();
+        let usdc = Instrument::from_native("binance", "ETHUSDC").unwrap();
+        assert_ne!(usdt.canonical(), usdc.canonical());
+        assert!(!usdt.is_inverse());
+        assert!(Instrument::from_native("okx", "ETH-USD-SWAP").unwrap().is_inverse());
+    }
+}
@@ -4,4 +4,7 @@ pub mod bybit;
 pub mod okx;
 pub mod exchange_client;
 pub mod exchange;
-pub mod exchange_entities;
\ No newline at end of file
+pub mod exchange_entities;
+pub mod instrument;
+pub mod rate_limited_client;
+pub mod ws_client;
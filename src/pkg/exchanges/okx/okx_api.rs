@@ -1,10 +1,30 @@
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc;
 
+use crate::pkg::aggregator::intervals::interval_to_ms;
+use crate::pkg::dbcontext::entities::SymbolKlineData;
 use crate::pkg::exchanges::exchange::ExchangeApi;
-use crate::pkg::exchanges::exchange_entities::{OKXTickerInfo, TickerInfo};
-use crate::pkg::exchanges::okx::rate_limited_client::RateLimitedClient;
+use crate::pkg::exchanges::exchange_client::{kline_open_time, listing_time, retain_closed};
+use crate::pkg::exchanges::exchange_entities::{
+    InstrumentInfo, InstrumentStatus, OKXTickerInfo, OkxInstrument, OkxWsTicker, OkxWsTrade, TickerInfo,
+    TradeData, TradeSide, WsDataMessage,
+};
+use crate::pkg::exchanges::instrument::{Instrument, InstrumentKind};
+use crate::pkg::exchanges::okx::rate_limit::OkxRateLimit;
+use crate::pkg::exchanges::okx::symbols;
+use crate::pkg::exchanges::rate_limited_client::RateLimitedClient;
+use crate::pkg::exchanges::ws_client::{WsSubscription, run_ws_streams};
+
+const EXCHANGE: &str = "okx";
+const WS_URL: &str = "wss://ws.okx.com:8443/ws/v5/public";
+const WS_SYMBOLS_PER_CONNECTION: usize = 100;
+const KLINES_LIMIT: usize = 100;
+const TRADE_BUFFER: usize = 1024;
 
 
 #[derive(Debug, Deserialize)]
@@ -20,11 +40,17 @@ pub struct OkxApi {
 impl OkxApi {
     pub fn new() -> Self {
         Self {
-            client: RateLimitedClient::new(None),
+            client: RateLimitedClient::new(None, OkxRateLimit),
         }
     }
 }
 
+impl Default for OkxApi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[async_trait]
 impl ExchangeApi for OkxApi {
     async fn get_all_tickers(&self) -> Result<Vec<TickerInfo>> {
@@ -34,7 +60,7 @@ impl ExchangeApi for OkxApi {
             .timeout(std::time::Duration::from_secs(10))
             .build()?;
 
-        let resp = self.client.send_with_retry(req, 1).await?;
+        let resp = self.client.send_with_retry(req).await?;
         let status = resp.status();
         let bytes = resp.bytes().await?;
 
@@ -61,37 +87,308 @@ impl ExchangeApi for OkxApi {
                 symbol: o.instrument_id,
                 last_price: o.last_price,
                 vol_24h: Some(o.volume_24h),
+                timestamp: o.ts.and_then(|ts| ts.parse().ok()),
             })
             .collect();
 
         Ok(standard_tickers)
     }
 
-    /*fn name(&self) -> &str {
-        "OKX"
-    }*/
+    async fn get_instruments(&self) -> Result<Vec<InstrumentInfo>> {
+        let url = "https://www.okx.com/api/v5/public/instruments?instType=SWAP";
+        let req = reqwest::Client::new()
+            .get(url)
+            .timeout(std::time::Duration::from_secs(10))
+            .build()?;
+
+        let resp = self.client.send_with_retry(req).await?;
+        let status = resp.status();
+        let bytes = resp.bytes().await?;
+
+        if !status.is_success() {
+            let body = String::from_utf8_lossy(&bytes);
+            return Err(anyhow!("Non-200 response: {} - {}", status.as_u16(), body));
+        }
+
+        let parsed: OkxResponse<Vec<OkxInstrument>> = serde_json::from_slice(&bytes)?;
+        if parsed.code != "0" {
+            return Err(anyhow!("OKX API error: {} - {}", parsed.code, parsed.msg));
+        }
+
+        let instruments = parsed
+            .data
+            .into_iter()
+            .map(|o| {
+                // `uly` is BASE-QUOTE for derivatives
+                let (base, quote) = o.uly.split_once('-').unwrap_or((o.uly.as_str(), ""));
+                InstrumentInfo {
+                    instrument: Instrument {
+                        exchange: EXCHANGE.to_string(),
+                        base: base.to_string(),
+                        quote: quote.to_string(),
+                        settle: o.settle_ccy.clone(),
+                        kind: match o.instrument_type.as_str() {
+                            "SWAP" => InstrumentKind::Perpetual,
+                            "FUTURES" => InstrumentKind::DatedFuture,
+                            _ => InstrumentKind::Spot,
+                        },
+                        expiry: symbols::parse(&o.instrument_id).ok().and_then(|i| i.expiry),
+                        native_symbol: o.instrument_id.clone(),
+                    },
+                    status: match o.state.as_str() {
+                        "live" => InstrumentStatus::Trading,
+                        "preopen" => InstrumentStatus::PreLaunch,
+                        "expired" => InstrumentStatus::Delisted,
+                        _ => InstrumentStatus::Suspended,
+                    },
+                    tick_size: o.tick_size.parse().unwrap_or(0.0),
+                    lot_size: o.lot_size.parse().unwrap_or(0.0),
+                    contract_value: o.contract_value.parse().unwrap_or(1.0),
+                    listing_time: listing_time(&o.list_time),
+                }
+            })
+            .collect();
+
+        Ok(instruments)
+    }
 
-    /*pub async fn get_ticker_info(&self, inst_id: &str) -> Result<OKXTickerInfo> {
+    async fn get_klines(
+        &self,
+        symbol: &str,
+        interval: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<SymbolKlineData>> {
+        let interval_ms = interval_to_ms(interval).ok_or_else(|| anyhow!("unsupported interval: {}", interval))?;
+        // `after`/`before` are exclusive bounds; results come back newest first
         let url = format!(
-            "https://www.okx.com/api/v5/market/ticker?instId={}",
-            inst_id
+            "https://www.okx.com/api/v5/market/history-candles?instId={}&bar={}&after={}&before={}&limit={}",
+            symbol,
+            okx_bar(interval)?,
+            end.timestamp_millis() + 1,
+            start.timestamp_millis() - 1,
+            KLINES_LIMIT
         );
         let req = reqwest::Client::new()
             .get(&url)
-            .timeout(Duration::from_secs(10))
+            .timeout(std::time::Duration::from_secs(10))
             .build()?;
 
-        let ticker: OKXTickerInfo = self
-            .client
-            .send_with_retry(req, 1)
-            .await
-            .context("Send failed")?
-            .error_for_status()
-            .context("Bad status")?
-            .json()
+        let resp = self.client.send_with_retry(req).await?;
+        let status = resp.status();
+        let bytes = resp.bytes().await?;
+
+        if !status.is_success() {
+            let body = String::from_utf8_lossy(&bytes);
+            return Err(anyhow!("Non-200 response: {} - {}", status.as_u16(), body));
+        }
+
+        // [ts, o, h, l, c, vol (contracts), volCcy (base), volCcyQuote, confirm]
+        let parsed: OkxResponse<Vec<Vec<String>>> = serde_json::from_slice(&bytes)?;
+        if parsed.code != "0" {
+            return Err(anyhow!("OKX API error: {} - {}", parsed.code, parsed.msg));
+        }
+
+        let mut klines = parsed
+            .data
+            .into_iter()
+            .filter(|r| r.len() >= 9 && r[8] == "1")
+            .map(|r| {
+                Ok(SymbolKlineData {
+                    exchange: EXCHANGE.to_string(),
+                    symbol: symbol.to_string(),
+                    interval: interval.to_string(),
+                    open: r[1].parse()?,
+                    high: r[2].parse()?,
+                    low: r[3].parse()?,
+                    close: r[4].parse()?,
+                    open_time: kline_open_time(r[0].parse()?)?,
+                    volume: r[6].parse()?,
+                    volume_24h: None,
+                    trade_count: 0,
+                    buy_volume: 0.0,
+                    sell_volume: 0.0,
+                    is_synthetic: false,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        retain_closed(&mut klines, interval_ms);
+        Ok(klines)
+    }
+
+    fn klines_page_limit(&self) -> usize {
+        KLINES_LIMIT
+    }
+
+    async fn stream_tickers(&self, symbols: &[String], tx: mpsc::Sender<TickerInfo>) -> Result<()> {
+        run_ws_streams("okx", ws_subscriptions(symbols, "tickers"), parse_ws_ticker, tx).await
+    }
+
+    async fn stream_trades(&self, symbols: &[String], tx: mpsc::Sender<TradeData>) -> Result<()> {
+        // Swap trade sizes are in contracts; scale them by ctVal so volumes are in the base currency
+        let contract_values: HashMap<String, f64> = self
+            .get_instruments()
             .await
-            .context("JSON parse failed")?;
+            .context("Failed to load OKX contract values")?
+            .into_iter()
+            .map(|i| (i.instrument.native_symbol, i.contract_value))
+            .collect();
+
+        let (raw_tx, mut raw_rx) = mpsc::channel(TRADE_BUFFER);
+        let forward = async move {
+            let mut filter = TradeFilter::new(contract_values);
+            while let Some(trade) = raw_rx.recv().await {
+                if let Some(trade) = filter.apply(trade)
+                    && tx.send(trade).await.is_err()
+                {
+                    break;
+                }
+            }
+        };
+
+        let (result, _) = tokio::join!(
+            run_ws_streams("okx", ws_subscriptions(symbols, "trades"), parse_ws_trade, raw_tx),
+            forward
+        );
+        result
+    }
+}
+
+fn parse_ws_ticker(text: &str) -> Vec<TickerInfo> {
+    // "pong" and subscribe events carry no data array and are skipped
+    match serde_json::from_str::<WsDataMessage<OkxWsTicker>>(text) {
+        Ok(msg) => msg
+            .data
+            .into_iter()
+            .map(|o| TickerInfo {
+                symbol: o.instrument_id,
+                last_price: o.last,
+                vol_24h: Some(o.volume_24h),
+                timestamp: o.ts.and_then(|ts| ts.parse().ok()),
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// One subscription per connection-sized chunk of symbols, for the given channel
+fn ws_subscriptions(symbols: &[String], channel: &str) -> Vec<WsSubscription> {
+    symbols
+        .chunks(WS_SYMBOLS_PER_CONNECTION)
+        .map(|chunk| {
+            let args: Vec<serde_json::Value> = chunk
+                .iter()
+                .map(|s| serde_json::json!({ "channel": channel, "instId": s }))
+                .collect();
+            let subscribe = serde_json::json!({ "op": "subscribe", "args": args });
 
-        Ok(ticker)
-    }*/
+            // OKX drops connections that stay silent for 30s
+            WsSubscription {
+                url: WS_URL.to_string(),
+                subscribe_messages: vec![subscribe.to_string()],
+                ping_message: Some("ping".to_string()),
+                ping_interval: Duration::from_secs(25),
+            }
+        })
+        .collect()
+}
+
+fn parse_ws_trade(text: &str) -> Vec<TradeData> {
+    match serde_json::from_str::<WsDataMessage<OkxWsTrade>>(text) {
+        Ok(msg) => msg
+            .data
+            .into_iter()
+            .filter_map(|o| {
+                Some(TradeData {
+                    price: o.px.parse().ok()?,
+                    qty: o.sz.parse().ok()?,
+                    side: if o.side == "buy" { TradeSide::Buy } else { TradeSide::Sell },
+                    timestamp: o.ts.parse().ok()?,
+                    count: o.count.and_then(|c| c.parse().ok()).unwrap_or(1),
+                    symbol: o.instrument_id,
+                    trade_id: o.trade_id,
+                })
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Scales trade sizes from contracts to base quantity and drops trades redelivered after a reconnect
+struct TradeFilter {
+    contract_values: HashMap<String, f64>,
+    last_trade_ids: HashMap<String, u64>,
+}
+
+impl TradeFilter {
+    fn new(contract_values: HashMap<String, f64>) -> Self {
+        Self {
+            contract_values,
+            last_trade_ids: HashMap::new(),
+        }
+    }
+
+    fn apply(&mut self, mut trade: TradeData) -> Option<TradeData> {
+        // OKX trade ids increase per instrument; anything at or below the last one was already counted
+        if let Ok(id) = trade.trade_id.parse::<u64>() {
+            match self.last_trade_ids.get_mut(&trade.symbol) {
+                Some(last) if id <= *last => return None,
+                Some(last) => *last = id,
+                None => {
+                    self.last_trade_ids.insert(trade.symbol.clone(), id);
+                }
+            }
+        }
+        trade.qty *= self.contract_values.get(&trade.symbol).copied().unwrap_or(1.0);
+        Some(trade)
+    }
+}
+
+// OKX bar names; daily candles use the UTC-aligned variant instead of Hong Kong time
+fn okx_bar(interval: &str) -> Result<&'static str> {
+    match interval {
+        "1m" => Ok("1m"),
+        "3m" => Ok("3m"),
+        "5m" => Ok("5m"),
+        "15m" => Ok("15m"),
+        "1h" => Ok("1H"),
+        "4h" => Ok("4H"),
+        "1d" => Ok("1Dutc"),
+        _ => Err(anyhow!("unsupported interval for OKX: {}", interval)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(symbol: &str, trade_id: &str, qty: f64) -> TradeData {
+        TradeData {
+            symbol: symbol.to_string(),
+            price: 100.0,
+            qty,
+            side: TradeSide::Buy,
+            trade_id: trade_id.to_string(),
+            timestamp: 0,
+            count: 1,
+        }
+    }
+
+    #[test]
+    fn trades_are_scaled_by_contract_value_and_redeliveries_dropped() {
+        let mut filter = TradeFilter::new(HashMap::from([("BTC-USDT-SWAP".to_string(), 0.01)]));
+
+        let first = filter.apply(trade("BTC-USDT-SWAP", "100", 5.0)).unwrap();
+        assert!((first.qty - 0.05).abs() < 1e-12);
+
+        // Replayed after a reconnect, with ids at or below the last one seen
+        assert!(filter.apply(trade("BTC-USDT-SWAP", "100", 5.0)).is_none());
+        assert!(filter.apply(trade("BTC-USDT-SWAP", "99", 1.0)).is_none());
+        assert!(filter.apply(trade("BTC-USDT-SWAP", "101", 2.0)).is_some());
+
+        // Ids are tracked per instrument; unknown instruments keep their size
+        let other = filter.apply(trade("ETH-USDT-SWAP", "50", 3.0)).unwrap();
+        assert_eq!(other.qty, 3.0);
+    }
 }
@@ -0,0 +1,26 @@
+use crate::pkg::exchanges::rate_limited_client::{Limit, RateLimitPolicy};
+use reqwest::Url;
+use std::time::Duration;
+
+/// OKX sends no rate limit headers; every public endpoint has its own documented
+/// budget per IP, mostly 20 requests per 2 seconds.
+pub struct OkxRateLimit;
+
+impl RateLimitPolicy for OkxRateLimit {
+    fn name(&self) -> &'static str {
+        "okx"
+    }
+
+    fn costs(&self, url: &Url) -> Vec<(String, u32)> {
+        vec![(url.path().to_string(), 1)]
+    }
+
+    fn hard_limit(&self, bucket: &str) -> Option<Limit> {
+        let per_two_seconds = match bucket {
+            "/api/v5/market/candles" => 40,
+            // history-candles, tickers, public/instruments, ...
+            _ => 20,
+        };
+        Some(Limit::new(per_two_seconds, Duration::from_secs(2)))
+    }
+}
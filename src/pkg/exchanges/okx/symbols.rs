@@ -0,0 +1,44 @@
+use crate::pkg::exchanges::instrument::{Instrument, InstrumentKind};
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+
+// BTC-USDT (spot), BTC-USDT-SWAP (linear perpetual), BTC-USD-SWAP (inverse), BTC-USDT-250926 (future)
+
+pub fn parse(native: &str) -> Result<Instrument> {
+    let native = native.to_uppercase();
+    let parts: Vec<&str> = native.split('-').collect();
+
+    let (base, quote, kind, expiry) = match parts.as_slice() {
+        [base, quote] => (*base, *quote, InstrumentKind::Spot, None),
+        [base, quote, "SWAP"] => (*base, *quote, InstrumentKind::Perpetual, None),
+        [base, quote, date] => {
+            let expiry = NaiveDate::parse_from_str(date, "%y%m%d")
+                .map_err(|_| anyhow!("invalid OKX expiry: {}", native))?;
+            (*base, *quote, InstrumentKind::DatedFuture, Some(expiry))
+        }
+        _ => return Err(anyhow!("unknown OKX symbol: {}", native)),
+    };
+
+    // USD-quoted derivatives are coin margined
+    let settle = if kind != InstrumentKind::Spot && quote == "USD" { base } else { quote };
+
+    Ok(Instrument {
+        exchange: "okx".to_string(),
+        base: base.to_string(),
+        quote: quote.to_string(),
+        settle: settle.to_string(),
+        kind,
+        expiry,
+        native_symbol: native.clone(),
+    })
+}
+
+pub fn format(instrument: &Instrument) -> Result<String> {
+    let pair = format!("{}-{}", instrument.base, instrument.quote);
+    match (instrument.kind, instrument.expiry) {
+        (InstrumentKind::Spot, _) => Ok(pair),
+        (InstrumentKind::Perpetual, _) => Ok(format!("{}-SWAP", pair)),
+        (InstrumentKind::DatedFuture, Some(expiry)) => Ok(format!("{}-{}", pair, expiry.format("%y%m%d"))),
+        _ => Err(anyhow!("OKX has no {}", instrument.canonical())),
+    }
+}
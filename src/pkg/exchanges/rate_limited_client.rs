@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use log::{error, warn};
+use rand::Rng;
+use reqwest::header::HeaderMap;
+use reqwest::{Client, Request, Response, StatusCode, Url};
+use tokio::sync::Mutex;
+
+/// Share of every limit kept in reserve for requests we can't see (other processes, WS logins, ...)
+const HEADROOM_RATIO: f64 = 0.1;
+
+/// A request budget of `limit` units per `window`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limit {
+    pub limit: u32,
+    pub window: Duration,
+}
+
+impl Limit {
+    pub const fn new(limit: u32, window: Duration) -> Self {
+        Self { limit, window }
+    }
+}
+
+/// What an exchange reported about a bucket in its response headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Observed {
+    /// Units already spent in the current window
+    pub used: u32,
+    /// Overrides the policy's hard limit when the exchange reports its own
+    pub limit: Option<u32>,
+    /// Time until the window resets, when the exchange says
+    pub reset_in: Option<Duration>,
+}
+
+/// Exchange specific rate limit rules plugged into `RateLimitedClient`.
+///
+/// A request is charged against one or more named buckets (an IP-wide weight pool,
+/// a per-endpoint counter, ...). Each bucket has an optional documented hard limit,
+/// and can be corrected from the exchange's response headers.
+pub trait RateLimitPolicy: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Buckets charged by a request to `url` and the weight charged to each
+    fn costs(&self, url: &Url) -> Vec<(String, u32)>;
+
+    /// Documented limit for a bucket; `None` means only headers are trusted
+    fn hard_limit(&self, bucket: &str) -> Option<Limit>;
+
+    /// Bucket state reported by the exchange in a response to `url`
+    fn observe(&self, _url: &Url, _headers: &HeaderMap) -> Vec<(String, Observed)> {
+        Vec::new()
+    }
+
+    fn is_rate_limited(&self, status: StatusCode) -> bool {
+        status == StatusCode::TOO_MANY_REQUESTS || status.as_u16() == 418
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BucketState {
+    used: u32,
+    limit: u32,
+    window: Duration,
+    reset_time: Instant,
+}
+
+impl BucketState {
+    fn new(limit: Limit) -> Self {
+        Self {
+            used: 0,
+            limit: limit.limit,
+            window: limit.window,
+            reset_time: Instant::now() + limit.window,
+        }
+    }
+
+    fn roll(&mut self, now: Instant) {
+        if now >= self.reset_time {
+            self.used = 0;
+            self.reset_time = now + self.window;
+        }
+    }
+
+    fn capacity(&self) -> u32 {
+        let reserve = (self.limit as f64 * HEADROOM_RATIO) as u32;
+        (self.limit - reserve).max(1)
+    }
+}
+
+/// HTTP client shared by all exchange adapters: throttles requests against the
+/// exchange's `RateLimitPolicy` and retries rate limited or transient failures.
+pub struct RateLimitedClient {
+    http_client: Client,
+    policy: Box<dyn RateLimitPolicy>,
+    buckets: Mutex<HashMap<String, BucketState>>,
+    max_retries: usize,
+}
+
+impl RateLimitedClient {
+    pub fn new(client: Option<Client>, policy: impl RateLimitPolicy + 'static) -> Self {
+        let http_client = client.unwrap_or_default();
+        Self {
+            http_client,
+            policy: Box::new(policy),
+            buckets: Mutex::new(HashMap::new()),
+            max_retries: 5,
+        }
+    }
+
+    pub async fn send_with_retry(&self, req: Request) -> Result<Response, reqwest::Error> {
+        let mut retry_count = 0;
+        let costs = self.policy.costs(req.url());
+
+        loop {
+            self.acquire(&costs).await;
+
+            let resp_result = self
+                .http_client
+                .execute(req.try_clone().expect("Failed to clone request"))
+                .await;
+
+            match resp_result {
+                Ok(resp) => {
+                    self.update_rate_limits(req.url(), resp.headers()).await;
+
+                    if self.policy.is_rate_limited(resp.status()) {
+                        if retry_count >= self.max_retries {
+                            error!("[{}] Max retries reached: {}", self.policy.name(), resp.status());
+                            return Ok(resp);
+                        }
+                        let delay = get_retry_delay(Some(&resp), retry_count);
+                        warn!("[{}] Rate limited, retrying after {:?}", self.policy.name(), delay);
+                        tokio::time::sleep(delay).await;
+                        retry_count += 1;
+                        continue;
+                    }
+                    return Ok(resp);
+                }
+                Err(e) => {
+                    if is_transient(&e) {
+                        if retry_count >= self.max_retries {
+                            error!("[{}] Transient error after max retries: {}", self.policy.name(), e);
+                            return Err(e);
+                        }
+                        let delay = get_retry_delay(None, retry_count);
+                        warn!("[{}] Transient error: {}. Retrying after {:?}", self.policy.name(), e, delay);
+                        tokio::time::sleep(delay).await;
+                        retry_count += 1;
+                        continue;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Wait until every bucket has room for its weight, then charge them.
+    /// The lock is never held while sleeping so other requests keep flowing.
+    async fn acquire(&self, costs: &[(String, u32)]) {
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().await;
+                let now = Instant::now();
+                let mut wait = Duration::ZERO;
+
+                for (key, weight) in costs {
+                    let state = match buckets.get_mut(key) {
+                        Some(state) => state,
+                        None => match self.policy.hard_limit(key) {
+                            Some(limit) => buckets.entry(key.clone()).or_insert(BucketState::new(limit)),
+                            None => continue,
+                        },
+                    };
+                    state.roll(now);
+                    // A request heavier than the whole budget still goes out on a fresh window
+                    if state.used > 0 && state.used + weight > state.capacity() {
+                        wait = wait.max(state.reset_time.saturating_duration_since(now));
+                    }
+                }
+
+                if wait.is_zero() {
+                    for (key, weight) in costs {
+                        if let Some(state) = buckets.get_mut(key) {
+                            state.used += weight;
+                        }
+                    }
+                }
+                wait
+            };
+
+            if wait.is_zero() {
+                return;
+            }
+            warn!("[{}] Delaying request for {:?} due to rate limit", self.policy.name(), wait);
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    async fn update_rate_limits(&self, url: &Url, headers: &HeaderMap) {
+        let observed = self.policy.observe(url, headers);
+        if observed.is_empty() {
+            return;
+        }
+
+        let mut buckets = self.buckets.lock().await;
+        let now = Instant::now();
+
+        for (key, obs) in observed {
+            let hard = self.policy.hard_limit(&key);
+            let Some(limit) = obs.limit.or(hard.map(|l| l.limit)) else {
+                continue;
+            };
+            let window = obs
+                .reset_in
+                .or(hard.map(|l| l.window))
+                .unwrap_or(Duration::from_secs(1));
+
+            let state = buckets
+                .entry(key)
+                .or_insert_with(|| BucketState::new(Limit::new(limit, window)));
+            state.used = obs.used;
+            state.limit = limit;
+            if let Some(reset_in) = obs.reset_in {
+                state.reset_time = now + reset_in;
+            }
+        }
+    }
+}
+
+fn get_retry_delay(resp: Option<&Response>, retry: usize) -> Duration {
+    if let Some(resp) = resp
+        && let Some(val) = resp.headers().get("Retry-After")
+        && let Ok(s) = val.to_str()
+        && let Ok(seconds) = s.parse::<u64>()
+    {
+        return Duration::from_secs(seconds);
+    }
+    let base = 2_f64.powf(retry as f64);
+    let jitter: f64 = rand::thread_rng().gen_range(0.75..1.25);
+    Duration::from_secs_f64(base * jitter)
+}
+
+fn is_transient(err: &reqwest::Error) -> bool {
+    if err.is_timeout() {
+        return true;
+    }
+    let err_str = err.to_string().to_lowercase();
+    err_str.contains("timeout") || err_str.contains("connection reset") || err_str.contains("temporary")
+}
+
+/// Header value parsed as a number, `None` when missing or malformed
+pub(crate) fn header_num<T: std::str::FromStr>(headers: &HeaderMap, name: &str) -> Option<T> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// Time left until a unix timestamp in milliseconds, `None` if it has already passed
+pub(crate) fn until_unix_ms(reset_ms: i64) -> Option<Duration> {
+    let remaining = reset_ms - chrono::Utc::now().timestamp_millis();
+    (remaining > 0).then(|| Duration::from_millis(remaining as u64))
+}
+
+/// Query parameter value of a request url
+pub(crate) fn query_param(url: &Url, name: &str) -> Option<String> {
+    url.query_pairs().find(|(k, _)| k == name).map(|(_, v)| v.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pkg::exchanges::binance::rate_limit::BinanceRateLimit;
+    use crate::pkg::exchanges::bybit::rate_limit::BybitRateLimit;
+    use crate::pkg::exchanges::okx::rate_limit::OkxRateLimit;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Two requests per 300ms on every endpoint
+    struct TightPolicy;
+
+    impl RateLimitPolicy for TightPolicy {
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        fn costs(&self, url: &Url) -> Vec<(String, u32)> {
+            vec![(url.path().to_string(), 1)]
+        }
+
+        fn hard_limit(&self, _bucket: &str) -> Option<Limit> {
+            Some(Limit::new(2, Duration::from_millis(300)))
+        }
+    }
+
+    fn get(server: &MockServer, route: &str) -> Request {
+        Client::new()
+            .get(format!("{}{}", server.uri(), route))
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn hard_limit_spaces_requests() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/market"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let client = RateLimitedClient::new(None, TightPolicy);
+        let started = Instant::now();
+        for _ in 0..3 {
+            let resp = client.send_with_retry(get(&server, "/market")).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+
+        // The third request has to wait for the window to roll over
+        assert!(started.elapsed() >= Duration::from_millis(250), "{:?}", started.elapsed());
+    }
+
+    #[tokio::test]
+    async fn retries_after_429_with_retry_after() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/busy"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/busy"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let client = RateLimitedClient::new(None, TightPolicy);
+        let resp = client.send_with_retry(get(&server, "/busy")).await.unwrap();
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn binance_used_weight_header_updates_bucket() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/fapi/v1/klines"))
+            .respond_with(ResponseTemplate::new(200).insert_header("X-MBX-USED-WEIGHT-1M", "2300"))
+            .mount(&server)
+            .await;
+
+        let client = RateLimitedClient::new(None, BinanceRateLimit);
+        client
+            .send_with_retry(get(&server, "/fapi/v1/klines?symbol=BTCUSDT&interval=1m&limit=1500"))
+            .await
+            .unwrap();
+
+        let buckets = client.buckets.lock().await;
+        let weight = buckets.get("weight").unwrap();
+        assert_eq!(weight.used, 2300);
+        assert_eq!(weight.limit, 2400);
+    }
+
+    #[tokio::test]
+    async fn bybit_limit_status_header_throttles_endpoint() {
+        let server = MockServer::start().await;
+        let reset_ms = chrono::Utc::now().timestamp_millis() + 500;
+        Mock::given(method("GET"))
+            .and(path("/v5/market/kline"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("X-Bapi-Limit-Status", "0")
+                    .insert_header("X-Bapi-Limit", "10")
+                    .insert_header("X-Bapi-Limit-Reset-Timestamp", reset_ms.to_string().as_str()),
+            )
+            .mount(&server)
+            .await;
+
+        let client = RateLimitedClient::new(None, BybitRateLimit);
+        client.send_with_retry(get(&server, "/v5/market/kline")).await.unwrap();
+
+        client.send_with_retry(get(&server, "/v5/market/kline")).await.unwrap();
+
+        // Nothing left on the endpoint, so the second call waits for the reported reset
+        let finished_ms = chrono::Utc::now().timestamp_millis();
+        assert!(finished_ms >= reset_ms - 20, "finished {}ms before reset", reset_ms - finished_ms);
+    }
+
+    #[test]
+    fn binance_weights_follow_endpoint_and_limit() {
+        let weight = |url: &str| BinanceRateLimit.costs(&Url::parse(url).unwrap())[0].1;
+
+        assert_eq!(weight("https://fapi.binance.com/fapi/v1/klines?symbol=BTCUSDT&limit=1500"), 10);
+        assert_eq!(weight("https://fapi.binance.com/fapi/v1/klines?symbol=BTCUSDT&limit=99"), 1);
+        assert_eq!(weight("https://fapi.binance.com/fapi/v1/klines?symbol=BTCUSDT"), 5);
+        assert_eq!(weight("https://fapi.binance.com/fapi/v1/ticker/24hr"), 40);
+        assert_eq!(weight("https://fapi.binance.com/fapi/v1/ticker/24hr?symbol=BTCUSDT"), 1);
+    }
+
+    #[test]
+    fn okx_endpoints_have_documented_limits() {
+        let two_secs = Duration::from_secs(2);
+
+        assert_eq!(
+            OkxRateLimit.hard_limit("/api/v5/market/history-candles"),
+            Some(Limit::new(20, two_secs))
+        );
+        assert_eq!(OkxRateLimit.hard_limit("/api/v5/market/candles"), Some(Limit::new(40, two_secs)));
+    }
+}
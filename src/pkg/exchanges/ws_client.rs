@@ -0,0 +1,185 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use futures_util::{SinkExt, StreamExt};
+use log::{error, info, warn};
+use rand::Rng;
+use tokio::sync::mpsc;
+use tokio::time::{Instant, interval_at};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+/// Reconnect if nothing (data, pong, ...) arrives for this long
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_BACKOFF_SECS: f64 = 60.0;
+
+/// One websocket connection and the messages needed to (re)subscribe on it
+pub struct WsSubscription {
+    pub url: String,
+    pub subscribe_messages: Vec<String>,
+    pub ping_message: Option<String>,
+    pub ping_interval: Duration,
+}
+
+/// Run one websocket connection per subscription until the receiver side of `tx` is dropped.
+/// Every connection reconnects with backoff and resubscribes on its own.
+pub async fn run_ws_streams<T: Send>(
+    name: &str,
+    subscriptions: Vec<WsSubscription>,
+    parse: fn(&str) -> Vec<T>,
+    tx: mpsc::Sender<T>,
+) -> Result<()> {
+    let streams = subscriptions
+        .into_iter()
+        .enumerate()
+        .map(|(idx, sub)| run_ws_stream(name, idx, sub, parse, tx.clone(), IDLE_TIMEOUT));
+
+    futures_util::future::join_all(streams).await;
+    Ok(())
+}
+
+async fn run_ws_stream<T: Send>(
+    name: &str,
+    idx: usize,
+    sub: WsSubscription,
+    parse: fn(&str) -> Vec<T>,
+    tx: mpsc::Sender<T>,
+    idle_timeout: Duration,
+) {
+    let mut attempt: u32 = 0;
+
+    loop {
+        if tx.is_closed() {
+            return;
+        }
+
+        match connect_async(sub.url.as_str()).await {
+            Ok((ws, _)) => {
+                info!("🔌 [{}#{}] WebSocket connected to {}", name, idx, sub.url);
+                let (mut write, mut read) = ws.split();
+
+                let mut subscribed = true;
+                for msg in &sub.subscribe_messages {
+                    if let Err(e) = write.send(Message::Text(msg.clone())).await {
+                        error!("❌ [{}#{}] Failed to send subscription: {}", name, idx, e);
+                        subscribed = false;
+                        break;
+                    }
+                    // Exchanges cap incoming messages per second on public sockets
+                    tokio::time::sleep(Duration::from_millis(150)).await;
+                }
+
+                if subscribed {
+                    attempt = 0;
+                    let mut ping = interval_at(Instant::now() + sub.ping_interval, sub.ping_interval);
+                    // Only received frames push this back; our own pings don't prove the socket is alive
+                    let idle = tokio::time::sleep(idle_timeout);
+                    tokio::pin!(idle);
+
+                    loop {
+                        tokio::select! {
+                            msg = read.next() => {
+                                idle.as_mut().reset(Instant::now() + idle_timeout);
+                                match msg {
+                                    Some(Ok(Message::Text(text))) => {
+                                        for item in parse(&text) {
+                                            if tx.send(item).await.is_err() {
+                                                return;
+                                            }
+                                        }
+                                    }
+                                    Some(Ok(Message::Ping(payload))) => {
+                                        if write.send(Message::Pong(payload)).await.is_err() {
+                                            break;
+                                        }
+                                    }
+                                    Some(Ok(Message::Close(frame))) => {
+                                        warn!("⚠️ [{}#{}] WebSocket closed by server: {:?}", name, idx, frame);
+                                        break;
+                                    }
+                                    Some(Ok(_)) => {}
+                                    Some(Err(e)) => {
+                                        warn!("⚠️ [{}#{}] WebSocket error: {}", name, idx, e);
+                                        break;
+                                    }
+                                    None => {
+                                        warn!("⚠️ [{}#{}] WebSocket stream ended", name, idx);
+                                        break;
+                                    }
+                                }
+                            }
+                            _ = &mut idle => {
+                                warn!("⚠️ [{}#{}] No data for {:?}, reconnecting", name, idx, idle_timeout);
+                                break;
+                            }
+                            _ = ping.tick() => {
+                                if let Some(ref ping_msg) = sub.ping_message
+                                    && write.send(Message::Text(ping_msg.clone())).await.is_err()
+                                {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                error!("❌ [{}#{}] WebSocket connect failed: {}", name, idx, e);
+            }
+        }
+
+        let delay = reconnect_delay(attempt);
+        attempt = attempt.saturating_add(1);
+        warn!("🔁 [{}#{}] Reconnecting in {:?}", name, idx, delay);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+fn reconnect_delay(attempt: u32) -> Duration {
+    let base = 2_f64.powi(attempt.min(6) as i32).min(MAX_BACKOFF_SECS);
+    let jitter: f64 = rand::thread_rng().gen_range(0.75..1.25);
+    Duration::from_secs_f64(base * jitter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn silent_socket_reconnects_despite_pings() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (accepted_tx, mut accepted_rx) = mpsc::unbounded_channel();
+
+        // Accepts every connection and reads what the client sends, but never answers
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let accepted_tx = accepted_tx.clone();
+                tokio::spawn(async move {
+                    let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                    let _ = accepted_tx.send(());
+                    while let Some(Ok(_)) = ws.next().await {}
+                });
+            }
+        });
+
+        let sub = WsSubscription {
+            url,
+            subscribe_messages: vec!["{\"op\":\"subscribe\"}".to_string()],
+            ping_message: Some("ping".to_string()),
+            ping_interval: Duration::from_millis(50),
+        };
+        let (tx, _rx) = mpsc::channel::<String>(1);
+        let stream = tokio::spawn(async move {
+            run_ws_stream("test", 0, sub, |_| Vec::new(), tx, Duration::from_millis(300)).await
+        });
+
+        for _ in 0..2 {
+            tokio::time::timeout(Duration::from_secs(5), accepted_rx.recv())
+                .await
+                .expect("client reconnects after the idle timeout")
+                .unwrap();
+        }
+        stream.abort();
+    }
+}
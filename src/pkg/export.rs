@@ -0,0 +1,103 @@
+use crate::pkg::dbcontext::entities::SymbolKlineData;
+use crate::pkg::store::{DEFAULT_PAGE_SIZE, KlineRange, KlineStore};
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::io::Write;
+
+pub struct ExportRequest {
+    pub exchange: String,
+    pub symbols: Vec<String>,
+    pub interval: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// One row per open time with every symbol's close instead of one row per candle
+    pub aligned: bool,
+}
+
+const CANDLE_HEADER: &str = "exchange,symbol,interval,open_time,open,high,low,close,volume,volume_24h,\
+    trade_count,buy_volume,sell_volume,is_synthetic";
+
+/// Write the stored candles of the request as CSV, page by page; returns the rows written
+pub async fn run_export(store: &dyn KlineStore, req: &ExportRequest, out: &mut dyn Write) -> Result<usize> {
+    if req.aligned {
+        return export_aligned(store, req, out).await;
+    }
+
+    writeln!(out, "{}", CANDLE_HEADER)?;
+    let mut written = 0;
+    for symbol in &req.symbols {
+        let range = KlineRange {
+            exchange: req.exchange.clone(),
+            symbol: symbol.clone(),
+            interval: req.interval.clone(),
+            start: req.start,
+            end: req.end,
+        };
+        let mut after = None;
+        loop {
+            let page = store.klines(&range, after, DEFAULT_PAGE_SIZE).await?;
+            for kline in &page.klines {
+                writeln!(out, "{}", candle_line(kline))?;
+            }
+            written += page.klines.len();
+            match page.next {
+                Some(next) => after = Some(next),
+                None => break,
+            }
+        }
+    }
+    out.flush()?;
+    Ok(written)
+}
+
+// Helper: `open_time,<symbol>...` rows of closes, empty where a symbol has no candle
+async fn export_aligned(store: &dyn KlineStore, req: &ExportRequest, out: &mut dyn Write) -> Result<usize> {
+    writeln!(out, "open_time,{}", req.symbols.join(","))?;
+    let mut written = 0;
+    let mut after = None;
+    loop {
+        let page = store
+            .aligned_klines(&req.exchange, &req.symbols, &req.interval, req.start, req.end, after, DEFAULT_PAGE_SIZE)
+            .await?;
+        for row in &page.rows {
+            let closes: Vec<String> = row
+                .klines
+                .iter()
+                .map(|k| k.as_ref().map_or_else(String::new, |k| k.close.to_string()))
+                .collect();
+            writeln!(out, "{},{}", timestamp(row.open_time), closes.join(","))?;
+        }
+        written += page.rows.len();
+        match page.next {
+            Some(next) => after = Some(next),
+            None => break,
+        }
+    }
+    out.flush()?;
+    Ok(written)
+}
+
+// Helper: one CSV line in `CANDLE_HEADER` order
+fn candle_line(k: &SymbolKlineData) -> String {
+    format!(
+        "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+        k.exchange,
+        k.symbol,
+        k.interval,
+        timestamp(k.open_time),
+        k.open,
+        k.high,
+        k.low,
+        k.close,
+        k.volume,
+        k.volume_24h.map_or_else(String::new, |v| v.to_string()),
+        k.trade_count,
+        k.buy_volume,
+        k.sell_volume,
+        k.is_synthetic
+    )
+}
+
+fn timestamp(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
@@ -0,0 +1,197 @@
+use crate::pkg::aggregator::intervals::interval_to_ms;
+use crate::pkg::backfill::page_ranges;
+use crate::pkg::exchanges::exchange::ExchangeApi;
+use crate::pkg::exchanges::exchange_client::kline_open_time;
+use crate::pkg::storage::StorageBackend;
+use crate::pkg::store::KlineRange;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use std::collections::HashSet;
+
+/// Scans stop this many minutes before now so candles still being flushed aren't reported
+pub const GAP_SCAN_SETTLE_MINUTES: i64 = 2;
+
+/// A run of consecutive missing candles, `first`..=`last` being open times
+#[derive(Debug, Clone)]
+pub struct Gap {
+    pub symbol: String,
+    pub interval: String,
+    pub first: DateTime<Utc>,
+    pub last: DateTime<Utc>,
+    pub missing: usize,
+}
+
+pub struct GapScanRequest {
+    pub exchange: String,
+    pub instance: String,
+    pub symbols: Vec<String>,
+    pub interval: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub repair: bool,
+}
+
+/// Find missing `open_time` buckets per symbol in [start, end] of the given storage
+pub async fn scan_gaps(storage: &StorageBackend, req: &GapScanRequest) -> Result<Vec<Gap>> {
+    let interval = req.interval.as_str();
+    let interval_ms = interval_to_ms(interval).ok_or_else(|| anyhow!("unsupported interval: {}", interval))?;
+    let start_ms = req.start.timestamp_millis() - req.start.timestamp_millis().rem_euclid(interval_ms);
+    let end_ms = req.end.timestamp_millis() - req.end.timestamp_millis().rem_euclid(interval_ms);
+    if end_ms < start_ms {
+        return Err(anyhow!("gap scan range is empty: {} > {}", req.start, req.end));
+    }
+
+    let mut gaps = Vec::new();
+    for symbol in &req.symbols {
+        let range = KlineRange {
+            exchange: req.exchange.clone(),
+            symbol: symbol.clone(),
+            interval: interval.to_string(),
+            start: kline_open_time(start_ms)?,
+            end: kline_open_time(end_ms)?,
+        };
+        let stored = storage.store().open_times(&range).await?;
+        let present: HashSet<i64> = stored.iter().map(|t| t.timestamp_millis()).collect();
+
+        for (first, last) in missing_runs(&present, start_ms, end_ms, interval_ms) {
+            gaps.push(Gap {
+                symbol: symbol.clone(),
+                interval: interval.to_string(),
+                first: kline_open_time(first)?,
+                last: kline_open_time(last)?,
+                missing: ((last - first) / interval_ms + 1) as usize,
+            });
+        }
+    }
+
+    Ok(gaps)
+}
+
+/// Fetch the missing candles from the exchange and store them; returns how many were written
+pub async fn repair_gaps(
+    api: &dyn ExchangeApi,
+    storage: &StorageBackend,
+    gaps: &[Gap],
+    instance: &str,
+) -> Result<usize> {
+    let mut repaired = 0;
+    let mut written: Option<(i64, i64)> = None; // open times of the candles written, for the aggregates
+
+    for gap in gaps {
+        let interval_ms =
+            interval_to_ms(&gap.interval).ok_or_else(|| anyhow!("unsupported interval: {}", gap.interval))?;
+        let pages = page_ranges(
+            gap.first.timestamp_millis(),
+            gap.last.timestamp_millis(),
+            interval_ms,
+            api.klines_page_limit(),
+        );
+
+        for (page_start, page_end) in pages {
+            let klines = match api
+                .get_klines(&gap.symbol, &gap.interval, kline_open_time(page_start)?, kline_open_time(page_end)?)
+                .await
+            {
+                Ok(k) => k,
+                Err(e) => {
+                    error!("❌ Failed to fetch klines to repair {} gap at {}: {:?}", gap.symbol, gap.first, e);
+                    continue;
+                }
+            };
+
+            if klines.is_empty() {
+                // Nothing traded or symbol not listed yet; the exchange has no candle either
+                continue;
+            }
+
+            storage.save(&klines, instance).await?;
+            repaired += klines.len();
+            written = Some(written.map_or((page_start, page_end), |(s, e)| (s.min(page_start), e.max(page_end))));
+        }
+    }
+
+    // Repaired candles can be older than the aggregates' refresh policy looks back
+    if let Some((first, last)) = written
+        && let Some(gap) = gaps.first()
+        && let Err(e) = storage.refresh_aggregates(&gap.interval, kline_open_time(first)?, kline_open_time(last)?).await
+    {
+        error!("❌ Refreshing continuous aggregates after the gap repair failed: {:?}", e);
+    }
+
+    Ok(repaired)
+}
+
+/// Scan and optionally repair, logging what was found
+pub async fn scan_and_repair(api: &dyn ExchangeApi, storage: &StorageBackend, req: &GapScanRequest) -> Result<Vec<Gap>> {
+    info!(
+        "🔍 Scanning {} symbols [{}] in {} for gaps from {} to {}",
+        req.symbols.len(),
+        req.interval,
+        storage.name(),
+        req.start,
+        req.end
+    );
+    let gaps = scan_gaps(storage, req).await?;
+
+    if gaps.is_empty() {
+        info!("✅ No gaps found");
+        return Ok(gaps);
+    }
+
+    let missing: usize = gaps.iter().map(|g| g.missing).sum();
+    warn!("🕳️ Found {} gaps, {} missing candles", gaps.len(), missing);
+    for gap in &gaps {
+        info!("   {} [{}] {} → {} ({} missing)", gap.symbol, gap.interval, gap.first, gap.last, gap.missing);
+    }
+
+    if req.repair {
+        let repaired = repair_gaps(api, storage, &gaps, &req.instance).await?;
+        info!("🩹 Repaired {} of {} missing candles from the exchange", repaired, missing);
+    }
+
+    Ok(gaps)
+}
+
+// Helper: inclusive runs of bucket open times in [start_ms, end_ms] absent from `present`
+fn missing_runs(present: &HashSet<i64>, start_ms: i64, end_ms: i64, interval_ms: i64) -> Vec<(i64, i64)> {
+    let mut runs = Vec::new();
+    let mut run_start: Option<i64> = None;
+    let mut t = start_ms;
+
+    while t <= end_ms {
+        if present.contains(&t) {
+            if let Some(first) = run_start.take() {
+                runs.push((first, t - interval_ms));
+            }
+        } else if run_start.is_none() {
+            run_start = Some(t);
+        }
+        t += interval_ms;
+    }
+    if let Some(first) = run_start {
+        runs.push((first, end_ms));
+    }
+
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: i64 = 60_000;
+
+    fn present(minutes: &[i64]) -> HashSet<i64> {
+        minutes.iter().map(|m| m * MINUTE).collect()
+    }
+
+    #[test]
+    fn missing_minutes_merge_into_runs() {
+        // Leading gap, adjacent minutes 4-5 as one run, and a trailing gap
+        let runs = missing_runs(&present(&[2, 3, 6, 7]), 0, 9 * MINUTE, MINUTE);
+        assert_eq!(runs, vec![(0, MINUTE), (4 * MINUTE, 5 * MINUTE), (8 * MINUTE, 9 * MINUTE)]);
+
+        assert!(missing_runs(&present(&[0, 1, 2, 3]), 0, 3 * MINUTE, MINUTE).is_empty());
+    }
+}
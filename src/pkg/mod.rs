@@ -3,6 +3,17 @@ pub mod logger;
 pub mod postgre_db;
 pub mod clickhouse_client;
 pub mod save_config;
+pub mod storage;
+pub mod cli;
+pub mod backfill;
+pub mod gap_scanner;
+pub mod export;
+pub mod collector;
+pub mod symbol_selector;
+pub mod shutdown;
+pub mod spool;
+pub mod sink;
+pub mod store;
 
 pub mod exchanges;
 pub mod aggregator;
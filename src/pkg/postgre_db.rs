@@ -1,11 +1,22 @@
 use crate::pkg::config::DatabaseConfig;
+use crate::pkg::dbcontext::entities::SymbolKlineData;
+use crate::pkg::dbcontext::kline::{
+    KlineTable, get_klines, get_last_open_times, get_latest_klines, get_open_times, write_klines,
+};
+use crate::pkg::sink::{KlineSink, UpdatePolicy};
+use crate::pkg::store::{KlinePage, KlineRange, KlineStore};
 use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
 use sqlx::{PgPool, postgres::PgPoolOptions};
 use std::env;
 use log::{info, error};
 
 pub struct DB {
     pub pool: PgPool,
+    pub table: KlineTable,
+    copy_min_rows: usize,
 }
 
 impl DB {
@@ -46,6 +57,66 @@ impl DB {
 
         info!("✅ PostgreSQL connected");
 
-        Ok(Self { pool })
+        Ok(Self {
+            pool,
+            table: KlineTable::new(&database_config.schema, &database_config.table),
+            copy_min_rows: database_config.copy_min_rows,
+        })
     }
-}
\ No newline at end of file
+}
+
+#[async_trait]
+impl KlineSink for DB {
+    fn name(&self) -> &str {
+        "Postgres"
+    }
+
+    async fn write(&self, policy: UpdatePolicy, kline_data: &[SymbolKlineData], instance: &str) -> Result<()> {
+        write_klines(&self.pool, &self.table, kline_data, instance, policy, self.copy_min_rows).await
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+#[async_trait]
+impl KlineStore for DB {
+    fn name(&self) -> &str {
+        "Postgres"
+    }
+
+    async fn klines(&self, range: &KlineRange, after: Option<DateTime<Utc>>, limit: usize) -> Result<KlinePage> {
+        let limit = limit.max(1);
+        let klines = get_klines(
+            &self.pool,
+            &self.table,
+            &range.exchange,
+            &range.symbol,
+            &range.interval,
+            range.start,
+            range.end,
+            after,
+            limit,
+        )
+        .await?;
+        Ok(KlinePage::new(klines, limit))
+    }
+
+    async fn latest_klines(&self, exchange: &str, symbol: &str, interval: &str, count: usize) -> Result<Vec<SymbolKlineData>> {
+        get_latest_klines(&self.pool, &self.table, exchange, symbol, interval, count).await
+    }
+
+    async fn last_open_times(
+        &self,
+        exchange: &str,
+        symbols: &[String],
+        interval: &str,
+    ) -> Result<HashMap<String, DateTime<Utc>>> {
+        get_last_open_times(&self.pool, &self.table, exchange, symbols, interval).await
+    }
+
+    async fn open_times(&self, range: &KlineRange) -> Result<Vec<DateTime<Utc>>> {
+        get_open_times(&self.pool, &self.table, &range.exchange, &range.symbol, &range.interval, range.start, range.end).await
+    }
+}
@@ -0,0 +1,29 @@
+use tokio::signal;
+
+/// Process exit codes of the collector run
+pub const EXIT_OK: i32 = 0;
+/// A collector panicked or its last flush failed
+pub const EXIT_COLLECTOR_FAILED: i32 = 1;
+/// Collectors didn't finish their last writes within `Shutdown.TimeoutSeconds`
+pub const EXIT_SHUTDOWN_TIMEOUT: i32 = 2;
+/// Storage couldn't be initialized, nothing was started
+pub const EXIT_STARTUP_FAILED: i32 = 3;
+/// A second signal arrived while shutting down, pending writes were abandoned
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// Resolves with the signal's name on Ctrl+C, or SIGTERM on Unix (systemd, Docker)
+pub async fn wait_for_signal() -> anyhow::Result<&'static str> {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = signal::ctrl_c() => result.map(|_| "SIGINT").map_err(Into::into),
+            _ = terminate.recv() => Ok("SIGTERM"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        signal::ctrl_c().await?;
+        Ok("Ctrl+C")
+    }
+}
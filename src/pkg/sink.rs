@@ -0,0 +1,220 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::pkg::dbcontext::entities::SymbolKlineData;
+use crate::pkg::spool::{Spool, SpoolStats};
+
+/// What a write does with candles that are already stored under the same
+/// (exchange, symbol, interval, open time)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdatePolicy {
+    /// Leave the stored candle untouched
+    #[default]
+    Ignore,
+    /// Replace the stored candle (late tick amendments, exchange candles, corrections)
+    Overwrite,
+    /// Combine with the stored candle: max high, min low, latest close, summed volumes
+    Merge,
+}
+
+/// A backend finished candles are written to
+#[async_trait]
+pub trait KlineSink: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Write candles, handling stored ones as `policy` says
+    async fn write(&self, policy: UpdatePolicy, kline_data: &[SymbolKlineData], instance: &str) -> Result<()>;
+
+    /// Write candles, stored ones are left untouched
+    async fn save(&self, kline_data: &[SymbolKlineData], instance: &str) -> Result<()> {
+        self.write(UpdatePolicy::Ignore, kline_data, instance).await
+    }
+
+    /// Close connections once nothing writes anymore
+    async fn close(&self) {}
+}
+
+/// Retries of a write before it counts as failed, with doubling waits in between
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub backoff: Duration,
+}
+
+/// One backend of a fan-out with its own retries and optional spool
+pub struct SinkMember {
+    sink: Arc<dyn KlineSink>,
+    retry: RetryPolicy,
+    spool: Option<Spool>,
+}
+
+impl SinkMember {
+    pub fn new(sink: Arc<dyn KlineSink>, retry: RetryPolicy, spool: Option<Spool>) -> Self {
+        Self { sink, retry, spool }
+    }
+
+    // Helper: write with retries; with a spool the batch is accepted once it is on disk and the
+    // spool takes over retrying
+    async fn write(&self, policy: UpdatePolicy, kline_data: &[SymbolKlineData], instance: &str) -> Result<()> {
+        if let Some(spool) = &self.spool {
+            match spool.write(self.sink.as_ref(), policy, kline_data, instance).await {
+                Ok(()) => return Ok(()),
+                Err(e) => error!("❌ [{}] Failed to spool klines for {}, writing them directly: {:?}", instance, self.sink.name(), e),
+            }
+        }
+
+        let mut wait = self.retry.backoff;
+        let mut attempt = 0;
+        loop {
+            match self.sink.write(policy, kline_data, instance).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < self.retry.retries => {
+                    attempt += 1;
+                    warn!("⚠️ [{}] Write to {} failed, retry {}/{} in {:?}: {:?}", instance, self.sink.name(), attempt, self.retry.retries, wait, e);
+                    tokio::time::sleep(wait).await;
+                    wait *= 2;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Writes every batch to all its members concurrently. A failing member doesn't hold up or fail
+/// the others: each retries on its own, and a spooled member takes the batch as soon as it is
+/// on disk. Errors only when some member couldn't take the batch at all.
+pub struct FanOutSink {
+    members: Vec<SinkMember>,
+    name: String,
+}
+
+impl FanOutSink {
+    pub fn new(members: Vec<SinkMember>) -> Self {
+        let name = members.iter().map(|m| m.sink.name()).collect::<Vec<_>>().join("+");
+        Self { members, name }
+    }
+
+    /// Retry spooled batches of every member; returns how many were written
+    pub async fn replay_spools(&self) -> usize {
+        let mut written = 0;
+        for member in &self.members {
+            let Some(spool) = &member.spool else { continue };
+            if spool.stats().await.pending_batches == 0 {
+                continue;
+            }
+            match spool.replay(member.sink.as_ref()).await {
+                Ok(n) => written += n,
+                Err(e) => warn!("⚠️ Spool replay into {} failed, retrying later: {:?}", member.sink.name(), e),
+            }
+        }
+        written
+    }
+
+    /// Depth of every member's spool
+    pub async fn spool_stats(&self) -> Vec<(&str, SpoolStats)> {
+        let mut stats = Vec::new();
+        for member in &self.members {
+            if let Some(spool) = &member.spool {
+                stats.push((member.sink.name(), spool.stats().await));
+            }
+        }
+        stats
+    }
+}
+
+#[async_trait]
+impl KlineSink for FanOutSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn write(&self, policy: UpdatePolicy, kline_data: &[SymbolKlineData], instance: &str) -> Result<()> {
+        let results = join_all(self.members.iter().map(|m| m.write(policy, kline_data, instance))).await;
+
+        let failed: Vec<String> = self
+            .members
+            .iter()
+            .zip(results)
+            .filter_map(|(m, r)| r.err().map(|e| format!("{}: {:#}", m.sink.name(), e)))
+            .collect();
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("{} of {} sinks failed: {}", failed.len(), self.members.len(), failed.join("; ")))
+        }
+    }
+
+    async fn close(&self) {
+        for member in &self.members {
+            member.sink.close().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // Sink that records batch sizes and fails while `down` is set
+    struct TestSink {
+        name: &'static str,
+        down: bool,
+        written: Mutex<Vec<usize>>,
+    }
+
+    #[async_trait]
+    impl KlineSink for TestSink {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn write(&self, _policy: UpdatePolicy, kline_data: &[SymbolKlineData], _instance: &str) -> Result<()> {
+            if self.down {
+                return Err(anyhow!("{} is down", self.name));
+            }
+            self.written.lock().unwrap().push(kline_data.len());
+            Ok(())
+        }
+    }
+
+    fn sink(name: &'static str, down: bool) -> Arc<TestSink> {
+        Arc::new(TestSink { name, down, written: Mutex::new(Vec::new()) })
+    }
+
+    #[tokio::test]
+    async fn failing_member_does_not_block_the_others() {
+        let retry = RetryPolicy { retries: 1, backoff: Duration::from_millis(1) };
+        let (up, down) = (sink("up", false), sink("down", true));
+        let fan_out = FanOutSink::new(vec![
+            SinkMember::new(up.clone(), retry, None),
+            SinkMember::new(down.clone(), retry, None),
+        ]);
+        assert_eq!(fan_out.name(), "up+down");
+
+        let err = fan_out.save(&[], "binance").await.unwrap_err();
+        assert!(err.to_string().starts_with("1 of 2 sinks failed: down"));
+        assert_eq!(*up.written.lock().unwrap(), [0]);
+    }
+
+    #[tokio::test]
+    async fn spooled_member_accepts_and_keeps_the_batch_while_down() {
+        let dir = std::env::temp_dir().join(format!("sink-test-{}", std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        let spool = Spool::open(&dir, 1 << 20, 1 << 16, Duration::from_millis(1)).await.unwrap();
+        let retry = RetryPolicy { retries: 0, backoff: Duration::from_millis(1) };
+        let fan_out = FanOutSink::new(vec![SinkMember::new(sink("down", true), retry, Some(spool))]);
+
+        fan_out.save(&[], "binance").await.unwrap();
+        let stats = fan_out.spool_stats().await;
+        assert_eq!((stats[0].0, stats[0].1.pending_batches), ("down", 1));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+}
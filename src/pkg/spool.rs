@@ -0,0 +1,418 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use crate::pkg::dbcontext::entities::SymbolKlineData;
+use crate::pkg::sink::{KlineSink, UpdatePolicy};
+
+const CURSOR_FILE: &str = "cursor.json";
+const SEGMENT_EXT: &str = "log";
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Spool depth, logged while its sink is behind
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpoolStats {
+    pub pending_batches: usize,
+    pub pending_bytes: u64,
+    pub segments: usize,
+    pub evicted_batches: u64, // dropped by the size bound since startup
+}
+
+/// Disk-backed write-ahead log in front of one sink. Every candle batch is appended to the active
+/// segment and synced before the sink sees it; a cursor file marks the first batch the sink hasn't
+/// acknowledged. Batches replay strictly in order, so an amendment never lands before the row it
+/// amends. When the spool outgrows its size bound the oldest segments are evicted.
+pub struct Spool {
+    dir: PathBuf,
+    max_bytes: u64,
+    segment_bytes: u64,
+    retry: Duration,
+    state: Mutex<SpoolState>,
+    replaying: Mutex<()>, // held by the one replay running, without blocking appends
+    evicted: AtomicU64,
+}
+
+struct SpoolState {
+    segments: BTreeMap<u64, u64>, // segment number -> size in bytes
+    cursor: Cursor,
+    pending: usize,
+    backoff: Duration,
+    retry_at: Option<Instant>,
+}
+
+/// First batch the sink hasn't acknowledged
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct Cursor {
+    segment: u64,
+    offset: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SpoolRecord {
+    policy: UpdatePolicy,
+    instance: String,
+    klines: Vec<SymbolKlineData>,
+}
+
+impl Spool {
+    /// Open (or create) the spool in `dir`, picking up batches a previous run left behind
+    pub async fn open(dir: impl AsRef<Path>, max_bytes: u64, segment_bytes: u64, retry: Duration) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("creating spool directory {}", dir.display()))?;
+
+        let mut segments = BTreeMap::new();
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|e| e == SEGMENT_EXT)
+                && let Some(number) = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<u64>().ok())
+            {
+                segments.insert(number, entry.metadata().await?.len());
+            }
+        }
+
+        // A crash mid-append leaves half a line at the end of the newest segment
+        if let Some((&last, size)) = segments.iter_mut().next_back() {
+            let path = segment_path(&dir, last);
+            let data = tokio::fs::read(&path).await?;
+            let complete = data.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1) as u64;
+            if complete != *size {
+                warn!("⚠️ Dropping a partly written batch at the end of {}", path.display());
+                tokio::fs::OpenOptions::new().write(true).open(&path).await?.set_len(complete).await?;
+                *size = complete;
+            }
+        }
+
+        let mut cursor: Cursor = match tokio::fs::read(dir.join(CURSOR_FILE)).await {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_default(),
+            Err(_) => Cursor::default(),
+        };
+        match segments.range(cursor.segment..).next() {
+            Some((&number, _)) if number != cursor.segment => cursor = Cursor { segment: number, offset: 0 },
+            Some(_) => {}
+            None => cursor = Cursor { segment: segments.keys().next_back().map_or(0, |n| n + 1), offset: 0 },
+        }
+
+        let mut pending = 0;
+        for (&number, _) in segments.range(cursor.segment..) {
+            let data = tokio::fs::read(segment_path(&dir, number)).await?;
+            let from = if number == cursor.segment { cursor.offset as usize } else { 0 };
+            pending += data.get(from..).map_or(0, |rest| rest.iter().filter(|b| **b == b'\n').count());
+        }
+        if pending > 0 {
+            info!("📦 Spool {} holds {} batches from a previous run", dir.display(), pending);
+        }
+
+        Ok(Self {
+            dir,
+            max_bytes,
+            segment_bytes: segment_bytes.max(1),
+            retry,
+            state: Mutex::new(SpoolState {
+                segments,
+                cursor,
+                pending,
+                backoff: retry,
+                retry_at: None,
+            }),
+            replaying: Mutex::new(()),
+            evicted: AtomicU64::new(0),
+        })
+    }
+
+    /// Spool a batch, then try to hand everything pending to `sink`. Errors only when the
+    /// batch couldn't be spooled; sink failures leave it on disk for `replay`.
+    pub async fn write(&self, sink: &dyn KlineSink, policy: UpdatePolicy, klines: &[SymbolKlineData], instance: &str) -> Result<()> {
+        let record = SpoolRecord {
+            policy,
+            instance: instance.to_string(),
+            klines: klines.to_vec(),
+        };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+
+        {
+            let mut state = self.state.lock().await;
+            self.append(&mut state, &line).await?;
+            self.evict(&mut state).await?;
+        }
+
+        if let Err(e) = self.replay(sink).await {
+            warn!("⚠️ [{}] Write to {} failed, {} batches kept in the spool: {:?}", instance, sink.name(), self.stats().await.pending_batches, e);
+        }
+        Ok(())
+    }
+
+    /// Write pending batches to `sink` oldest first, until the spool is empty or a write
+    /// fails. Failures back off exponentially; calls before the next retry, or while another
+    /// replay runs, are no-ops. The spool state is only locked between sink writes, so appends
+    /// never wait on a slow sink. Returns how many batches the sink acknowledged.
+    pub async fn replay(&self, sink: &dyn KlineSink) -> Result<usize> {
+        let Ok(_replaying) = self.replaying.try_lock() else {
+            return Ok(0);
+        };
+        {
+            let state = self.state.lock().await;
+            if state.pending == 0 || state.retry_at.is_some_and(|at| Instant::now() < at) {
+                return Ok(0);
+            }
+        }
+
+        let mut acknowledged = 0;
+        loop {
+            let (number, from) = {
+                let mut state = self.state.lock().await;
+                if state.pending == 0 {
+                    break;
+                }
+                let Some((&number, _)) = state.segments.range(state.cursor.segment..).next() else {
+                    break;
+                };
+                if number != state.cursor.segment {
+                    state.cursor = Cursor { segment: number, offset: 0 };
+                }
+                (number, state.cursor.offset)
+            };
+
+            let data = match tokio::fs::read(segment_path(&self.dir, number)).await {
+                Ok(data) => data,
+                // Evicted since the snapshot, the cursor has moved past it
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            // Appends only ever add whole lines after `from`, a missing newline means not yet written
+            let mut offset = from as usize;
+            let mut moved = false;
+            while let Some(len) = data.get(offset..).and_then(|rest| rest.iter().position(|b| *b == b'\n')) {
+                let line = &data[offset..offset + len];
+                match serde_json::from_slice::<SpoolRecord>(line) {
+                    Ok(record) => {
+                        if let Err(e) = sink.write(record.policy, &record.klines, &record.instance).await {
+                            let mut state = self.state.lock().await;
+                            state.retry_at = Some(Instant::now() + state.backoff);
+                            state.backoff = (state.backoff * 2).min(MAX_BACKOFF);
+                            return Err(e);
+                        }
+                        debug!("Spooled batch of {} candles written to {}", record.klines.len(), sink.name());
+                    }
+                    Err(e) => error!("❌ Skipping unreadable spooled batch in segment {}: {:?}", number, e),
+                }
+
+                let mut state = self.state.lock().await;
+                if state.cursor.segment != number || state.cursor.offset != offset as u64 {
+                    // Eviction moved the cursor while the sink was writing
+                    moved = true;
+                    break;
+                }
+                offset += len + 1;
+                state.cursor.offset = offset as u64;
+                state.pending -= 1;
+                acknowledged += 1;
+                self.save_cursor(state.cursor).await?;
+            }
+            if moved {
+                continue;
+            }
+
+            // A fully written segment other than the active one is done with
+            let mut state = self.state.lock().await;
+            let active = state.segments.keys().next_back().copied();
+            if Some(number) == active {
+                if state.segments.get(&number).is_some_and(|size| *size > offset as u64) {
+                    // Batches were appended while this one was read
+                    continue;
+                }
+                break;
+            }
+            if state.cursor.segment == number {
+                tokio::fs::remove_file(segment_path(&self.dir, number)).await?;
+                state.segments.remove(&number);
+            }
+        }
+
+        let mut state = self.state.lock().await;
+        state.backoff = self.retry;
+        state.retry_at = None;
+        Ok(acknowledged)
+    }
+
+    pub async fn stats(&self) -> SpoolStats {
+        let state = self.state.lock().await;
+        let consumed = state.segments.get(&state.cursor.segment).map_or(0, |_| state.cursor.offset);
+        SpoolStats {
+            pending_batches: state.pending,
+            pending_bytes: state.segments.range(state.cursor.segment..).map(|(_, size)| size).sum::<u64>() - consumed,
+            segments: state.segments.len(),
+            evicted_batches: self.evicted.load(Ordering::Relaxed),
+        }
+    }
+
+    // Helper: append one record to the active segment, starting a new one when it's full
+    async fn append(&self, state: &mut SpoolState, line: &[u8]) -> Result<()> {
+        let number = match state.segments.iter().next_back() {
+            Some((&number, &size)) if size < self.segment_bytes => number,
+            Some((&number, _)) => number + 1,
+            None => state.cursor.segment,
+        };
+
+        let path = segment_path(&self.dir, number);
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("opening spool segment {}", path.display()))?;
+        file.write_all(line).await?;
+        file.sync_data().await?;
+
+        *state.segments.entry(number).or_default() += line.len() as u64;
+        state.pending += 1;
+        Ok(())
+    }
+
+    // Helper: drop the oldest segments while the spool is over its size bound; the active
+    // segment always stays
+    async fn evict(&self, state: &mut SpoolState) -> Result<()> {
+        while state.segments.len() > 1 && state.segments.values().sum::<u64>() > self.max_bytes {
+            let Some((number, _)) = state.segments.pop_first() else { break };
+            let path = segment_path(&self.dir, number);
+
+            if number >= state.cursor.segment {
+                let data = tokio::fs::read(&path).await?;
+                let from = if number == state.cursor.segment { state.cursor.offset as usize } else { 0 };
+                let lost = data.get(from..).map_or(0, |rest| rest.iter().filter(|b| **b == b'\n').count());
+                state.pending -= lost;
+                self.evicted.fetch_add(lost as u64, Ordering::Relaxed);
+                warn!("🗑️ Spool over {} bytes, evicted {} unwritten batches from {}", self.max_bytes, lost, path.display());
+
+                let next = state.segments.keys().next().copied().unwrap_or(number + 1);
+                state.cursor = Cursor { segment: next, offset: 0 };
+                self.save_cursor(state.cursor).await?;
+            }
+            tokio::fs::remove_file(&path).await?;
+        }
+        Ok(())
+    }
+
+    // Helper: persist the cursor, replaced atomically
+    async fn save_cursor(&self, cursor: Cursor) -> Result<()> {
+        let path = self.dir.join(CURSOR_FILE);
+        let tmp = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec(&cursor)?).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+}
+
+// Helper: segment file for a segment number, zero padded so names sort by age
+fn segment_path(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", number, SEGMENT_EXT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(n: usize) -> Vec<u8> {
+        let mut line = serde_json::to_vec(&SpoolRecord {
+            policy: UpdatePolicy::Ignore,
+            instance: format!("batch-{}", n),
+            klines: Vec::new(),
+        })
+        .unwrap();
+        line.push(b'\n');
+        line
+    }
+
+    #[tokio::test]
+    async fn segments_roll_evict_oldest_first_and_survive_a_reopen() {
+        let dir = std::env::temp_dir().join(format!("spool-test-{}", std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        let line_len = record(0).len() as u64;
+
+        // Two batches per segment, room for four
+        let spool = Spool::open(&dir, line_len * 4, line_len * 2, Duration::from_secs(1)).await.unwrap();
+        for n in 0..5 {
+            let mut state = spool.state.lock().await;
+            spool.append(&mut state, &record(n)).await.unwrap();
+            spool.evict(&mut state).await.unwrap();
+        }
+        let stats = spool.stats().await;
+        assert_eq!((stats.pending_batches, stats.segments, stats.evicted_batches), (3, 2, 2));
+
+        // Half a batch from a crash mid-append is dropped on reopen
+        let newest = *spool.state.lock().await.segments.keys().next_back().unwrap();
+        let mut file = tokio::fs::OpenOptions::new().append(true).open(segment_path(&dir, newest)).await.unwrap();
+        file.write_all(b"{\"policy\":").await.unwrap();
+        drop(file);
+
+        let reopened = Spool::open(&dir, line_len * 4, line_len * 2, Duration::from_secs(1)).await.unwrap();
+        let stats = reopened.stats().await;
+        assert_eq!((stats.pending_batches, stats.pending_bytes), (3, line_len * 3));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+
+    // Holds every write until released, counting the batches it got
+    struct StalledSink {
+        release: tokio::sync::Semaphore,
+        written: AtomicU64,
+    }
+
+    #[async_trait::async_trait]
+    impl KlineSink for StalledSink {
+        fn name(&self) -> &str {
+            "stalled"
+        }
+
+        async fn write(&self, _policy: UpdatePolicy, _klines: &[SymbolKlineData], _instance: &str) -> Result<()> {
+            self.release.acquire().await?.forget();
+            self.written.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn appends_do_not_wait_for_a_replay_stuck_in_the_sink() {
+        let dir = std::env::temp_dir().join(format!("spool-stall-test-{}", std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        let spool = std::sync::Arc::new(Spool::open(&dir, 1 << 20, 1 << 20, Duration::from_millis(10)).await.unwrap());
+        let sink = std::sync::Arc::new(StalledSink {
+            release: tokio::sync::Semaphore::new(0),
+            written: AtomicU64::new(0),
+        });
+
+        {
+            let mut state = spool.state.lock().await;
+            spool.append(&mut state, &record(0)).await.unwrap();
+        }
+        let replay = tokio::spawn({
+            let (spool, sink) = (spool.clone(), sink.clone());
+            async move { spool.replay(sink.as_ref()).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // The sink is stuck on the first batch; the next one still goes to disk right away
+        tokio::time::timeout(Duration::from_secs(1), spool.write(sink.as_ref(), UpdatePolicy::Ignore, &[], "batch-1"))
+            .await
+            .expect("append blocked behind the replay")
+            .unwrap();
+        assert_eq!(spool.stats().await.pending_batches, 2);
+
+        // Once the sink recovers the running replay picks up the batch appended meanwhile
+        sink.release.add_permits(2);
+        assert_eq!(replay.await.unwrap().unwrap(), 2);
+        assert_eq!((spool.stats().await.pending_batches, sink.written.load(Ordering::Relaxed)), (0, 2));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+}
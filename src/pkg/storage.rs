@@ -0,0 +1,283 @@
+use crate::pkg::clickhouse_client::ClickHouseClient;
+use crate::pkg::aggregator::ticker_aggregator::BASE_INTERVAL;
+use crate::pkg::config::{AppSettings, ClickHouseConfig, DatabaseConfig, ExchangeSettings, SinkKind, TimescaleSettings};
+use crate::pkg::dbcontext::entities::SymbolKlineData;
+use crate::pkg::dbcontext::migration::{MigrationState, migration_status, run_migrations};
+use crate::pkg::dbcontext::timescale::{apply_timescale_settings, refresh_aggregates};
+use crate::pkg::postgre_db::DB;
+use crate::pkg::sink::{FanOutSink, KlineSink, RetryPolicy, SinkMember, UpdatePolicy};
+use crate::pkg::spool::Spool;
+use crate::pkg::store::KlineStore;
+use anyhow::{Result, bail};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+/// Where finished candles go: every configured sink, concurrently. Reads go to the first configured
+/// sink that is connected. A sink that can't be reached at startup doesn't stop the others, it
+/// reconnects in the background and takes over reads and aggregate refreshes once it is up;
+/// startup only fails when none came up.
+pub struct StorageBackend {
+    sink: FanOutSink,
+    backends: Vec<Arc<OnceLock<Connected>>>, // per sink in configured order, set once connected
+    aggregates: Option<TimescaleSettings>,    // continuous aggregates to refresh once Postgres is up
+}
+
+impl StorageBackend {
+    /// Connect to every configured sink and make sure its schema is in place
+    pub async fn init(settings: &AppSettings) -> Result<Self> {
+        let mut members = Vec::new();
+        let mut backends = Vec::new();
+        let mut seen = Vec::new();
+        // Candles of a ClickHouse table from before exchanges were stored came from the first section
+        let legacy = settings.exchange_section(None);
+
+        for sink_settings in settings.sink_settings() {
+            if seen.contains(&sink_settings.kind) {
+                warn!("⚠️ {:?} is listed in Sinks more than once, using the first entry", sink_settings.kind);
+                continue;
+            }
+            seen.push(sink_settings.kind);
+
+            let kind = sink_settings.kind;
+            let sink: Arc<dyn KlineSink> = match connect_sink(kind, &settings.clickhouse, &settings.database, legacy.as_ref()).await {
+                Ok(connected) => {
+                    let sink = Arc::clone(&connected.sink);
+                    backends.push(Arc::new(OnceLock::from(connected)));
+                    sink
+                }
+                Err(e) => {
+                    error!("❌ Failed to initialize {:?}, retrying in the background: {:?}", kind, e);
+                    let reconnecting = ReconnectingSink::start(
+                        kind,
+                        settings.clickhouse.clone(),
+                        settings.database.clone(),
+                        legacy.clone(),
+                    );
+                    backends.push(Arc::clone(&reconnecting.connected));
+                    Arc::new(reconnecting)
+                }
+            };
+
+            let spool = if settings.spool.enabled {
+                let dir = Path::new(&settings.spool.directory).join(sink.name().to_lowercase());
+                let retry = Duration::from_secs(settings.spool.retry_seconds.max(1));
+                let max_bytes = settings.spool.max_megabytes * 1024 * 1024;
+                let segment_bytes = settings.spool.segment_megabytes * 1024 * 1024;
+                match Spool::open(&dir, max_bytes, segment_bytes, retry).await {
+                    Ok(spool) => Some(spool),
+                    Err(e) => {
+                        error!("❌ Failed to open the spool for {}, writing to it directly: {:?}", sink.name(), e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            let retry = RetryPolicy {
+                retries: sink_settings.retries,
+                backoff: Duration::from_millis(sink_settings.retry_backoff_millis),
+            };
+            members.push(SinkMember::new(sink, retry, spool));
+        }
+
+        if members.is_empty() {
+            bail!("no storage sink enabled, check Sinks in appsettings.yaml");
+        }
+        if !backends.iter().any(|b| b.get().is_some()) {
+            bail!("no storage sink could be initialized");
+        }
+        let aggregates = (!settings.database.timescale.continuous_aggregates.is_empty())
+            .then(|| settings.database.timescale.clone());
+        let sink = FanOutSink::new(members);
+        info!("✅ Writing candles to {}", sink.name());
+        Ok(Self { sink, backends, aggregates })
+    }
+
+    pub fn name(&self) -> &str {
+        self.sink.name()
+    }
+
+    /// Every sink behind one write interface
+    pub fn sink(&self) -> &FanOutSink {
+        &self.sink
+    }
+
+    /// Close connections once nothing writes anymore
+    pub async fn close(&self) {
+        self.sink.close().await;
+    }
+
+    /// Save candles to every sink, rows that already exist are left untouched
+    pub async fn save(&self, kline_data: &[SymbolKlineData], instance: &str) -> Result<()> {
+        self.sink.save(kline_data, instance).await
+    }
+
+    /// Write candles to every sink, handling stored ones as `policy` says
+    pub async fn write(&self, policy: UpdatePolicy, kline_data: &[SymbolKlineData], instance: &str) -> Result<()> {
+        self.sink.write(policy, kline_data, instance).await
+    }
+
+    /// Read side of the first configured sink that is connected
+    pub fn store(&self) -> &dyn KlineStore {
+        // One came up at startup and a connected sink stays connected
+        self.backends
+            .iter()
+            .find_map(|b| b.get())
+            .map(|c| c.store.as_ref())
+            .expect("a storage sink connected at startup")
+    }
+
+    /// Bring the Postgres continuous aggregates up to date with `interval` candles written for
+    /// open times in [start, end]. Only 1m writes feed them; a no-op without aggregates, and while
+    /// Postgres isn't connected, with a warning.
+    pub async fn refresh_aggregates(&self, interval: &str, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<()> {
+        let Some(settings) = &self.aggregates else {
+            return Ok(());
+        };
+        if interval != BASE_INTERVAL {
+            return Ok(());
+        }
+        let Some(db) = self.backends.iter().find_map(|b| b.get().and_then(|c| c.db.as_ref())) else {
+            warn!("⚠️ Postgres isn't connected, skipping the continuous aggregate refresh of {} to {}", start, end);
+            return Ok(());
+        };
+        refresh_aggregates(&db.pool, &db.table, settings, &settings.continuous_aggregates, start, end).await
+    }
+}
+
+// One connected backend, as a sink and as a store
+struct Connected {
+    sink: Arc<dyn KlineSink>,
+    store: Arc<dyn KlineStore>,
+    db: Option<Arc<DB>>, // set for Postgres
+}
+
+// Helper: connect one backend
+async fn connect_sink(
+    kind: SinkKind,
+    clickhouse: &ClickHouseConfig,
+    database: &DatabaseConfig,
+    legacy: Option<&ExchangeSettings>,
+) -> Result<Connected> {
+    Ok(match kind {
+        SinkKind::Clickhouse => {
+            let client = Arc::new(init_clickhouse(clickhouse, legacy).await?);
+            Connected {
+                sink: Arc::clone(&client) as Arc<dyn KlineSink>,
+                store: client,
+                db: None,
+            }
+        }
+        SinkKind::Postgres => {
+            let db = Arc::new(init_postgres(database).await?);
+            Connected {
+                sink: Arc::clone(&db) as Arc<dyn KlineSink>,
+                store: Arc::clone(&db) as Arc<dyn KlineStore>,
+                db: Some(db),
+            }
+        }
+    })
+}
+
+/// A sink whose backend was down at startup. A background task keeps connecting, doubling its wait
+/// up to `RECONNECT_MAX_WAIT`; until it gets through every write fails, so with a spool batches
+/// wait on disk and are replayed once it is up. `StorageBackend` reads through the same cell.
+struct ReconnectingSink {
+    name: &'static str,
+    connected: Arc<OnceLock<Connected>>,
+}
+
+const RECONNECT_FIRST_WAIT: Duration = Duration::from_secs(5);
+const RECONNECT_MAX_WAIT: Duration = Duration::from_secs(300);
+
+impl ReconnectingSink {
+    fn start(kind: SinkKind, clickhouse: ClickHouseConfig, database: DatabaseConfig, legacy: Option<ExchangeSettings>) -> Self {
+        let connected = Arc::new(OnceLock::new());
+        let cell = Arc::clone(&connected);
+        tokio::spawn(async move {
+            let mut wait = RECONNECT_FIRST_WAIT;
+            loop {
+                tokio::time::sleep(wait).await;
+                match connect_sink(kind, &clickhouse, &database, legacy.as_ref()).await {
+                    Ok(connected) => {
+                        info!("✅ {} is up, writing to and reading from it again", connected.sink.name());
+                        let _ = cell.set(connected);
+                        return;
+                    }
+                    Err(e) => {
+                        wait = (wait * 2).min(RECONNECT_MAX_WAIT);
+                        warn!("⚠️ {:?} still unavailable, retrying in {:?}: {:?}", kind, wait, e);
+                    }
+                }
+            }
+        });
+
+        let name = match kind {
+            SinkKind::Clickhouse => "ClickHouse",
+            SinkKind::Postgres => "Postgres",
+        };
+        Self { name, connected }
+    }
+}
+
+#[async_trait]
+impl KlineSink for ReconnectingSink {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    async fn write(&self, policy: UpdatePolicy, kline_data: &[SymbolKlineData], instance: &str) -> Result<()> {
+        match self.connected.get() {
+            Some(c) => c.sink.write(policy, kline_data, instance).await,
+            None => bail!("{} is not connected yet", self.name),
+        }
+    }
+
+    async fn close(&self) {
+        if let Some(c) = self.connected.get() {
+            c.sink.close().await;
+        }
+    }
+}
+
+// Helper: connect to ClickHouse and create the table
+async fn init_clickhouse(config: &ClickHouseConfig, legacy: Option<&ExchangeSettings>) -> Result<ClickHouseClient> {
+    info!("⚡ Initializing ClickHouse...");
+    let ch_client = ClickHouseClient::init(config).await?;
+
+    // Optionally create table
+    ch_client.create_kline_table(legacy).await?;
+    info!("✅ ClickHouse ready");
+    Ok(ch_client)
+}
+
+// Helper: connect to Postgres and migrate the schema
+async fn init_postgres(config: &DatabaseConfig) -> Result<DB> {
+    info!("⚡ Initializing Postgres...");
+    let db = DB::init(config).await?;
+
+    if config.auto_migrate {
+        let applied = run_migrations(&db.pool, &db.table).await?;
+        apply_timescale_settings(&db.pool, &db.table, &config.timescale).await?;
+        info!("✅ Postgres schema of {} up to date ({} migrations applied)", db.table.qualified(), applied);
+    } else {
+        let pending = migration_status(&db.pool, &db.table)
+            .await?
+            .iter()
+            .filter(|m| matches!(m.state, MigrationState::Pending | MigrationState::Modified(_)))
+            .count();
+        if pending > 0 {
+            bail!(
+                "{} migrations of {} are pending or modified, run `TickAggregator migrate`",
+                pending,
+                db.table.qualified()
+            );
+        }
+    }
+    Ok(db)
+}
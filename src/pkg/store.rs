@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::pkg::dbcontext::entities::SymbolKlineData;
+
+/// Candles per page when a caller pages through a whole range
+pub const DEFAULT_PAGE_SIZE: usize = 10_000;
+
+/// Candles of one native symbol and interval of an exchange, open times in [start, end]
+#[derive(Debug, Clone)]
+pub struct KlineRange {
+    pub exchange: String,
+    pub symbol: String,
+    pub interval: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// One page of a range, ascending. `next` is the open time to continue after, `None` on the last page.
+#[derive(Debug, Default)]
+pub struct KlinePage {
+    pub klines: Vec<SymbolKlineData>,
+    pub next: Option<DateTime<Utc>>,
+}
+
+impl KlinePage {
+    /// Page of candles read with `limit`; a full page may have more after it
+    pub fn new(klines: Vec<SymbolKlineData>, limit: usize) -> Self {
+        let next = if klines.len() >= limit { klines.last().map(|k| k.open_time) } else { None };
+        Self { klines, next }
+    }
+}
+
+/// Candles of several symbols sharing an open time, in the order the symbols were asked for.
+/// `None` where a symbol has no candle at that time.
+#[derive(Debug)]
+pub struct AlignedKlines {
+    pub open_time: DateTime<Utc>,
+    pub klines: Vec<Option<SymbolKlineData>>,
+}
+
+/// One page of aligned rows, ascending; `next` continues it like `KlinePage::next`
+#[derive(Debug, Default)]
+pub struct AlignedPage {
+    pub rows: Vec<AlignedKlines>,
+    pub next: Option<DateTime<Utc>>,
+}
+
+/// Read side of a backend candles are stored in. Symbols are the native ones, as written, and
+/// candles come back with their native symbol.
+#[async_trait]
+pub trait KlineStore: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Up to `limit` candles of `range` opened after `after` (from `range.start` when `None`), ascending
+    async fn klines(&self, range: &KlineRange, after: Option<DateTime<Utc>>, limit: usize) -> Result<KlinePage>;
+
+    /// The `count` most recent candles of a symbol, ascending
+    async fn latest_klines(&self, exchange: &str, symbol: &str, interval: &str, count: usize) -> Result<Vec<SymbolKlineData>>;
+
+    /// Open time of the newest candle per symbol; symbols without candles are left out
+    async fn last_open_times(
+        &self,
+        exchange: &str,
+        symbols: &[String],
+        interval: &str,
+    ) -> Result<HashMap<String, DateTime<Utc>>>;
+
+    /// Open times stored within `range`, ascending. Cheaper than `klines` for presence checks.
+    async fn open_times(&self, range: &KlineRange) -> Result<Vec<DateTime<Utc>>>;
+
+    /// Every candle of `range`, read `page_size` at a time
+    async fn all_klines(&self, range: &KlineRange, page_size: usize) -> Result<Vec<SymbolKlineData>> {
+        let mut klines = Vec::new();
+        let mut after = None;
+        loop {
+            let page = self.klines(range, after, page_size).await?;
+            klines.extend(page.klines);
+            match page.next {
+                Some(next) => after = Some(next),
+                None => return Ok(klines),
+            }
+        }
+    }
+
+    /// Candles of `symbols` over [start, end] joined on open time, up to `limit` per symbol and page.
+    /// A row is only returned once every symbol's candles up to its open time have been read.
+    #[allow(clippy::too_many_arguments)]
+    async fn aligned_klines(
+        &self,
+        exchange: &str,
+        symbols: &[String],
+        interval: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        after: Option<DateTime<Utc>>,
+        limit: usize,
+    ) -> Result<AlignedPage> {
+        let mut pages = Vec::with_capacity(symbols.len());
+        for symbol in symbols {
+            let range = KlineRange {
+                exchange: exchange.to_string(),
+                symbol: symbol.clone(),
+                interval: interval.to_string(),
+                start,
+                end,
+            };
+            pages.push(self.klines(&range, after, limit).await?);
+        }
+        Ok(align_pages(pages))
+    }
+}
+
+// Helper: join per-symbol pages on open time, up to the earliest point one of them stops short of
+fn align_pages(pages: Vec<KlinePage>) -> AlignedPage {
+    let next = pages.iter().filter_map(|p| p.next).min();
+    let width = pages.len();
+
+    let mut rows: Vec<AlignedKlines> = Vec::new();
+    let mut index: HashMap<DateTime<Utc>, usize> = HashMap::new();
+    for (column, page) in pages.into_iter().enumerate() {
+        for kline in page.klines {
+            if next.is_some_and(|cutoff| kline.open_time > cutoff) {
+                continue;
+            }
+            let row = *index.entry(kline.open_time).or_insert_with(|| {
+                rows.push(AlignedKlines {
+                    open_time: kline.open_time,
+                    klines: vec![None; width],
+                });
+                rows.len() - 1
+            });
+            rows[row].klines[column] = Some(kline);
+        }
+    }
+    rows.sort_by_key(|r| r.open_time);
+
+    AlignedPage { rows, next }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candle(symbol: &str, minute: i64) -> SymbolKlineData {
+        SymbolKlineData {
+            exchange: "binance".to_string(),
+            symbol: symbol.to_string(),
+            interval: "1m".to_string(),
+            open: 1.0,
+            high: 1.0,
+            low: 1.0,
+            close: 1.0,
+            open_time: DateTime::<Utc>::from_timestamp(minute * 60, 0).unwrap(),
+            volume: 0.0,
+            volume_24h: None,
+            trade_count: 0,
+            buy_volume: 0.0,
+            sell_volume: 0.0,
+            is_synthetic: false,
+        }
+    }
+
+    #[test]
+    fn aligned_rows_stop_where_a_full_page_ends() {
+        let minute = |m: i64| DateTime::<Utc>::from_timestamp(m * 60, 0).unwrap();
+        // BTC's page is full at minute 2, ETH's is complete but misses minute 1
+        let btc = KlinePage {
+            klines: vec![candle("BTCUSDT", 0), candle("BTCUSDT", 1), candle("BTCUSDT", 2)],
+            next: Some(minute(2)),
+        };
+        let eth = KlinePage {
+            klines: vec![candle("ETHUSDT", 0), candle("ETHUSDT", 2), candle("ETHUSDT", 3)],
+            next: None,
+        };
+
+        let page = align_pages(vec![btc, eth]);
+
+        assert_eq!(page.next, Some(minute(2)));
+        let times: Vec<_> = page.rows.iter().map(|r| r.open_time).collect();
+        assert_eq!(times, vec![minute(0), minute(1), minute(2)]);
+        assert!(page.rows[1].klines[0].is_some() && page.rows[1].klines[1].is_none());
+        assert_eq!(page.rows[2].klines[1].as_ref().unwrap().symbol, "ETHUSDT");
+    }
+}
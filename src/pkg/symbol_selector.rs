@@ -0,0 +1,62 @@
+use crate::pkg::config::SymbolRules;
+use crate::pkg::exchanges::exchange::ExchangeApi;
+use crate::pkg::exchanges::exchange_entities::{InstrumentInfo, InstrumentStatus, TickerInfo};
+use crate::pkg::exchanges::instrument::InstrumentKind;
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+
+/// Symbols matching `rules` right now, most traded first.
+/// Only instruments currently trading are kept, so delisted contracts drop out on refresh.
+pub async fn select_symbols(api: &dyn ExchangeApi, rules: &SymbolRules, blacklist: &HashSet<String>) -> Result<Vec<String>> {
+    let instruments = api.get_instruments().await?;
+    let tickers = api.get_all_tickers().await?;
+    Ok(apply_rules(&instruments, &tickers, rules, blacklist))
+}
+
+fn apply_rules(
+    instruments: &[InstrumentInfo],
+    tickers: &[TickerInfo],
+    rules: &SymbolRules,
+    blacklist: &HashSet<String>,
+) -> Vec<String> {
+    let tickers: HashMap<&str, &TickerInfo> = tickers.iter().map(|t| (t.symbol.as_str(), t)).collect();
+
+    let mut selected: Vec<(String, f64)> = instruments
+        .iter()
+        .filter(|i| i.status == InstrumentStatus::Trading)
+        .filter(|i| !rules.perpetual_only || i.instrument.kind == InstrumentKind::Perpetual)
+        .filter(|i| rules.quote_asset.is_empty() || i.instrument.quote.eq_ignore_ascii_case(&rules.quote_asset))
+        .filter(|i| !blacklist.contains(&i.instrument.native_symbol.to_uppercase()))
+        .map(|i| {
+            let symbol = &i.instrument.native_symbol;
+            (symbol.clone(), quote_volume_24h(i, tickers.get(symbol.as_str()).copied()))
+        })
+        .filter(|(_, volume)| *volume >= rules.min_quote_volume_24h)
+        .collect();
+
+    selected.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    if rules.max_symbols > 0 {
+        selected.truncate(rules.max_symbols);
+    }
+
+    selected.into_iter().map(|(symbol, _)| symbol).collect()
+}
+
+// Helper: 24h turnover in the quote asset; ticker volume is in contracts, each worth `contract_value`
+// base on linear contracts and `contract_value` quote on inverse ones
+fn quote_volume_24h(instrument: &InstrumentInfo, ticker: Option<&TickerInfo>) -> f64 {
+    let Some(ticker) = ticker else {
+        return 0.0;
+    };
+    let price = ticker.last_price.parse::<f64>().unwrap_or(0.0);
+    let volume = ticker
+        .vol_24h
+        .as_deref()
+        .and_then(|v| v.parse::<f64>().ok())
+        .unwrap_or(0.0);
+    if instrument.instrument.is_inverse() {
+        volume * instrument.contract_value
+    } else {
+        price * volume * instrument.contract_value
+    }
+}